@@ -0,0 +1,748 @@
+// src/api.rs
+use chrono::{DateTime, Local, NaiveDate};
+use log::error;
+use rocket::http::Status;
+use rocket::request::Request;
+use rocket::response::{self, Responder};
+use rocket::serde::json::{self, Json};
+use rocket::{catch, catchers, delete, get, post, put, routes, Catcher, FromForm, Route, State};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, SecurityScheme};
+use utoipa::{IntoParams, Modify, OpenApi, ToSchema};
+use uuid::Uuid;
+use crate::email::{EmailConfig, EmailService};
+use crate::models::{
+    ChangeFilter, CheckRecord, Device, DeviceState, DeviceStatusReport, LogFilter, Paging, Rollup,
+    SensorStatusReport, SensorType, StateChange, User,
+};
+use crate::storage::Storage;
+use crate::{
+    create_device, read_check_records_page, read_state_changes_page, remove_device, replace_device, replace_users,
+    Auth, DeviceError, SharedDevices, WebDevice,
+};
+
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 1000;
+
+/// The routes of version 1 of the API, to be mounted at `/api/v1`.
+pub fn routes() -> Vec<Route> {
+    routes![
+        openapi,
+        list_devices,
+        add_device,
+        get_device,
+        update_device,
+        delete_device,
+        device_sensors,
+        device_status,
+        list_statuses,
+        list_events,
+        list_checks,
+        list_rollups,
+        list_users,
+        add_user,
+        update_user,
+        delete_user,
+        get_email_settings,
+        update_email_settings,
+        send_test_email,
+    ]
+}
+
+/// JSON errors for everything under `/api/v1`, including requests no route took.
+pub fn catchers() -> Vec<Catcher> {
+    catchers![api_catcher]
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "RustPing API", version = "1"),
+    servers((url = "/api/v1")),
+    paths(
+        list_devices, add_device, get_device, update_device, delete_device, device_sensors, device_status,
+        list_statuses, list_events, list_checks, list_rollups,
+        list_users, add_user, update_user, delete_user,
+        get_email_settings, update_email_settings, send_test_email,
+    ),
+    components(schemas(ErrorBody)),
+    modifiers(&CookieAuth),
+    security(("cookie" = [])),
+    tags(
+        (name = "devices", description = "Monitored devices, as kept in devices.json"),
+        (name = "status", description = "The latest results of devices and their sensors"),
+        (name = "history", description = "State changes, check results and rollups"),
+        (name = "users", description = "Dashboard logins"),
+        (name = "notifications", description = "Alert email settings"),
+    ),
+)]
+struct ApiDoc;
+
+// The dashboard's login cookie
+struct CookieAuth;
+
+impl Modify for CookieAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme("cookie", SecurityScheme::ApiKey(ApiKey::Cookie(ApiKeyValue::new("auth"))));
+    }
+}
+
+/// An error as every `/api/v1` route reports it.
+#[derive(Debug, Serialize, ToSchema)]
+struct ErrorBody {
+    // Always "error"
+    status: &'static str,
+    message: String,
+}
+
+#[derive(Debug)]
+struct ApiError {
+    status: Status,
+    message: String,
+}
+
+impl ApiError {
+    fn new(status: Status, message: impl Into<String>) -> Self {
+        Self { status, message: message.into() }
+    }
+
+    fn bad_request(message: impl Into<String>) -> Self {
+        Self::new(Status::BadRequest, message)
+    }
+
+    fn not_found(message: impl Into<String>) -> Self {
+        Self::new(Status::NotFound, message)
+    }
+
+    fn internal(message: impl Into<String>) -> Self {
+        Self::new(Status::InternalServerError, message)
+    }
+
+    // A request body that isn't the JSON expected
+    fn invalid_body(e: json::Error<'_>) -> Self {
+        Self::bad_request(match e {
+            json::Error::Parse(_, e) => format!("Invalid request body: {}", e),
+            json::Error::Io(e) => format!("Failed to read request body: {}", e),
+        })
+    }
+}
+
+impl From<DeviceError> for ApiError {
+    fn from(e: DeviceError) -> Self {
+        if let DeviceError::File(ref message) = e {
+            error!("{}", message);
+        }
+        Self::new(e.status(), e.to_string())
+    }
+}
+
+impl<'r> Responder<'r, 'static> for ApiError {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        (self.status, Json(ErrorBody { status: "error", message: self.message })).respond_to(request)
+    }
+}
+
+type ApiResult<T> = Result<T, ApiError>;
+
+#[catch(default)]
+fn api_catcher(status: Status, _request: &Request) -> ApiError {
+    let message = match status.code {
+        401 => "Not logged in",
+        404 => "No such resource",
+        _ => status.reason().unwrap_or("Request failed"),
+    };
+    ApiError::new(status, message)
+}
+
+/// One page of a longer list.
+#[derive(Debug, Serialize, ToSchema)]
+struct Page<T> {
+    items: Vec<T>,
+    // Items in the whole list
+    total: usize,
+    offset: usize,
+    limit: usize,
+}
+
+impl<T> Page<T> {
+    // The page of `items` asked for
+    fn of(items: Vec<T>, offset: Option<usize>, limit: Option<usize>) -> Self {
+        let paging = paging(offset, limit);
+        let total = items.len();
+        Self::new(paging.apply(items), total, paging)
+    }
+
+    // A page already cut from a list of `total` items
+    fn new(items: Vec<T>, total: usize, paging: Paging) -> Self {
+        Self { items, total, offset: paging.offset, limit: paging.limit }
+    }
+}
+
+// `limit` items from `offset` on; 100 unless set, and at most 1000
+fn paging(offset: Option<usize>, limit: Option<usize>) -> Paging {
+    Paging { offset: offset.unwrap_or(0), limit: limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) }
+}
+
+#[get("/openapi.json")]
+fn openapi() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+// Devices
+
+/// Filters for devices and their status, all optional.
+#[derive(Debug, FromForm, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct DeviceQuery {
+    /// Only devices in this category, ignoring case
+    category: Option<String>,
+    /// Only devices in this state, e.g. DOWN
+    state: Option<String>,
+    /// Part of the device's name, IP address or id
+    search: Option<String>,
+    /// Items to skip
+    offset: Option<usize>,
+    /// Items to return, 100 unless set and at most 1000
+    limit: Option<usize>,
+}
+
+impl DeviceQuery {
+    fn filter(&self, devices: &[Device]) -> ApiResult<Vec<Device>> {
+        let state: Option<DeviceState> = self.state.as_deref()
+            .map(|state| state.to_uppercase().parse().map_err(ApiError::bad_request))
+            .transpose()?;
+        let search = self.search.as_ref().map(|search| search.to_lowercase());
+        Ok(devices.iter()
+            .filter(|d| self.category.as_ref().is_none_or(|category| d.category.eq_ignore_ascii_case(category)))
+            .filter(|d| state.is_none_or(|state| d.state == Some(state)))
+            .filter(|d| search.as_ref().is_none_or(|search| {
+                d.name.to_lowercase().contains(search.as_str())
+                    || d.ip.to_lowercase().contains(search.as_str())
+                    || d.id.to_string().contains(search.as_str())
+            }))
+            .cloned()
+            .collect())
+    }
+}
+
+// The id in a path, which can't name a device unless it's a UUID
+fn parse_id(id: &str) -> ApiResult<Uuid> {
+    id.parse().map_err(|_| ApiError::not_found(format!("No device with id {}", id)))
+}
+
+async fn find_device(devices: &SharedDevices, id: &str) -> ApiResult<Device> {
+    let id = parse_id(id)?;
+    devices.lock().await.iter()
+        .find(|d| d.id == id)
+        .cloned()
+        .ok_or_else(|| ApiError::not_found(format!("No device with id {}", id)))
+}
+
+/// List devices with their latest results.
+#[utoipa::path(
+    get, path = "/devices", tag = "devices",
+    params(DeviceQuery),
+    responses(
+        (status = 200, body = Page<Device>),
+        (status = 400, description = "Unknown state", body = ErrorBody),
+        (status = 401, description = "Not logged in", body = ErrorBody),
+    ),
+)]
+#[get("/devices?<query..>")]
+async fn list_devices(_auth: Auth, query: DeviceQuery, devices: &State<SharedDevices>) -> ApiResult<Json<Page<Device>>> {
+    let devices = query.filter(&devices.lock().await)?;
+    Ok(Json(Page::of(devices, query.offset, query.limit)))
+}
+
+/// Add a device to devices.json and start monitoring it. Any `id` sent is replaced by a new one.
+#[utoipa::path(
+    post, path = "/devices", tag = "devices",
+    request_body = WebDevice,
+    responses(
+        (status = 201, body = Device),
+        (status = 400, description = "Invalid device", body = ErrorBody),
+        (status = 409, description = "Name already taken", body = ErrorBody),
+    ),
+)]
+#[post("/devices", data = "<device>")]
+async fn add_device(
+    _auth: Auth,
+    device: Result<Json<WebDevice>, json::Error<'_>>,
+    devices: &State<SharedDevices>,
+) -> ApiResult<(Status, Json<Device>)> {
+    let device = device.map_err(ApiError::invalid_body)?.into_inner();
+    let device = create_device(device, devices).await?;
+    Ok((Status::Created, Json(Device::from(device))))
+}
+
+/// A device with its latest results.
+#[utoipa::path(
+    get, path = "/devices/{id}", tag = "devices",
+    params(("id" = Uuid, Path, description = "Device id")),
+    responses((status = 200, body = Device), (status = 404, body = ErrorBody)),
+)]
+#[get("/devices/<id>")]
+async fn get_device(_auth: Auth, id: &str, devices: &State<SharedDevices>) -> ApiResult<Json<Device>> {
+    find_device(devices, id).await.map(Json)
+}
+
+/// Replace a device's settings.
+#[utoipa::path(
+    put, path = "/devices/{id}", tag = "devices",
+    params(("id" = Uuid, Path, description = "Device id")),
+    request_body = WebDevice,
+    responses(
+        (status = 200, body = Device),
+        (status = 400, description = "Invalid device", body = ErrorBody),
+        (status = 404, body = ErrorBody),
+        (status = 409, description = "Name already taken", body = ErrorBody),
+    ),
+)]
+#[put("/devices/<id>", data = "<device>")]
+async fn update_device(
+    _auth: Auth,
+    id: &str,
+    device: Result<Json<WebDevice>, json::Error<'_>>,
+    devices: &State<SharedDevices>,
+) -> ApiResult<Json<Device>> {
+    let id = parse_id(id)?;
+    let device = device.map_err(ApiError::invalid_body)?.into_inner();
+    replace_device(id, device, devices).await?;
+    find_device(devices, &id.to_string()).await.map(Json)
+}
+
+/// Remove a device from devices.json. Its history stays in the database.
+#[utoipa::path(
+    delete, path = "/devices/{id}", tag = "devices",
+    params(("id" = Uuid, Path, description = "Device id")),
+    responses(
+        (status = 204, description = "Removed"),
+        (status = 404, body = ErrorBody),
+        (status = 409, description = "Other devices depend on it", body = ErrorBody),
+    ),
+)]
+#[delete("/devices/<id>")]
+async fn delete_device(_auth: Auth, id: &str, devices: &State<SharedDevices>) -> ApiResult<Status> {
+    remove_device(parse_id(id)?, devices).await?;
+    Ok(Status::NoContent)
+}
+
+/// A sensor's settings and latest result.
+#[derive(Debug, Serialize, ToSchema)]
+struct SensorReport {
+    // As in the log and the status, e.g. "TCP 22"
+    label: String,
+    sensor: SensorType,
+    #[serde(flatten)]
+    status: SensorStatusReport,
+}
+
+/// The sensors of a device, with their latest results.
+#[utoipa::path(
+    get, path = "/devices/{id}/sensors", tag = "status",
+    params(("id" = Uuid, Path, description = "Device id")),
+    responses((status = 200, body = Vec<SensorReport>), (status = 404, body = ErrorBody)),
+)]
+#[get("/devices/<id>/sensors")]
+async fn device_sensors(_auth: Auth, id: &str, devices: &State<SharedDevices>) -> ApiResult<Json<Vec<SensorReport>>> {
+    let device = find_device(devices, id).await?;
+    let mut report = device.status_report(Local::now());
+    // An HTTPS sensor comes with its certificate check
+    let certificates = device.sensors.iter()
+        .filter(|sensor| **sensor == SensorType::Https)
+        .map(|sensor| (sensor.check_label(), sensor));
+    Ok(Json(device.sensors.iter()
+        .map(|sensor| (sensor.to_string(), sensor))
+        .chain(certificates)
+        .filter_map(|(label, sensor)| {
+            let status = report.sensors.remove(&label)?;
+            Some(SensorReport { label, sensor: sensor.clone(), status })
+        })
+        .collect()))
+}
+
+/// A device's state, how long it has been in it and the state of each sensor.
+#[utoipa::path(
+    get, path = "/devices/{id}/status", tag = "status",
+    params(("id" = Uuid, Path, description = "Device id")),
+    responses((status = 200, body = DeviceStatusReport), (status = 404, body = ErrorBody)),
+)]
+#[get("/devices/<id>/status")]
+async fn device_status(_auth: Auth, id: &str, devices: &State<SharedDevices>) -> ApiResult<Json<DeviceStatusReport>> {
+    Ok(Json(find_device(devices, id).await?.status_report(Local::now())))
+}
+
+/// The state of every device, as for a single one.
+#[utoipa::path(
+    get, path = "/status", tag = "status",
+    params(DeviceQuery),
+    responses((status = 200, body = Page<DeviceStatusReport>), (status = 400, body = ErrorBody)),
+)]
+#[get("/status?<query..>")]
+async fn list_statuses(
+    _auth: Auth,
+    query: DeviceQuery,
+    devices: &State<SharedDevices>,
+) -> ApiResult<Json<Page<DeviceStatusReport>>> {
+    let now = Local::now();
+    let reports = query.filter(&devices.lock().await)?.iter().map(|d| d.status_report(now)).collect();
+    Ok(Json(Page::of(reports, query.offset, query.limit)))
+}
+
+// History
+
+/// Filters for the history, all optional. Results are oldest first.
+#[derive(Debug, FromForm, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct HistoryQuery {
+    /// Comma separated parts of device names, IP addresses or ids, any of which may match
+    devices: Option<String>,
+    /// First day, YYYY-MM-DD
+    start_date: Option<String>,
+    /// Last day, YYYY-MM-DD, included
+    end_date: Option<String>,
+    /// Items to skip
+    offset: Option<usize>,
+    /// Items to return, 100 unless set and at most 1000
+    limit: Option<usize>,
+}
+
+impl HistoryQuery {
+    fn filter(&self) -> ApiResult<LogFilter> {
+        for (name, date) in [("start_date", &self.start_date), ("end_date", &self.end_date)] {
+            if let Some(date) = date {
+                NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                    .map_err(|_| ApiError::bad_request(format!("Invalid {} {:?}, expected YYYY-MM-DD", name, date)))?;
+            }
+        }
+        Ok(LogFilter::from_query(self.devices.as_deref(), self.start_date.as_deref(), self.end_date.as_deref()))
+    }
+}
+
+/// State changes of devices and their sensors.
+#[utoipa::path(
+    get, path = "/events", tag = "history",
+    params(
+        HistoryQuery,
+        ("sensor" = Option<String>, Query,
+            description = "Only changes of this sensor, by label, e.g. \"TCP 22\"; \"device\" for changes of whole devices"),
+        ("state" = Option<String>, Query, description = "Only changes into this state, e.g. DOWN"),
+    ),
+    responses((status = 200, body = Page<StateChange>), (status = 400, body = ErrorBody)),
+)]
+#[get("/events?<sensor>&<state>&<query..>")]
+async fn list_events(
+    _auth: Auth,
+    query: HistoryQuery,
+    sensor: Option<&str>,
+    state: Option<&str>,
+    storage: &State<Arc<dyn Storage>>,
+) -> ApiResult<Json<Page<StateChange>>> {
+    let filter = query.filter()?;
+    let state: Option<DeviceState> = state
+        .map(|state| state.to_uppercase().parse().map_err(ApiError::bad_request))
+        .transpose()?;
+    let changes = ChangeFilter { sensor: sensor.map(str::to_string), state };
+    let paging = paging(query.offset, query.limit);
+    let (items, total) = read_state_changes_page(storage.as_ref(), &filter, &changes, paging).await
+        .map_err(ApiError::internal)?;
+    Ok(Json(Page::new(items, total, paging)))
+}
+
+/// Logged rounds of checks.
+#[utoipa::path(
+    get, path = "/history/checks", tag = "history",
+    params(HistoryQuery),
+    responses((status = 200, body = Page<CheckRecord>), (status = 400, body = ErrorBody)),
+)]
+#[get("/history/checks?<query..>")]
+async fn list_checks(
+    _auth: Auth,
+    query: HistoryQuery,
+    storage: &State<Arc<dyn Storage>>,
+) -> ApiResult<Json<Page<CheckRecord>>> {
+    let paging = paging(query.offset, query.limit);
+    let (items, total) = read_check_records_page(storage.as_ref(), &query.filter()?, paging).await
+        .map_err(ApiError::internal)?;
+    Ok(Json(Page::new(items, total, paging)))
+}
+
+/// Uptime and latency of a device over five minutes or an hour.
+#[derive(Debug, Serialize, ToSchema)]
+struct RollupReport {
+    device_id: Uuid,
+    device: String,
+    ip: String,
+    category: String,
+    start: DateTime<Local>,
+    // 300 or 3600
+    period_secs: i64,
+    samples: u32,
+    uptime_percent: Option<f64>,
+    latency_min_ms: Option<f64>,
+    latency_avg_ms: Option<f64>,
+    latency_max_ms: Option<f64>,
+}
+
+impl From<Rollup> for RollupReport {
+    fn from(rollup: Rollup) -> Self {
+        Self {
+            uptime_percent: rollup.uptime_percent(),
+            device_id: rollup.device_id,
+            device: rollup.device,
+            ip: rollup.ip,
+            category: rollup.category,
+            start: rollup.start,
+            period_secs: rollup.period_secs,
+            samples: rollup.samples,
+            latency_min_ms: rollup.latency_min_ms,
+            latency_avg_ms: rollup.latency_avg_ms,
+            latency_max_ms: rollup.latency_max_ms,
+        }
+    }
+}
+
+/// Five minute or hourly rollups; maintenance is left out.
+#[utoipa::path(
+    get, path = "/history/rollups", tag = "history",
+    params(
+        HistoryQuery,
+        ("resolution" = Option<String>, Query, description = "5m (the default) or 1h"),
+    ),
+    responses((status = 200, body = Page<RollupReport>), (status = 400, body = ErrorBody)),
+)]
+#[get("/history/rollups?<resolution>&<query..>")]
+async fn list_rollups(
+    _auth: Auth,
+    query: HistoryQuery,
+    resolution: Option<&str>,
+    storage: &State<Arc<dyn Storage>>,
+) -> ApiResult<Json<Page<RollupReport>>> {
+    let period_secs = match resolution.unwrap_or("5m") {
+        "5m" => Rollup::FIVE_MINUTES,
+        "1h" => Rollup::HOURLY,
+        other => return Err(ApiError::bad_request(format!("Unknown resolution {:?}, expected 5m or 1h", other))),
+    };
+    let rollups = storage.rollups(period_secs, &query.filter()?).await.map_err(|e| {
+        error!("Failed to read rollups: {:#}", e);
+        ApiError::internal("Failed to read rollups")
+    })?;
+    let reports = rollups.into_iter().map(RollupReport::from).collect();
+    Ok(Json(Page::of(reports, query.offset, query.limit)))
+}
+
+// Users
+
+/// A dashboard login, without its password.
+#[derive(Debug, Serialize, ToSchema)]
+struct UserInfo {
+    username: String,
+    role: String,
+}
+
+impl From<&User> for UserInfo {
+    fn from(user: &User) -> Self {
+        Self { username: user.username.clone(), role: user.role.clone() }
+    }
+}
+
+/// A new login.
+#[derive(Debug, Deserialize, ToSchema)]
+struct NewUser {
+    username: String,
+    /// SHA-256 of the password, hex encoded
+    password_hash: String,
+    /// "admin" unless set
+    role: Option<String>,
+}
+
+/// Changes to a login; what isn't set stays as it is.
+#[derive(Debug, Deserialize, ToSchema)]
+struct UserUpdate {
+    /// SHA-256 of the password, hex encoded
+    password_hash: Option<String>,
+    role: Option<String>,
+}
+
+fn check_password_hash(hash: &str) -> ApiResult<()> {
+    if hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(ApiError::bad_request("password_hash must be the SHA-256 of the password, hex encoded"));
+    }
+    Ok(())
+}
+
+async fn load_users(storage: &dyn Storage) -> ApiResult<Vec<User>> {
+    storage.users().await.map_err(|e| {
+        error!("Failed to read users: {:#}", e);
+        ApiError::internal("Failed to read users")
+    })
+}
+
+async fn save_users(storage: &dyn Storage, users: &[User]) -> ApiResult<()> {
+    replace_users(storage, users).await.map_err(|e| {
+        error!("Failed to save users: {:#}", e);
+        ApiError::internal("Failed to save users")
+    })
+}
+
+/// The dashboard logins.
+#[utoipa::path(get, path = "/users", tag = "users", responses((status = 200, body = Vec<UserInfo>)))]
+#[get("/users")]
+async fn list_users(_auth: Auth, storage: &State<Arc<dyn Storage>>) -> ApiResult<Json<Vec<UserInfo>>> {
+    Ok(Json(load_users(storage.as_ref()).await?.iter().map(UserInfo::from).collect()))
+}
+
+/// Add a login.
+#[utoipa::path(
+    post, path = "/users", tag = "users",
+    request_body = NewUser,
+    responses(
+        (status = 201, body = UserInfo),
+        (status = 400, body = ErrorBody),
+        (status = 409, description = "Username already taken", body = ErrorBody),
+    ),
+)]
+#[post("/users", data = "<user>")]
+async fn add_user(
+    _auth: Auth,
+    user: Result<Json<NewUser>, json::Error<'_>>,
+    storage: &State<Arc<dyn Storage>>,
+) -> ApiResult<(Status, Json<UserInfo>)> {
+    let user = user.map_err(ApiError::invalid_body)?.into_inner();
+    if user.username.trim().is_empty() {
+        return Err(ApiError::bad_request("Username cannot be empty"));
+    }
+    check_password_hash(&user.password_hash)?;
+    let mut users = load_users(storage.as_ref()).await?;
+    if users.iter().any(|u| u.username == user.username) {
+        return Err(ApiError::new(Status::Conflict, "Username already exists"));
+    }
+    let user = User {
+        username: user.username,
+        password_hash: user.password_hash.to_lowercase(),
+        role: user.role.unwrap_or_else(|| "admin".to_string()),
+    };
+    let info = UserInfo::from(&user);
+    users.push(user);
+    save_users(storage.as_ref(), &users).await?;
+    Ok((Status::Created, Json(info)))
+}
+
+/// Change a login's password or role.
+#[utoipa::path(
+    put, path = "/users/{username}", tag = "users",
+    params(("username" = String, Path)),
+    request_body = UserUpdate,
+    responses((status = 200, body = UserInfo), (status = 400, body = ErrorBody), (status = 404, body = ErrorBody)),
+)]
+#[put("/users/<username>", data = "<update>")]
+async fn update_user(
+    _auth: Auth,
+    username: &str,
+    update: Result<Json<UserUpdate>, json::Error<'_>>,
+    storage: &State<Arc<dyn Storage>>,
+) -> ApiResult<Json<UserInfo>> {
+    let update = update.map_err(ApiError::invalid_body)?.into_inner();
+    if let Some(ref hash) = update.password_hash {
+        check_password_hash(hash)?;
+    }
+    let mut users = load_users(storage.as_ref()).await?;
+    let user = users.iter_mut()
+        .find(|u| u.username == username)
+        .ok_or_else(|| ApiError::not_found(format!("No user {}", username)))?;
+    if let Some(hash) = update.password_hash {
+        user.password_hash = hash.to_lowercase();
+    }
+    if let Some(role) = update.role {
+        user.role = role;
+    }
+    let info = UserInfo::from(&*user);
+    save_users(storage.as_ref(), &users).await?;
+    Ok(Json(info))
+}
+
+/// Remove a login. The last one can't be removed.
+#[utoipa::path(
+    delete, path = "/users/{username}", tag = "users",
+    params(("username" = String, Path)),
+    responses((status = 204, description = "Removed"), (status = 400, body = ErrorBody), (status = 404, body = ErrorBody)),
+)]
+#[delete("/users/<username>")]
+async fn delete_user(_auth: Auth, username: &str, storage: &State<Arc<dyn Storage>>) -> ApiResult<Status> {
+    let mut users = load_users(storage.as_ref()).await?;
+    let index = users.iter()
+        .position(|u| u.username == username)
+        .ok_or_else(|| ApiError::not_found(format!("No user {}", username)))?;
+    if users.len() == 1 {
+        return Err(ApiError::bad_request("Can't remove the last user"));
+    }
+    users.remove(index);
+    save_users(storage.as_ref(), &users).await?;
+    Ok(Status::NoContent)
+}
+
+// Notifications
+
+/// The alert email settings. `sender_password` is left out.
+#[utoipa::path(get, path = "/notifications/email", tag = "notifications", responses((status = 200, body = EmailConfig)))]
+#[get("/notifications/email")]
+async fn get_email_settings(_auth: Auth, email_service: &State<Arc<EmailService>>) -> Json<EmailConfig> {
+    let mut config = email_service.get_config().await;
+    config.sender_password.clear();
+    Json(config)
+}
+
+/// Replace the alert email settings. An empty `sender_password` keeps the current one.
+#[utoipa::path(
+    put, path = "/notifications/email", tag = "notifications",
+    request_body = EmailConfig,
+    responses((status = 200, body = EmailConfig), (status = 400, body = ErrorBody)),
+)]
+#[put("/notifications/email", data = "<config>")]
+async fn update_email_settings(
+    _auth: Auth,
+    config: Result<Json<EmailConfig>, json::Error<'_>>,
+    email_service: &State<Arc<EmailService>>,
+) -> ApiResult<Json<EmailConfig>> {
+    let mut config = config.map_err(ApiError::invalid_body)?.into_inner();
+    if config.sender_password.is_empty() {
+        config.sender_password = email_service.get_config().await.sender_password;
+    }
+    email_service.update_config(config.clone()).await.map_err(|e| ApiError::bad_request(e.to_string()))?;
+    config.sender_password.clear();
+    Ok(Json(config))
+}
+
+/// Where to send a test email.
+#[derive(Debug, Deserialize, ToSchema)]
+struct TestEmail {
+    to: String,
+}
+
+/// Send a test email with the current settings.
+#[utoipa::path(
+    post, path = "/notifications/email/test", tag = "notifications",
+    request_body = TestEmail,
+    responses(
+        (status = 204, description = "Sent"),
+        (status = 400, body = ErrorBody),
+        (status = 502, description = "The mail server refused it", body = ErrorBody),
+    ),
+)]
+#[post("/notifications/email/test", data = "<request>")]
+async fn send_test_email(
+    _auth: Auth,
+    request: Result<Json<TestEmail>, json::Error<'_>>,
+    email_service: &State<Arc<EmailService>>,
+) -> ApiResult<Status> {
+    let request = request.map_err(ApiError::invalid_body)?.into_inner();
+    if request.to.trim().is_empty() {
+        return Err(ApiError::bad_request("Test email address is required"));
+    }
+    email_service.send_test_email(&request.to).await.map_err(|e| {
+        error!("Failed to send test email: {}", e);
+        ApiError::new(Status::BadGateway, e.to_string())
+    })?;
+    Ok(Status::NoContent)
+}
@@ -0,0 +1,121 @@
+// src/config.rs
+use anyhow::Result;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use crate::models::{CheckSettings, StateSettings};
+
+const CONFIG_FILE: &str = "monitor_config.json";
+
+/// Settings for the background monitor, read from `monitor_config.json`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct MonitorConfig {
+    /// Defaults for devices and sensors that don't set their own
+    #[serde(flatten)]
+    pub defaults: CheckSettings,
+    /// Defaults for when devices count as down or flapping
+    #[serde(flatten)]
+    pub state_defaults: StateSettings,
+    /// Seconds between reloads of devices.json
+    pub reload_secs: u64,
+    /// Maximum number of devices probed at the same time
+    pub max_concurrent_checks: usize,
+    /// Which rounds of checks are kept as check results
+    pub check_results: CheckResults,
+    /// Also write results to rustPing_running.log, besides the database
+    pub log_file: bool,
+    pub log_rotation: LogRotation,
+    pub retention: Retention,
+    /// Bearer token /metrics asks for; open to anyone when unset
+    pub metrics_token: Option<String>,
+}
+
+/// Which rounds of checks are kept besides the state changes.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckResults {
+    /// Rounds in which a result changed
+    #[default]
+    Changes,
+    All,
+    Off,
+}
+
+/// When rustPing_running.log is moved aside and how many old logs are kept.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct LogRotation {
+    /// Rotate once the log is bigger than this; 0 for no limit
+    pub max_size_mb: u64,
+    /// Rotate when a new day starts
+    pub daily: bool,
+    /// Rotated logs to keep; 0 keeps all of them
+    pub keep: usize,
+    /// Gzip rotated logs
+    pub compress: bool,
+}
+
+impl Default for LogRotation {
+    fn default() -> Self {
+        Self { max_size_mb: 50, daily: false, keep: 10, compress: true }
+    }
+}
+
+/// Days of history kept in the database; 0 keeps it forever.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Retention {
+    /// Check results and state changes
+    pub raw_days: u32,
+    pub five_minute_days: u32,
+    pub hourly_days: u32,
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Self { raw_days: 30, five_minute_days: 90, hourly_days: 730 }
+    }
+}
+
+impl Default for MonitorConfig {
+    fn default() -> Self {
+        Self {
+            defaults: CheckSettings::default(),
+            state_defaults: StateSettings::default(),
+            reload_secs: 30,
+            max_concurrent_checks: 32,
+            check_results: CheckResults::default(),
+            log_file: true,
+            log_rotation: LogRotation::default(),
+            retention: Retention::default(),
+            metrics_token: None,
+        }
+    }
+}
+
+impl MonitorConfig {
+    pub fn new() -> Self {
+        if !Path::new(CONFIG_FILE).exists() {
+            info!("No monitor configuration file found, using defaults");
+            return Self::default();
+        }
+        match Self::load_config_from_file() {
+            Ok(config) => config,
+            Err(e) => {
+                error!("Failed to load {}: {}, using defaults", CONFIG_FILE, e);
+                Self::default()
+            }
+        }
+    }
+
+    fn load_config_from_file() -> Result<Self> {
+        let config_str = fs::read_to_string(CONFIG_FILE)?;
+        let mut config: Self = serde_json::from_str(&config_str)?;
+        // Zero would stall the scheduler
+        config.reload_secs = config.reload_secs.max(1);
+        config.max_concurrent_checks = config.max_concurrent_checks.max(1);
+        Ok(config)
+    }
+}
@@ -2,9 +2,9 @@ use anyhow::Result;
 use lettre::{
     transport::smtp::authentication::Credentials,
     AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
-    transport::smtp::client::SmtpConnection,
 };
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 use std::fs;
@@ -18,10 +18,12 @@ use std::collections::HashMap;
 
 const CONFIG_FILE: &str = "email_config.json";
 const SMTP_TIMEOUT: Duration = Duration::from_secs(30); // Increased timeout to 30 seconds
+#[allow(dead_code)]
 const NOTIFICATION_INTERVAL: Duration = Duration::from_secs(1800); // 30 minutes
+#[allow(dead_code)]
 const LOG_COLLECTION_PERIOD: Duration = Duration::from_secs(1800); // 30 minutes
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
 pub struct EmailConfig {
     pub smtp_server: String,
     pub smtp_port: u16,
@@ -41,11 +43,12 @@ impl Default for EmailConfig {
             sender_password: String::new(),
             recipients: Vec::new(),
             email_subject: "Failed Log Alert - {device_name}".to_string(),
-            email_body: "Device {device_name} failed at {date} {time}\nPing Status: {ping_status}\nHTTP Status: {http_status}\nBandwidth: {bandwidth}".to_string(),
+            email_body: "Device {device_name} failed at {date} {time}\nState: {state}\nPing Status: {ping_status}\nHTTP Status: {http_status}\nBandwidth: {bandwidth}\nChecks: {checks}".to_string(),
         }
     }
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Clone)]
 pub struct DeviceStatus {
     pub name: String,
@@ -58,6 +61,7 @@ pub struct DeviceStatus {
     pub last_failure: Option<String>,
 }
 
+#[allow(dead_code)]
 pub struct EmailService {
     config: Arc<RwLock<EmailConfig>>,
     mailer: Arc<RwLock<Option<AsyncSmtpTransport<Tokio1Executor>>>>,
@@ -189,6 +193,7 @@ impl EmailService {
         Ok(mailer_lock.as_ref().unwrap().clone())
     }
 
+    #[allow(dead_code)]
     pub async fn add_notification(&self, device_name: &str, status: &str, log_data: &LogData) -> Result<()> {
         let device_status = DeviceStatus {
             name: device_name.to_string(),
@@ -234,6 +239,7 @@ impl EmailService {
         Ok(())
     }
 
+    #[allow(dead_code)]
     async fn send_batch_notification(&self) -> Result<()> {
         let notifications = self.pending_notifications.read().await;
         let history = self.device_status_history.read().await;
@@ -247,7 +253,7 @@ impl EmailService {
 
         // Calculate statistics
         let total_devices = history.len();
-        let ok_devices = history.values().filter(|h| h.last().map_or(false, |s| s.status == "OK")).count();
+        let ok_devices = history.values().filter(|h| h.last().is_some_and(|s| s.status == "OK")).count();
         let failed_devices = total_devices - ok_devices;
         let health_percentage = if total_devices > 0 {
             (ok_devices as f64 / total_devices as f64 * 100.0).round()
@@ -619,7 +625,9 @@ impl EmailService {
             .replace("{time}", &log_data.time)
             .replace("{ping_status}", &log_data.ping_status)
             .replace("{http_status}", &log_data.http_status)
-            .replace("{bandwidth}", &log_data.bandwidth);
+            .replace("{bandwidth}", &log_data.bandwidth)
+            .replace("{checks}", &log_data.checks)
+            .replace("{state}", &log_data.state);
 
         let mut email_builder = Message::builder()
             .from(config.sender_email.parse()?)
@@ -709,4 +717,6 @@ pub struct LogData {
     pub ping_status: String,
     pub http_status: String,
     pub bandwidth: String,
-} 
\ No newline at end of file
+    pub checks: String,
+    pub state: String,
+}
@@ -0,0 +1,316 @@
+// src/http_client.rs
+use anyhow::{anyhow, bail, Result};
+use hyper::body::{Bytes, HttpBody};
+use hyper::client::conn;
+use hyper::header::{HeaderName, HeaderValue, CONTENT_LENGTH, CONTENT_TYPE, HOST, LOCATION, USER_AGENT};
+use hyper::{Body, Method, Request, StatusCode};
+use log::debug;
+use reqwest::Url;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{lookup_host, TcpStream};
+use tokio::time::Instant;
+use crate::models::HttpTiming;
+use crate::tls;
+
+const MAX_REDIRECTS: usize = 10;
+// Credentials that only go to the origin they were configured for
+const SENSITIVE_HEADERS: &[&str] = &["authorization", "cookie", "proxy-authorization"];
+// Responses are kept in memory for the keyword and JSON checks, so a check fails
+// rather than reading more than this
+const MAX_BODY_BYTES: usize = 10 * 1024 * 1024;
+
+/// A single request to send, following redirects when `follow_redirects` is set.
+pub struct HttpRequest<'a> {
+    pub url: &'a str,
+    pub method: Method,
+    pub headers: &'a BTreeMap<String, String>,
+    pub body: Option<&'a str>,
+    pub follow_redirects: bool,
+}
+
+pub struct HttpResponse {
+    pub status: StatusCode,
+    pub body: Bytes,
+    pub timing: HttpTiming,
+}
+
+// Time spent in each phase of one connection
+#[derive(Default)]
+struct Phases {
+    dns_ms: f64,
+    connect_ms: f64,
+    tls_ms: f64,
+}
+
+fn elapsed_ms(since: Instant) -> f64 {
+    since.elapsed().as_secs_f64() * 1000.0
+}
+
+/// Send the request over a fresh connection per hop, timing DNS, connect,
+/// TLS and time-to-first-byte separately. reqwest doesn't expose these.
+pub async fn send(request: HttpRequest<'_>) -> Result<HttpResponse> {
+    let started = Instant::now();
+    let mut url = Url::parse(request.url)?;
+    let mut method = request.method.clone();
+    let mut body = request.body;
+    let mut headers = request.headers.clone();
+    let mut phases = Phases::default();
+    let mut redirects = 0;
+
+    loop {
+        let (response, hop) = send_once(&url, &method, &headers, body).await?;
+        phases.dns_ms += hop.dns_ms;
+        phases.connect_ms += hop.connect_ms;
+        phases.tls_ms += hop.tls_ms;
+
+        let status = response.status();
+        let location = response.headers().get(LOCATION).and_then(|value| value.to_str().ok());
+        if request.follow_redirects && status.is_redirection() && location.is_some() {
+            if redirects == MAX_REDIRECTS {
+                bail!("Too many redirects");
+            }
+            let next = url.join(location.unwrap_or_default())?;
+            debug!("Following {} redirect from {} to {}", status.as_u16(), url, next);
+            // Like browsers, only 307/308 keep the method and body
+            if !matches!(status, StatusCode::TEMPORARY_REDIRECT | StatusCode::PERMANENT_REDIRECT)
+                && method != Method::HEAD
+            {
+                method = Method::GET;
+                body = None;
+            }
+            // Like reqwest and browsers, don't pass credentials on to another origin
+            if next.origin() != url.origin() {
+                remove_sensitive_headers(&mut headers);
+            }
+            url = next;
+            redirects += 1;
+            continue;
+        }
+
+        let ttfb_ms = elapsed_ms(started);
+        let body = read_body(response.into_body(), MAX_BODY_BYTES).await?;
+        let timing = HttpTiming {
+            dns_ms: phases.dns_ms,
+            connect_ms: phases.connect_ms,
+            tls_ms: (url.scheme() == "https").then_some(phases.tls_ms),
+            ttfb_ms,
+            total_ms: elapsed_ms(started),
+            size_bytes: body.len() as u64,
+            final_url: url.to_string(),
+            redirects,
+        };
+        return Ok(HttpResponse { status, body, timing });
+    }
+}
+
+fn remove_sensitive_headers(headers: &mut BTreeMap<String, String>) {
+    headers.retain(|name, _| !SENSITIVE_HEADERS.iter().any(|sensitive| name.eq_ignore_ascii_case(sensitive)));
+}
+
+async fn read_body(mut body: Body, limit: usize) -> Result<Bytes> {
+    let too_large = || anyhow!("Response body is larger than {} bytes", limit);
+    if body.size_hint().lower() > limit as u64 {
+        return Err(too_large());
+    }
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        if bytes.len() + chunk.len() > limit {
+            return Err(too_large());
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(bytes.into())
+}
+
+async fn send_once(
+    url: &Url,
+    method: &Method,
+    headers: &BTreeMap<String, String>,
+    body: Option<&str>,
+) -> Result<(hyper::Response<Body>, Phases)> {
+    let https = match url.scheme() {
+        "http" => false,
+        "https" => true,
+        scheme => bail!("Unsupported URL scheme {}", scheme),
+    };
+    let host = url.host_str().ok_or_else(|| anyhow!("URL has no host: {}", url))?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let port = url.port_or_known_default().unwrap_or(if https { 443 } else { 80 });
+    let mut phases = Phases::default();
+
+    let started = Instant::now();
+    let addrs: Vec<SocketAddr> = lookup_host((host, port)).await?.collect();
+    phases.dns_ms = elapsed_ms(started);
+
+    let started = Instant::now();
+    let stream = connect(&addrs).await?;
+    phases.connect_ms = elapsed_ms(started);
+
+    let path = match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_string(),
+    };
+    let authority = match url.port() {
+        Some(port) => format!("{}:{}", url.host_str().unwrap_or(host), port),
+        None => url.host_str().unwrap_or(host).to_string(),
+    };
+    let mut builder = Request::builder()
+        .method(method.clone())
+        .uri(path)
+        .header(HOST, authority)
+        .header(USER_AGENT, concat!("RustPing/", env!("CARGO_PKG_VERSION")));
+    for (name, value) in headers {
+        let name = HeaderName::from_bytes(name.as_bytes())?;
+        let value = HeaderValue::from_str(value)?;
+        if let Some(request_headers) = builder.headers_mut() {
+            request_headers.insert(name, value);
+        }
+    }
+    let request = match body {
+        Some(body) => {
+            let has_type = builder.headers_ref().is_some_and(|h| h.contains_key(CONTENT_TYPE));
+            if !has_type {
+                builder = builder.header(CONTENT_TYPE, "text/plain");
+            }
+            builder.header(CONTENT_LENGTH, body.len()).body(Body::from(body.to_string()))?
+        }
+        None => builder.body(Body::empty())?,
+    };
+
+    let response = if https {
+        let started = Instant::now();
+        let (stream, peer) = tls::handshake(stream, host).await?;
+        phases.tls_ms = elapsed_ms(started);
+        if let Some(e) = peer.verify_error {
+            bail!("Invalid certificate: {}", e);
+        }
+        exchange(stream, request).await?
+    } else {
+        exchange(stream, request).await?
+    };
+    Ok((response, phases))
+}
+
+async fn connect(addrs: &[SocketAddr]) -> Result<TcpStream> {
+    let mut last_error = None;
+    for addr in addrs {
+        match TcpStream::connect(addr).await {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.map_or_else(|| anyhow!("No addresses found"), Into::into))
+}
+
+async fn exchange<S>(stream: S, request: Request<Body>) -> Result<hyper::Response<Body>>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (mut sender, connection) = conn::handshake(stream).await?;
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            debug!("HTTP connection closed with error: {}", e);
+        }
+    });
+    Ok(sender.send_request(request).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunked(chunks: &[&'static str]) -> Body {
+        let (mut sender, body) = Body::channel();
+        let chunks: Vec<&'static str> = chunks.to_vec();
+        tokio::spawn(async move {
+            for chunk in chunks {
+                if sender.send_data(Bytes::from(chunk)).await.is_err() {
+                    return;
+                }
+            }
+        });
+        body
+    }
+
+    #[tokio::test]
+    async fn reads_body_up_to_limit() {
+        let body = read_body(chunked(&["abc", "def"]), 6).await.unwrap();
+        assert_eq!(&body[..], b"abcdef");
+    }
+
+    #[tokio::test]
+    async fn rejects_body_over_limit() {
+        assert!(read_body(Body::from("abcdefg"), 6).await.is_err());
+        assert!(read_body(chunked(&["abc", "def", "g"]), 6).await.is_err());
+    }
+
+    // Answer one request per response with `responses`, sending back the request heads
+    async fn server(responses: Vec<String>) -> (u16, tokio::sync::mpsc::UnboundedReceiver<String>) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (heads, received) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            for response in responses {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut head = Vec::new();
+                let mut buffer = [0u8; 1024];
+                while !head.ends_with(b"\r\n\r\n") {
+                    let read = stream.read(&mut buffer).await.unwrap();
+                    if read == 0 {
+                        break;
+                    }
+                    head.extend_from_slice(&buffer[..read]);
+                }
+                let _ = heads.send(String::from_utf8_lossy(&head).to_lowercase());
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        (port, received)
+    }
+
+    fn redirect(to: &str) -> String {
+        format!("HTTP/1.1 302 Found\r\nLocation: {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", to)
+    }
+
+    const OK: &str = "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok";
+
+    #[test]
+    fn removes_sensitive_headers_ignoring_case() {
+        let mut headers: BTreeMap<String, String> = [("Authorization", "Bearer x"), ("COOKIE", "a=b"), ("X-Trace", "1")]
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        remove_sensitive_headers(&mut headers);
+        assert_eq!(headers.keys().collect::<Vec<_>>(), ["X-Trace"]);
+    }
+
+    #[tokio::test]
+    async fn redirects_keep_credentials_within_the_origin_only() {
+        let (other, mut other_heads) = server(vec![OK.to_string()]).await;
+        let (origin, mut origin_heads) = server(vec![
+            redirect("/next"),
+            redirect(&format!("http://127.0.0.1:{}/elsewhere", other)),
+        ]).await;
+        let headers: BTreeMap<String, String> = [("Authorization", "Bearer secret"), ("X-Trace", "1")]
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        let url = format!("http://127.0.0.1:{}/", origin);
+        let request = HttpRequest { url: &url, method: Method::GET, headers: &headers, body: None, follow_redirects: true };
+
+        let response = send(request).await.unwrap();
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.timing.redirects, 2);
+        for _ in 0..2 {
+            assert!(origin_heads.recv().await.unwrap().contains("authorization: bearer secret"));
+        }
+        // Another port is another origin
+        let head = other_heads.recv().await.unwrap();
+        assert!(!head.contains("authorization"));
+        assert!(head.contains("x-trace: 1"));
+    }
+}
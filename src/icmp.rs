@@ -0,0 +1,410 @@
+// src/icmp.rs
+use anyhow::{anyhow, Result};
+use log::debug;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::net::{IpAddr, SocketAddr};
+use tokio::io::Interest;
+use tokio::net::{lookup_host, UdpSocket};
+use tokio::time::{sleep, timeout_at, Duration, Instant};
+
+const ICMP_HEADER_SIZE: usize = 8;
+const PAYLOAD_SIZE: usize = 32;
+const RECV_BUFFER_SIZE: usize = 2048;
+
+const ICMPV4_ECHO_REQUEST: u8 = 8;
+const ICMPV4_ECHO_REPLY: u8 = 0;
+const ICMPV6_ECHO_REQUEST: u8 = 128;
+const ICMPV6_ECHO_REPLY: u8 = 129;
+
+/// A single echo reply received for one probe.
+#[derive(Debug, Clone)]
+pub struct EchoReply {
+    pub seq: u16,
+    pub rtt: Duration,
+    pub ttl: Option<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SocketKind {
+    // Unprivileged ICMP socket (Linux ping_group_range, macOS).
+    Datagram,
+    // Raw socket, needs root or CAP_NET_RAW.
+    Raw,
+}
+
+/// An ICMP echo session against a single target.
+pub struct Pinger {
+    socket: UdpSocket,
+    kind: SocketKind,
+    dest: SocketAddr,
+    ident: u16,
+}
+
+impl Pinger {
+    pub fn new(addr: IpAddr) -> Result<Self> {
+        let (domain, protocol) = match addr {
+            IpAddr::V4(_) => (Domain::IPV4, Protocol::ICMPV4),
+            IpAddr::V6(_) => (Domain::IPV6, Protocol::ICMPV6),
+        };
+
+        // Prefer datagram sockets so we don't need elevated privileges,
+        // and only fall back to raw sockets when the kernel refuses them.
+        let (socket, kind) = match Socket::new(domain, Type::DGRAM, Some(protocol)) {
+            Ok(socket) => (socket, SocketKind::Datagram),
+            Err(dgram_err) => match Socket::new(domain, Type::RAW, Some(protocol)) {
+                Ok(socket) => (socket, SocketKind::Raw),
+                Err(raw_err) => {
+                    return Err(anyhow!(
+                        "Unable to open ICMP socket (datagram: {}, raw: {})",
+                        dgram_err, raw_err
+                    ));
+                }
+            },
+        };
+        debug!("Using {:?} ICMP socket for {}", kind, addr);
+
+        socket.set_nonblocking(true)?;
+        #[cfg(target_os = "linux")]
+        enable_recv_ttl(&socket, addr)?;
+
+        let socket = UdpSocket::from_std(socket.into())?;
+
+        Ok(Self {
+            socket,
+            kind,
+            dest: SocketAddr::new(addr, 0),
+            ident: rand::random(),
+        })
+    }
+
+    /// Send one echo request and wait for its reply.
+    /// Returns `Ok(None)` when no reply arrives before the timeout.
+    pub async fn echo(&self, seq: u16, timeout: Duration) -> Result<Option<EchoReply>> {
+        let request = self.encode_request(seq);
+        let sent_at = Instant::now();
+        let deadline = sent_at + timeout;
+        self.socket.send_to(&request, self.dest).await?;
+
+        let mut buffer = [0u8; RECV_BUFFER_SIZE];
+        loop {
+            let (len, header_ttl) = match timeout_at(deadline, self.recv(&mut buffer)).await {
+                Ok(result) => result?,
+                Err(_) => return Ok(None),
+            };
+            let rtt = sent_at.elapsed();
+
+            if let Some(ttl) = self.match_reply(&buffer[..len], seq) {
+                return Ok(Some(EchoReply { seq, rtt, ttl: ttl.or(header_ttl) }));
+            }
+        }
+    }
+
+    fn encode_request(&self, seq: u16) -> Vec<u8> {
+        let icmp_type = match self.dest {
+            SocketAddr::V4(_) => ICMPV4_ECHO_REQUEST,
+            SocketAddr::V6(_) => ICMPV6_ECHO_REQUEST,
+        };
+
+        let mut packet = vec![0u8; ICMP_HEADER_SIZE + PAYLOAD_SIZE];
+        packet[0] = icmp_type;
+        packet[4..6].copy_from_slice(&self.ident.to_be_bytes());
+        packet[6..8].copy_from_slice(&seq.to_be_bytes());
+        for (i, byte) in packet[ICMP_HEADER_SIZE..].iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+
+        // The kernel fills in the checksum for ICMPv6.
+        if self.dest.is_ipv4() {
+            let checksum = checksum(&packet);
+            packet[2..4].copy_from_slice(&checksum.to_be_bytes());
+        }
+        packet
+    }
+
+    /// Check whether `packet` is the echo reply for `seq`.
+    /// On a match, returns the TTL taken from the IP header when one is present.
+    fn match_reply(&self, packet: &[u8], seq: u16) -> Option<Option<u8>> {
+        // Raw IPv4 sockets (and datagram sockets on macOS) hand us the IP header too.
+        let (icmp, ttl) = if self.dest.is_ipv4() && packet.first().is_some_and(|b| b >> 4 == 4) {
+            let header_len = ((packet[0] & 0x0f) as usize) * 4;
+            if packet.len() < header_len {
+                return None;
+            }
+            (&packet[header_len..], packet.get(8).copied())
+        } else {
+            (packet, None)
+        };
+
+        if icmp.len() < ICMP_HEADER_SIZE {
+            return None;
+        }
+
+        let reply_type = if self.dest.is_ipv4() { ICMPV4_ECHO_REPLY } else { ICMPV6_ECHO_REPLY };
+        if icmp[0] != reply_type {
+            return None;
+        }
+
+        // Datagram sockets get their identifier rewritten by the kernel, which
+        // also filters replies per socket, so only raw sockets check it.
+        let ident = u16::from_be_bytes([icmp[4], icmp[5]]);
+        if self.kind == SocketKind::Raw && ident != self.ident {
+            return None;
+        }
+        if u16::from_be_bytes([icmp[6], icmp[7]]) != seq {
+            return None;
+        }
+
+        Some(ttl)
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn recv(&self, buffer: &mut [u8]) -> Result<(usize, Option<u8>)> {
+        use socket2::{MaybeUninitSlice, MsgHdrMut, SockRef};
+        use std::mem::MaybeUninit;
+
+        let result = self.socket.async_io(Interest::READABLE, || {
+            let mut data = [MaybeUninit::<u8>::uninit(); RECV_BUFFER_SIZE];
+            let mut control = [MaybeUninit::<u8>::uninit(); 64];
+            let mut bufs = [MaybeUninitSlice::new(&mut data)];
+            let mut msg = MsgHdrMut::new()
+                .with_buffers(&mut bufs)
+                .with_control(&mut control);
+            let len = SockRef::from(&self.socket).recvmsg(&mut msg, 0)?;
+            let control_len = msg.control_len();
+
+            let len = len.min(buffer.len());
+            for (dst, src) in buffer.iter_mut().zip(data.iter()).take(len) {
+                // SAFETY: recvmsg initialised the first `len` bytes.
+                *dst = unsafe { src.assume_init() };
+            }
+            // SAFETY: recvmsg initialised the first `control_len` bytes.
+            let control: Vec<u8> = control[..control_len]
+                .iter()
+                .map(|b| unsafe { b.assume_init() })
+                .collect();
+            Ok((len, parse_ttl_cmsg(&control)))
+        }).await?;
+        Ok(result)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    async fn recv(&self, buffer: &mut [u8]) -> Result<(usize, Option<u8>)> {
+        let len = self.socket.async_io(Interest::READABLE, || {
+            socket2::SockRef::from(&self.socket).recv(unsafe {
+                &mut *(buffer as *mut [u8] as *mut [std::mem::MaybeUninit<u8>])
+            })
+        }).await?;
+        Ok((len, None))
+    }
+}
+
+/// Ask the kernel to attach the reply TTL / hop limit as ancillary data,
+/// since datagram ICMP sockets on Linux strip the IP header.
+#[cfg(target_os = "linux")]
+fn enable_recv_ttl(socket: &Socket, addr: IpAddr) -> Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let enable: libc::c_int = 1;
+    let (level, name) = match addr {
+        IpAddr::V4(_) => (libc::IPPROTO_IP, libc::IP_RECVTTL),
+        IpAddr::V6(_) => (libc::IPPROTO_IPV6, libc::IPV6_RECVHOPLIMIT),
+    };
+    // SAFETY: the fd is owned by `socket` and `enable` outlives the call.
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            name,
+            &enable as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if ret != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn parse_ttl_cmsg(control: &[u8]) -> Option<u8> {
+    if control.is_empty() {
+        return None;
+    }
+
+    // SAFETY: the msghdr only describes `control`, which stays alive for the
+    // whole walk, and the CMSG_* macros never read past msg_controllen.
+    unsafe {
+        let mut msg: libc::msghdr = std::mem::zeroed();
+        msg.msg_control = control.as_ptr() as *mut libc::c_void;
+        msg.msg_controllen = control.len() as _;
+
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            let header = &*cmsg;
+            let is_ttl = (header.cmsg_level == libc::IPPROTO_IP && header.cmsg_type == libc::IP_TTL)
+                || (header.cmsg_level == libc::IPPROTO_IPV6 && header.cmsg_type == libc::IPV6_HOPLIMIT);
+            if is_ttl {
+                let value = std::ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const libc::c_int);
+                return u8::try_from(value).ok();
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+    None
+}
+
+fn checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = 0;
+    for chunk in data.chunks(2) {
+        let word = match chunk {
+            [hi, lo] => u16::from_be_bytes([*hi, *lo]),
+            [hi] => u16::from_be_bytes([*hi, 0]),
+            _ => 0,
+        };
+        sum += word as u32;
+    }
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Resolve an IP address or hostname to the address we should probe.
+pub async fn resolve(host: &str) -> Result<IpAddr> {
+    if let Ok(addr) = host.parse::<IpAddr>() {
+        return Ok(addr);
+    }
+    lookup_host((host, 0))
+        .await?
+        .next()
+        .map(|addr| addr.ip())
+        .ok_or_else(|| anyhow!("No addresses found for {}", host))
+}
+
+/// Send `count` echo requests to `host`, spaced `interval` apart.
+/// The result has one entry per probe, `None` for probes that were lost.
+pub async fn ping(host: &str, count: u16, timeout: Duration, interval: Duration) -> Result<Vec<Option<EchoReply>>> {
+    let addr = resolve(host).await?;
+    let pinger = Pinger::new(addr)?;
+
+    let mut replies = Vec::with_capacity(count as usize);
+    for seq in 1..=count {
+        replies.push(pinger.echo(seq, timeout).await?);
+        if seq < count {
+            sleep(interval).await;
+        }
+    }
+    Ok(replies)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IDENT: u16 = 0x1234;
+
+    async fn new_pinger(dest: &str, kind: SocketKind) -> Pinger {
+        // Any socket will do, as matching replies never touches it
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        Pinger { socket, kind, dest: SocketAddr::new(dest.parse().unwrap(), 0), ident: IDENT }
+    }
+
+    fn echo_reply(icmp_type: u8, ident: u16, seq: u16) -> Vec<u8> {
+        let mut packet = vec![0u8; ICMP_HEADER_SIZE + PAYLOAD_SIZE];
+        packet[0] = icmp_type;
+        packet[4..6].copy_from_slice(&ident.to_be_bytes());
+        packet[6..8].copy_from_slice(&seq.to_be_bytes());
+        packet
+    }
+
+    // An IPv4 header of `words` 32-bit words with the given TTL, in front of `icmp`
+    fn with_ip_header(words: u8, ttl: u8, icmp: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0u8; words as usize * 4];
+        packet[0] = 0x40 | words;
+        packet[8] = ttl;
+        packet.extend_from_slice(icmp);
+        packet
+    }
+
+    #[test]
+    fn checksum_matches_rfc_1071() {
+        assert_eq!(checksum(&[0x00, 0x01, 0xf2, 0x03, 0xf4, 0xf5, 0xf6, 0xf7]), 0x220d);
+        // An odd byte out is padded with a zero
+        assert_eq!(checksum(&[0x00, 0x01, 0xf2]), !0xf201);
+        assert_eq!(checksum(&[]), 0xffff);
+    }
+
+    #[tokio::test]
+    async fn encodes_echo_request() {
+        let pinger = new_pinger("127.0.0.1", SocketKind::Datagram).await;
+        let packet = pinger.encode_request(7);
+        assert_eq!(packet.len(), ICMP_HEADER_SIZE + PAYLOAD_SIZE);
+        assert_eq!(packet[0], ICMPV4_ECHO_REQUEST);
+        assert_eq!(&packet[4..8], &[0x12, 0x34, 0x00, 0x07]);
+        // A packet carrying its own checksum sums to zero
+        assert_eq!(checksum(&packet), 0);
+
+        let pinger = new_pinger("::1", SocketKind::Datagram).await;
+        let packet = pinger.encode_request(7);
+        assert_eq!(packet[0], ICMPV6_ECHO_REQUEST);
+        assert_eq!(&packet[2..4], &[0, 0]);
+    }
+
+    #[tokio::test]
+    async fn matches_datagram_replies() {
+        let pinger = new_pinger("127.0.0.1", SocketKind::Datagram).await;
+        // The kernel rewrites the identifier, so any will do
+        assert_eq!(pinger.match_reply(&echo_reply(ICMPV4_ECHO_REPLY, 0x9999, 3), 3), Some(None));
+        assert_eq!(pinger.match_reply(&echo_reply(ICMPV4_ECHO_REPLY, IDENT, 4), 3), None);
+        assert_eq!(pinger.match_reply(&echo_reply(ICMPV4_ECHO_REQUEST, IDENT, 3), 3), None);
+        assert_eq!(pinger.match_reply(&echo_reply(ICMPV4_ECHO_REPLY, IDENT, 3)[..4], 3), None);
+
+        let pinger = new_pinger("::1", SocketKind::Datagram).await;
+        assert_eq!(pinger.match_reply(&echo_reply(ICMPV6_ECHO_REPLY, IDENT, 3), 3), Some(None));
+        assert_eq!(pinger.match_reply(&echo_reply(ICMPV4_ECHO_REPLY, IDENT, 3), 3), None);
+    }
+
+    #[tokio::test]
+    async fn matches_raw_replies_after_ip_header() {
+        let pinger = new_pinger("127.0.0.1", SocketKind::Raw).await;
+        let reply = echo_reply(ICMPV4_ECHO_REPLY, IDENT, 3);
+        assert_eq!(pinger.match_reply(&with_ip_header(5, 57, &reply), 3), Some(Some(57)));
+        // With IP options the header is longer
+        assert_eq!(pinger.match_reply(&with_ip_header(6, 120, &reply), 3), Some(Some(120)));
+        // Replies to other processes' pings come in on raw sockets too
+        let other = echo_reply(ICMPV4_ECHO_REPLY, 0x9999, 3);
+        assert_eq!(pinger.match_reply(&with_ip_header(5, 57, &other), 3), None);
+        assert_eq!(pinger.match_reply(&with_ip_header(5, 57, &reply[..4]), 3), None);
+        assert_eq!(pinger.match_reply(&with_ip_header(5, 57, &[])[..12], 3), None);
+    }
+
+    // A control message carrying `value`, in a buffer aligned like the kernel's
+    #[cfg(target_os = "linux")]
+    fn cmsg(level: libc::c_int, kind: libc::c_int, value: libc::c_int) -> Vec<u8> {
+        let mut buffer = [0u64; 8];
+        let size = std::mem::size_of::<libc::c_int>() as u32;
+        // SAFETY: the header and data written fit in CMSG_SPACE bytes of `buffer`.
+        let space = unsafe {
+            let mut msg: libc::msghdr = std::mem::zeroed();
+            msg.msg_control = buffer.as_mut_ptr() as *mut libc::c_void;
+            msg.msg_controllen = std::mem::size_of_val(&buffer) as _;
+            let header = libc::CMSG_FIRSTHDR(&msg);
+            (*header).cmsg_level = level;
+            (*header).cmsg_type = kind;
+            (*header).cmsg_len = libc::CMSG_LEN(size) as _;
+            std::ptr::write_unaligned(libc::CMSG_DATA(header) as *mut libc::c_int, value);
+            libc::CMSG_SPACE(size) as usize
+        };
+        buffer.iter().flat_map(|word| word.to_ne_bytes()).take(space).collect()
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn parses_ttl_from_control_messages() {
+        assert_eq!(parse_ttl_cmsg(&cmsg(libc::IPPROTO_IP, libc::IP_TTL, 64)), Some(64));
+        assert_eq!(parse_ttl_cmsg(&cmsg(libc::IPPROTO_IPV6, libc::IPV6_HOPLIMIT, 255)), Some(255));
+        assert_eq!(parse_ttl_cmsg(&cmsg(libc::IPPROTO_IP, libc::IP_TOS, 64)), None);
+        assert_eq!(parse_ttl_cmsg(&cmsg(libc::IPPROTO_IP, libc::IP_TTL, 300)), None);
+        assert_eq!(parse_ttl_cmsg(&[]), None);
+    }
+}
@@ -0,0 +1,363 @@
+// src/log_file.rs
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use log::{debug, error, info};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use crate::config::LogRotation;
+use crate::models::{BandwidthRates, CheckRecord, CheckResult, HttpTiming, LogFilter, StateChange};
+
+/// One line of the log file, as JSON with a `type` of `check` or `state_change`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LogEvent {
+    Check(Box<CheckRecord>),
+    StateChange(Box<StateChange>),
+}
+
+impl LogEvent {
+    /// A line as written now, or a check result in the text format of older versions.
+    /// None for blank lines, "//" comments and anything else that can't be read.
+    pub fn parse(line: &str) -> Option<Self> {
+        let line = line.trim();
+        if line.starts_with('{') {
+            return serde_json::from_str(line)
+                .map_err(|e| debug!("Skipping unreadable log entry: {}", e))
+                .ok();
+        }
+        parse_legacy(line).map(|record| LogEvent::Check(Box::new(record)))
+    }
+}
+
+/// Check results in the log file at `path` passing `filter`, oldest first.
+pub fn read_checks(path: impl AsRef<Path>, filter: &LogFilter) -> io::Result<Vec<CheckRecord>> {
+    Ok(read_events(path)?
+        .into_iter()
+        .filter_map(|event| match event {
+            LogEvent::Check(record) => Some(*record),
+            LogEvent::StateChange(_) => None,
+        })
+        .filter(|record| filter.matches(record.timestamp, record.device_id, &record.device, &record.ip))
+        .collect())
+}
+
+/// State changes in the log file at `path` passing `filter`, oldest first.
+pub fn read_state_changes(path: impl AsRef<Path>, filter: &LogFilter) -> io::Result<Vec<StateChange>> {
+    Ok(read_events(path)?
+        .into_iter()
+        .filter_map(|event| match event {
+            LogEvent::StateChange(change) => Some(*change),
+            LogEvent::Check(_) => None,
+        })
+        .filter(|change| filter.matches(change.timestamp, change.device_id, &change.device, &change.ip))
+        .collect())
+}
+
+fn read_events(path: impl AsRef<Path>) -> io::Result<Vec<LogEvent>> {
+    Ok(fs::read_to_string(path)?.lines().filter_map(LogEvent::parse).collect())
+}
+
+// "2024-05-01 10:00:00 - Core Switch (10.0.0.1): State: UP, Ping: OK, HTTP: N/A, Bandwidth: N/A, ..."
+// as written before the log became JSON
+fn parse_legacy(line: &str) -> Option<CheckRecord> {
+    let (timestamp, rest) = line.split_once(" - ")?;
+    let timestamp = NaiveDateTime::parse_from_str(timestamp, "%Y-%m-%d %H:%M:%S").ok()
+        .and_then(|t| Local.from_local_datetime(&t).earliest())?;
+    let (device, statuses) = rest.split_once("): ")?;
+    let (device, ip) = device.rsplit_once(" (")?;
+    let mut record = CheckRecord {
+        timestamp,
+        device_id: None,
+        device: device.trim().to_string(),
+        ip: ip.to_string(),
+        category: String::new(),
+        state: None,
+        ping: "N/A".to_string(),
+        http: "N/A".to_string(),
+        bandwidth: None,
+        latency_ms: None,
+        jitter_ms: None,
+        loss_percent: None,
+        http_timing: None,
+        checks: Vec::new(),
+    };
+    let ms = |value: &str| value.trim_end_matches(" ms").parse::<f64>().ok();
+    for status in statuses.split(", ") {
+        let Some((name, value)) = status.split_once(": ") else { continue };
+        let value = value.trim();
+        match name.trim() {
+            "State" => record.state = value.parse().ok(),
+            "Ping" => record.ping = value.to_string(),
+            "HTTP" => record.http = value.to_string(),
+            "Bandwidth" => record.bandwidth = parse_bandwidth(value),
+            "Latency" => record.latency_ms = ms(value),
+            "Jitter" => record.jitter_ms = ms(value),
+            "Loss" => record.loss_percent = value.trim_end_matches('%').parse().ok(),
+            "HTTP Timing" => record.http_timing = parse_http_timing(value),
+            "Final URL" => {
+                if let Some(ref mut timing) = record.http_timing {
+                    timing.final_url = value.to_string();
+                    timing.redirects = timing.redirects.max(1);
+                }
+            }
+            sensor => record.checks.push(CheckResult {
+                sensor: sensor.to_string(),
+                success: value != "FAIL",
+                warning: (value == "WARN").then(|| value.to_string()),
+                ..Default::default()
+            }),
+        }
+    }
+    Some(record)
+}
+
+// "In 1.00 Mbps / Out 2.00 Mbps" or "1.00 Mbps"
+fn parse_bandwidth(value: &str) -> Option<BandwidthRates> {
+    let mbps = |value: &str| value.trim().trim_end_matches(" Mbps").parse::<f64>().ok();
+    match value.strip_prefix("In ").and_then(|rest| rest.split_once(" / Out ")) {
+        Some((in_mbps, out_mbps)) => Some(BandwidthRates { in_mbps: mbps(in_mbps)?, out_mbps: mbps(out_mbps) }),
+        None => Some(BandwidthRates { in_mbps: mbps(value)?, out_mbps: None }),
+    }
+}
+
+// "DNS 1.00 ms / Connect 2.00 ms / TLS N/A / TTFB 3.00 ms / Total 4.00 ms / 512 B"
+fn parse_http_timing(value: &str) -> Option<HttpTiming> {
+    let mut timing = HttpTiming::default();
+    for part in value.split(" / ") {
+        let ms = |value: &str| value.trim_end_matches(" ms").parse::<f64>().ok();
+        match part.split_once(' ') {
+            Some(("DNS", value)) => timing.dns_ms = ms(value)?,
+            Some(("Connect", value)) => timing.connect_ms = ms(value)?,
+            Some(("TLS", value)) => timing.tls_ms = ms(value),
+            Some(("TTFB", value)) => timing.ttfb_ms = ms(value)?,
+            Some(("Total", value)) => timing.total_ms = ms(value)?,
+            Some((size, "B")) => timing.size_bytes = size.parse().ok()?,
+            _ => return None,
+        }
+    }
+    Some(timing)
+}
+
+/// The log of check results and state changes, moved aside once it gets too big or
+/// a new day starts. Rotated logs are named after the log and the time of their last
+/// entry, e.g. `rustPing_running-20240131-235959.log`, and gzipped unless `compress` is off.
+pub struct LogFile {
+    path: PathBuf,
+    rotation: LogRotation,
+}
+
+impl LogFile {
+    pub fn new(path: impl Into<PathBuf>, rotation: LogRotation) -> Self {
+        Self { path: path.into(), rotation }
+    }
+
+    /// Append `event` as a line of JSON, rotating the log first if it's due.
+    pub fn append(&self, event: &LogEvent) {
+        if let Err(e) = self.rotate_if_due() {
+            error!("Failed to rotate {}: {}", self.path.display(), e);
+        }
+        let line = match serde_json::to_string(event) {
+            Ok(line) => line,
+            Err(e) => return error!("Failed to serialize log entry: {}", e),
+        };
+        let written = OpenOptions::new().append(true).create(true).open(&self.path)
+            .and_then(|mut file| file.write_all(format!("{}\n", line).as_bytes()));
+        if let Err(e) = written {
+            error!("Failed to write log entry: {}", e);
+        }
+    }
+
+    fn rotate_if_due(&self) -> io::Result<()> {
+        let metadata = match fs::metadata(&self.path) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let modified: DateTime<Local> = metadata.modified()?.into();
+        let too_big = self.rotation.max_size_mb > 0 && metadata.len() > self.rotation.max_size_mb * 1024 * 1024;
+        let new_day = self.rotation.daily && modified.date_naive() < Local::now().date_naive();
+        if !too_big && !new_day {
+            return Ok(());
+        }
+
+        let rotated = self.rotated_path(&modified);
+        fs::rename(&self.path, &rotated)?;
+        info!("Rotated {} to {}", self.path.display(), rotated.display());
+
+        // Compressing a big log takes a while, so the monitor carries on meanwhile
+        let rotation = self.rotation.clone();
+        let path = self.path.clone();
+        tokio::task::spawn_blocking(move || {
+            if rotation.compress {
+                if let Err(e) = compress(&rotated) {
+                    error!("Failed to compress {}: {}", rotated.display(), e);
+                }
+            }
+            if rotation.keep > 0 {
+                if let Err(e) = remove_old(&path, rotation.keep) {
+                    error!("Failed to remove old logs: {}", e);
+                }
+            }
+        });
+        Ok(())
+    }
+
+    // A name that isn't taken yet, even when rotating twice within a second
+    fn rotated_path(&self, modified: &DateTime<Local>) -> PathBuf {
+        let (stem, extension) = stem_and_extension(&self.path);
+        let name = format!("{}-{}", stem, modified.format("%Y%m%d-%H%M%S"));
+        let mut rotated = self.path.with_file_name(format!("{}.{}", name, extension));
+        let mut n = 1;
+        while rotated.exists() || rotated.with_extension(format!("{}.gz", extension)).exists() {
+            rotated = self.path.with_file_name(format!("{}-{}.{}", name, n, extension));
+            n += 1;
+        }
+        rotated
+    }
+}
+
+fn stem_and_extension(path: &Path) -> (String, String) {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+    let extension = path.extension().unwrap_or_default().to_string_lossy().into_owned();
+    (stem, extension)
+}
+
+// Replace `path` with `path.gz`
+fn compress(path: &Path) -> io::Result<()> {
+    let mut gz_name = path.as_os_str().to_owned();
+    gz_name.push(".gz");
+    let mut encoder = GzEncoder::new(File::create(&gz_name)?, Compression::default());
+    io::copy(&mut File::open(path)?, &mut encoder)?;
+    encoder.finish()?;
+    fs::remove_file(path)
+}
+
+// Keep only the `keep` newest rotated logs of `path`
+fn remove_old(path: &Path, keep: usize) -> io::Result<()> {
+    let (stem, extension) = stem_and_extension(path);
+    let prefix = format!("{}-", stem);
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut rotated: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|rotated| {
+            let name = rotated.file_name().unwrap_or_default().to_string_lossy();
+            name.starts_with(&prefix)
+                && (name.ends_with(&format!(".{}", extension)) || name.ends_with(&format!(".{}.gz", extension)))
+        })
+        .collect();
+    // The timestamps in the names sort by age, newest last
+    rotated.sort();
+    let excess = rotated.len().saturating_sub(keep);
+    for old in rotated.into_iter().take(excess) {
+        fs::remove_file(&old)?;
+        info!("Removed old log {}", old.display());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::DeviceState;
+    use uuid::Uuid;
+
+    fn legacy(line: &str) -> CheckRecord {
+        match LogEvent::parse(line) {
+            Some(LogEvent::Check(record)) => *record,
+            other => panic!("{:?} was read as {:?}", line, other),
+        }
+    }
+
+    #[test]
+    fn parses_baseline_lines() {
+        let record = legacy("2024-05-01 10:00:00 - Core Switch (10.0.0.1): Ping: OK, HTTP: OK, Bandwidth: 12.34 Mbps");
+        assert_eq!(record.timestamp.format("%Y-%m-%d %H:%M:%S").to_string(), "2024-05-01 10:00:00");
+        assert_eq!((record.device.as_str(), record.ip.as_str()), ("Core Switch", "10.0.0.1"));
+        assert_eq!((record.ping.as_str(), record.http.as_str()), ("OK", "OK"));
+        assert_eq!(record.bandwidth.map(|b| (b.in_mbps, b.out_mbps)), Some((12.34, None)));
+        assert_eq!(record.state, None);
+        assert_eq!(record.device_id, None);
+        assert!(record.checks.is_empty());
+
+        for (line, ping, http) in [
+            ("2024-05-01 10:00:05 - Core Switch (10.0.0.1): Ping: FAIL, HTTP: FAIL, Bandwidth: N/A", "FAIL", "FAIL"),
+            ("2024-05-01 10:00:05 - Printer (10.0.0.9): Ping: OK, HTTP: N/A, Bandwidth: N/A", "OK", "N/A"),
+            ("2024-05-01 10:00:05 - Web (Prod) (10.0.0.2): Ping: N/A, HTTP: FAIL, Bandwidth: N/A", "N/A", "FAIL"),
+        ] {
+            let record = legacy(line);
+            assert_eq!((record.ping.as_str(), record.http.as_str()), (ping, http), "{}", line);
+            assert!(record.bandwidth.is_none(), "{}", line);
+        }
+        assert_eq!(legacy("2024-05-01 10:00:05 - Web (Prod) (10.0.0.2): Ping: OK, HTTP: OK, Bandwidth: N/A").device, "Web (Prod)");
+    }
+
+    #[test]
+    fn parses_lines_without_bandwidth() {
+        let record = legacy("2024-05-01 10:00:00 - Core Switch (10.0.0.1): Ping: OK, HTTP: N/A");
+        assert_eq!((record.ping.as_str(), record.http.as_str()), ("OK", "N/A"));
+        assert!(record.bandwidth.is_none());
+    }
+
+    #[test]
+    fn parses_lines_with_states_timings_and_sensors() {
+        let record = legacy(
+            "2024-05-01 10:00:00 - Web (10.0.0.2): State: DOWN, Ping: DEGRADED, HTTP: OK, \
+             Bandwidth: In 1.50 Mbps / Out 0.25 Mbps, Latency: 12.50 ms, Jitter: 1.25 ms, Loss: 20%, \
+             HTTP Timing: DNS 1.00 ms / Connect 2.00 ms / TLS N/A / TTFB 3.00 ms / Total 4.00 ms / 512 B, \
+             Final URL: http://10.0.0.2/login, TCP 22: FAIL, HTTPS certificate: WARN, DNS: OK",
+        );
+        assert_eq!(record.state, Some(DeviceState::Down));
+        assert_eq!(record.ping, "DEGRADED");
+        assert_eq!(record.bandwidth.map(|b| (b.in_mbps, b.out_mbps)), Some((1.5, Some(0.25))));
+        assert_eq!((record.latency_ms, record.jitter_ms, record.loss_percent), (Some(12.5), Some(1.25), Some(20.0)));
+        let timing = record.http_timing.expect("timing");
+        assert_eq!((timing.dns_ms, timing.connect_ms, timing.tls_ms), (1.0, 2.0, None));
+        assert_eq!((timing.ttfb_ms, timing.total_ms, timing.size_bytes), (3.0, 4.0, 512));
+        assert_eq!((timing.final_url.as_str(), timing.redirects), ("http://10.0.0.2/login", 1));
+        let checks: Vec<_> = record.checks.iter().map(|check| (check.sensor.as_str(), check.status())).collect();
+        assert_eq!(checks, [("TCP 22", "FAIL"), ("HTTPS certificate", "WARN"), ("DNS", "OK")]);
+
+        let record = legacy("2024-05-01 10:00:00 - Web (10.0.0.2): State: N/A, Ping: N/A, HTTP: N/A, Bandwidth: N/A, Latency: N/A, Jitter: N/A, Loss: N/A");
+        assert_eq!(record.state, None);
+        assert_eq!((record.latency_ms, record.jitter_ms, record.loss_percent), (None, None, None));
+    }
+
+    #[test]
+    fn skips_lines_that_are_not_entries() {
+        for line in ["", "   ", "// Log started", "not a log line", "2024-05-01 10:00:00 - no address", "{not json"] {
+            assert!(LogEvent::parse(line).is_none(), "{:?} was read", line);
+        }
+    }
+
+    #[test]
+    fn json_lines_round_trip() {
+        let check = legacy(
+            "2024-05-01 10:00:00 - Web (10.0.0.2): State: UP, Ping: OK, HTTP: OK, Bandwidth: In 1.50 Mbps / Out 0.25 Mbps, \
+             Latency: 12.50 ms, Jitter: N/A, Loss: 0%, TCP 22: OK",
+        );
+        let change = StateChange {
+            timestamp: check.timestamp,
+            device_id: Some(Uuid::new_v4()),
+            device: "Web".to_string(),
+            ip: "10.0.0.2".to_string(),
+            category: "servers".to_string(),
+            sensor: Some("TCP 22".to_string()),
+            old_state: Some(DeviceState::Up),
+            new_state: DeviceState::Down,
+            duration_secs: Some(90.5),
+        };
+        for event in [LogEvent::Check(Box::new(check)), LogEvent::StateChange(Box::new(change))] {
+            let line = serde_json::to_string(&event).unwrap();
+            assert!(!line.contains('\n'));
+            let parsed = LogEvent::parse(&line).expect("round trip");
+            assert_eq!(serde_json::to_value(&parsed).unwrap(), serde_json::to_value(&event).unwrap());
+        }
+    }
+}
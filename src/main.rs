@@ -4,121 +4,102 @@ extern crate rocket;
 mod models;
 mod sensors;
 mod email;
+mod icmp;
+mod tls;
+mod http_client;
+mod snmp;
+mod config;
+mod monitor;
+mod maintenance;
+mod storage;
+mod log_file;
+mod api;
+mod metrics;
 
 use rocket::{get, post, delete, put, routes, State, response::Redirect, catch, catchers};
 use rocket::serde::json::Json;
 use rocket::fs::{NamedFile, FileServer, relative};
-use models::{Device as ModelDevice, SensorType};
+use models::{BandwidthSource, ChangeFilter, CheckRecord, CheckSettings, DeviceState, DeviceStatusReport, Device as ModelDevice, HttpOptions, LogFilter, Paging, SensorType, StateChange, StateSettings, User};
 use log::{info, error};
-use sensors::{monitor_ping, monitor_http};
-use std::fs::{self, OpenOptions};
-use std::io::{Read, Write};
+use sensors::monitor_ping;
+use std::fs;
 use serde_json::{self, json};
 use serde_json::from_str;
 use std::path::Path;
 use tokio::time::{sleep, Duration};
-use rand::Rng;
 use std::sync::Arc;
+use std::collections::{BTreeMap, HashMap};
 use tokio::sync::Mutex;
-use std::collections::HashMap;
-use chrono::{NaiveDate, Local, DateTime};
 use rocket::response::content::RawText;
-use rocket::http::{Status};
+use rocket::response::stream::{Event, EventStream};
+use rocket::Shutdown;
+use tokio::sync::broadcast::{self, error::RecvError};
+use log_file::LogEvent;
+use rocket::http::{ContentType, Status};
 use rocket::request::{self, Request, FromRequest};
 use rocket::outcome::Outcome;
 use serde::Deserialize;
 use rocket::serde::Serialize;
 use email::EmailService;
+use config::MonitorConfig;
+use maintenance::{MaintenanceService, MaintenanceWindow};
+use metrics::Metrics;
+use storage::Storage;
 use std::sync::atomic::{AtomicPtr, Ordering};
 use serde_json::Value;
-use rocket::http::ContentType;
+use uuid::Uuid;
 
 static AUTH_CONFIG: AtomicPtr<serde_json::Value> = AtomicPtr::new(std::ptr::null_mut());
 
 fn init_auth_config() {
-    if let Ok(content) = fs::read_to_string("static/config.js") {
-        if let Some(config_str) = content.strip_prefix("const AUTH_CONFIG = ") {
-            if let Ok(config) = serde_json::from_str::<serde_json::Value>(config_str) {
-                let config_ptr = Box::into_raw(Box::new(config));
-                let old_ptr = AUTH_CONFIG.swap(config_ptr, Ordering::SeqCst);
-                if !old_ptr.is_null() {
-                    unsafe {
-                        drop(Box::from_raw(old_ptr));
-                    }
-                }
-            }
-        }
+    if let Some(config) = read_auth_config() {
+        set_auth_config(config);
     }
 }
 
-async fn process_logs(start_date_parsed: Option<NaiveDate>, end_date_parsed: Option<NaiveDate>) -> Vec<String> {
-    let mut filtered_logs = Vec::new();
-    if let Ok(contents) = fs::read_to_string(LOG_FILE) {
-        for line in contents.lines() {
-            if let Some((timestamp, rest)) = line.split_once(" - ") {
-                let date_str = &timestamp[..10]; 
-                if let Ok(entry_date) = NaiveDate::parse_from_str(date_str, "%Y-%m-%d") {
-                    let mut include = true;
-                    
-                    if let Some(start) = start_date_parsed {
-                        if entry_date < start {
-                            include = false;
-                        }
-                    }
-                    if let Some(end) = end_date_parsed {
-                        if entry_date > end {
-                            include = false;
-                        }
-                    }
-                    if include {
-                        filtered_logs.push(format!("{} - {}", timestamp, rest));
-                    }
-                }
-            }
+fn set_auth_config(config: Value) {
+    let config_ptr = Box::into_raw(Box::new(config));
+    let old_ptr = AUTH_CONFIG.swap(config_ptr, Ordering::SeqCst);
+    if !old_ptr.is_null() {
+        unsafe {
+            drop(Box::from_raw(old_ptr));
         }
     }
-    filtered_logs
 }
 
-// Define a struct to track device status.
-#[derive(Debug, Clone)]
-struct DeviceStatus {
-    ping_status: Option<bool>,
-    http_status: Option<bool>,
-    bandwidth_usage: Option<f64>,
-    last_update: DateTime<Local>,
-    changed_at: DateTime<Local>,
+fn read_auth_config() -> Option<Value> {
+    let content = fs::read_to_string("static/config.js").ok()?;
+    let config_str = content.strip_prefix("const AUTH_CONFIG = ")?;
+    serde_json::from_str(config_str.trim_end().trim_end_matches(';')).ok()
 }
 
-impl DeviceStatus {
-    fn new() -> Self {
-        let now = Local::now();
-        Self {
-            ping_status: None,
-            http_status: None,
-            bandwidth_usage: None,
-            last_update: now,
-            changed_at: now,
+// Keep the stored users in line with the ones in static/config.js
+async fn save_users_from_config(storage: &dyn Storage, config: &Value) {
+    let Some(users) = config.get("users") else { return };
+    match serde_json::from_value::<Vec<User>>(users.clone()) {
+        Ok(users) => {
+            if let Err(e) = storage.save_users(&users).await {
+                error!("Failed to store users: {:#}", e);
+            }
         }
+        Err(e) => error!("Invalid users in config.js: {}", e),
     }
+}
 
-    fn update_ping(&mut self, new_status: bool) -> bool {
-        let changed = self.ping_status != Some(new_status);
-        if changed {
-            self.ping_status = Some(new_status);
-            self.changed_at = Local::now();
-        }
-        self.last_update = Local::now();
-        changed
-    }
+// Replace the users, in static/config.js for the login page as well as in storage
+async fn replace_users(storage: &dyn Storage, users: &[User]) -> anyhow::Result<()> {
+    let config = json!({ "users": users });
+    fs::write("static/config.js", format!("const AUTH_CONFIG = {};", serde_json::to_string_pretty(&config)?))?;
+    set_auth_config(config);
+    storage.save_users(users).await
 }
 
-// Use the new DeviceStatus struct in the type alias.
-type DeviceStatusMap = Arc<Mutex<HashMap<String, DeviceStatus>>>;
 type SharedDevices = Arc<Mutex<Vec<ModelDevice>>>;
-type DeviceList = Arc<Mutex<Vec<WebDevice>>>;
 
 static LOG_FILE: &str = "rustPing_running.log";
+const DEVICES_FILE: &str = "devices.json";
+// Events kept for event stream clients that fall behind
+const EVENT_BUFFER: usize = 1024;
 
 // Redirect / to /static/index.html (this is now optional, but good for clarity)
 #[get("/")]
@@ -136,8 +117,9 @@ async fn login_page() -> Option<NamedFile> {
 async fn add_model_device(device: Json<ModelDevice>, devices: &State<SharedDevices>) -> &'static str {
     let mut dev = device.into_inner();
     if dev.sensors.contains(&SensorType::Ping) {
-        let status = monitor_ping(&dev.ip).await;
-        dev.ping_status = Some(status);
+        let result = monitor_ping(&dev.ip, &dev.settings_for(&SensorType::Ping)).await;
+        dev.ping_status = Some(result.is_up());
+        dev.ping_result = Some(result);
     }
     let mut devices_locked = devices.lock().await;
     devices_locked.push(dev);
@@ -151,188 +133,244 @@ async fn get_devices(_auth: Auth, devices: &State<SharedDevices>) -> Json<Vec<Mo
     Json(devices_locked.clone())
 }
 
+/// Live state of every device and sensor, by device id.
+#[get("/api/devices/status")]
+async fn get_device_statuses(_auth: Auth, devices: &State<SharedDevices>) -> Json<BTreeMap<Uuid, DeviceStatusReport>> {
+    let now = chrono::Local::now();
+    let devices_locked = devices.lock().await;
+    Json(devices_locked.iter().map(|device| (device.id, device.status_report(now))).collect())
+}
+
+// The Authorization header of a request, if it has one
+struct Authorization<'r>(Option<&'r str>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Authorization<'r> {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        Outcome::Success(Authorization(request.headers().get_one("Authorization")))
+    }
+}
+
+/// Device and sensor gauges and the monitor's counters, for Prometheus to scrape.
+#[get("/metrics")]
+async fn get_metrics(
+    authorization: Authorization<'_>,
+    metrics: &State<Arc<Metrics>>,
+    devices: &State<SharedDevices>,
+) -> Result<(ContentType, String), (Status, &'static str)> {
+    if !metrics.allows(authorization.0) {
+        return Err((Status::Unauthorized, "Missing or wrong bearer token\n"));
+    }
+    let text = metrics.render(&devices.lock().await);
+    Ok((ContentType::new("text", "plain").with_params([("version", "0.0.4"), ("charset", "utf-8")]), text))
+}
+
 /// Export logs filtered by (optional) date range and device names.
 #[get("/export_log?<devices>&<start_date>&<end_date>&<format>")]
 async fn export_log(
     devices: Option<&str>,
     start_date: Option<&str>,
     end_date: Option<&str>,
-    format: Option<&str>
+    format: Option<&str>,
+    storage: &State<Arc<dyn Storage>>,
 ) -> RawText<String> {
-    // Read the entire log file.
-    let mut file_content = String::new();
-    if let Ok(mut file) = OpenOptions::new().read(true).open(LOG_FILE) {
-        if let Err(e) = file.read_to_string(&mut file_content) {
-            error!("Failed to read log file: {}", e);
-            return RawText("Failed to read log file".to_string());
-        }
-    } else {
-        return RawText("Log file not found".to_string());
-    }
-    
-    let lines: Vec<&str> = file_content.lines().collect();
-    let mut filtered_lines = Vec::new();
-
-    // Parse optional date filters.
-    let start_date_parsed: Option<NaiveDate> =
-        start_date.and_then(|s| NaiveDate::parse_from_str(s, "%Y-%m-%d").ok());
-    let end_date_parsed: Option<NaiveDate> =
-        end_date.and_then(|s| NaiveDate::parse_from_str(s, "%Y-%m-%d").ok());
-
-    // Split and trim device filter names (which could be names or IP addresses).
-    let device_filters: Option<Vec<String>> = devices.map(|d| {
-        d.split(',')
-         .map(|s| s.trim().to_lowercase())
-         .collect()
-    });
-    
-    // Process each log line.
-    for line in lines {
-        // Retain lines starting with "//" (e.g. header lines).
-        if line.starts_with("//") {
-            filtered_lines.push(line);
-            continue;
-        }
-        if let Some((timestamp, rest)) = line.split_once(" - ") {
-            // Extract device name and IP.
-            // Assuming rest is like: "Device: Network Switch, 192.168.0.100, Ping: FAIL, HTTP: N/A, Bandwidth: N/A"
-            let parts: Vec<&str> = rest.split(',').collect();
-            if parts.len() >= 2 {
-                let device_part = parts[0].trim(); // "Device: Network Switch"
-                let ip_part = parts[1].trim();       // "192.168.0.100"
-                let device_name = device_part.trim_start_matches("Device:").trim().to_lowercase();
-                let device_ip = ip_part.to_lowercase();
-                
-                // Filter by date.
-                let mut include = true;
-                // Extract the date part from timestamp (first 10 characters)
-                let date_str = &timestamp[..10];
-                if let Ok(entry_date) = NaiveDate::parse_from_str(date_str, "%Y-%m-%d") {
-                    if let Some(start) = start_date_parsed {
-                        if entry_date < start {
-                            include = false;
-                        }
-                    }
-                    if let Some(end) = end_date_parsed {
-                        if entry_date > end {
-                            include = false;
-                        }
-                    }
-                }
-                
-                // Apply device/IP filters if provided.
-                if let Some(ref filters) = device_filters {
-                    let mut found = false;
-                    for f in filters {
-                        if device_name.contains(f) || device_ip.contains(f) {
-                            found = true;
-                            break;
-                        }
-                    }
-                    if !found {
-                        include = false;
-                    }
-                }
-
-                if include {
-                    filtered_lines.push(line);
-                }
-            }
-        }
-    }
+    let filter = LogFilter::from_query(devices, start_date, end_date);
+    let records = match read_check_records(storage.as_ref(), &filter).await {
+        Ok(records) => records,
+        Err(e) => return RawText(e.to_string()),
+    };
     
     // Output formatting
-    let output = if let Some(fmt) = format {
-        if fmt.to_lowercase() == "csv" {
-            // CSV export implementation (e.g., create CSV lines)
-            let mut csv_lines = vec!["Timestamp,Device Name,IP Address,Ping,HTTP,Bandwidth".to_string()];
-            for line in filtered_lines {
-                if line.starts_with("//") { continue; }
-                if let Some((timestamp, rest)) = line.split_once(" - ") {
-                    // Extract device name, IP, and statuses.
-                    let parts: Vec<&str> = rest.split(',').collect();
-                    if parts.len() >= 2 {
-                        let device_name = parts[0].trim().trim_start_matches("Device:").trim();
-                        let device_ip = parts[1].trim();
-                        let ping = parts.get(2)
-                            .map(|s| s.replace("Ping:", "").trim().to_string())
-                            .unwrap_or_else(|| "N/A".to_string());
-                        let http = parts.get(3)
-                            .map(|s| s.replace("HTTP:", "").trim().to_string())
-                            .unwrap_or_else(|| "N/A".to_string());
-                        let bandwidth = parts.get(4)
-                            .map(|s| s.replace("Bandwidth:", "").trim().to_string())
-                            .unwrap_or_else(|| "N/A".to_string());
-                        let csv_line = format!("{},{},{},{},{},{}", timestamp, device_name, device_ip, ping, http, bandwidth);
-                        csv_lines.push(csv_line);
-                    }
-                }
-            }
-            csv_lines.join("\n")
-        } else {
-            // Plain text output.
-            filtered_lines.join("\n")
+    let output = if format.is_some_and(|fmt| fmt.eq_ignore_ascii_case("csv")) {
+        let mut csv_lines = vec!["Timestamp,Device ID,Device Name,IP Address,State,Ping,HTTP,Bandwidth,Latency,Jitter,Loss".to_string()];
+        for record in records.iter() {
+            let fields = [
+                record.timestamp.format("%Y-%m-%d %H:%M:%S").to_string(),
+                record.device_id.map(|id| id.to_string()).unwrap_or_default(),
+                record.device.clone(),
+                record.ip.clone(),
+                record.state_text(),
+                record.ping.clone(),
+                record.http.clone(),
+                record.bandwidth_text(),
+                record.latency_text(),
+                record.jitter_text(),
+                record.loss_text(),
+            ];
+            csv_lines.push(fields.iter().map(|field| csv_field(field)).collect::<Vec<_>>().join(","));
         }
+        csv_lines.join("\n")
     } else {
-        filtered_lines.join("\n")
+        // Plain text output.
+        records.iter().map(|record| record.to_string()).collect::<Vec<_>>().join("\n")
     };
 
     RawText(output)
 }
 
-/// New endpoint to expose logs as JSON.
-#[get("/logs_json")]
-async fn logs_json() -> Json<serde_json::Value> {
-    let mut file_content = String::new();
-    if let Ok(mut file) = OpenOptions::new().read(true).open(LOG_FILE) {
-        if let Err(e) = file.read_to_string(&mut file_content) {
-            error!("Failed to read log file: {}", e);
-            return Json(json!({"error": "Failed to read log file"}));
-        }
+// Quoted when it holds a comma, quote or line break, e.g. a device name like "Switch, Floor 2"
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
     } else {
-        return Json(json!({"error": "Log file not found"}));
+        value.to_string()
     }
-    let lines: Vec<&str> = file_content.lines().collect();
-    let mut entries = Vec::new();
-    for line in lines {
-        if line.starts_with("//") || line.trim().is_empty() {
-            continue;
+}
+
+/// Logs as JSON, optionally filtered by date range and device names.
+#[get("/logs_json?<devices>&<start_date>&<end_date>")]
+async fn logs_json(
+    devices: Option<&str>,
+    start_date: Option<&str>,
+    end_date: Option<&str>,
+    storage: &State<Arc<dyn Storage>>,
+) -> Json<serde_json::Value> {
+    let filter = LogFilter::from_query(devices, start_date, end_date);
+    match read_check_records(storage.as_ref(), &filter).await {
+        Ok(records) => Json(json!(records.iter().map(log_entry_json).collect::<Vec<_>>())),
+        Err(e) => Json(json!({"error": e})),
+    }
+}
+
+// A check result as the log pages expect it
+fn log_entry_json(record: &CheckRecord) -> Value {
+    let checks: serde_json::Map<String, Value> = record.checks.iter()
+        .map(|check| (check.sensor.clone(), json!(check.status())))
+        .collect();
+    json!({
+        "timestamp": record.timestamp.format("%Y-%m-%d %H:%M:%S").to_string(),
+        "date": record.timestamp.format("%Y-%m-%d").to_string(),
+        "time": record.timestamp.format("%H:%M:%S").to_string(),
+        "device": format!("{} ({})", record.device, record.ip),
+        "device_id": record.device_id,
+        "name": record.device,
+        "ip": record.ip,
+        "category": record.category,
+        "state": record.state.map(|state| state.to_string()).unwrap_or_default(),
+        "ping": record.ping,
+        "http": record.http,
+        "bandwidth": record.bandwidth_text(),
+        "latency": record.latency_text(),
+        "jitter": record.jitter_text(),
+        "loss": record.loss_text(),
+        "http_timing": record.http_timing_text().unwrap_or_default(),
+        "final_url": record.http_timing.as_ref().filter(|t| t.redirects > 0).map(|t| t.final_url.clone()).unwrap_or_default(),
+        "checks": checks,
+        "down": record.is_down(),
+        "degraded": record.is_degraded(),
+        "maintenance": record.state == Some(DeviceState::Maintenance)
+    })
+}
+
+/// State changes of devices and their sensors, optionally filtered by date range and device names.
+#[get("/api/events?<devices>&<start_date>&<end_date>")]
+async fn get_events(
+    _auth: Auth,
+    devices: Option<&str>,
+    start_date: Option<&str>,
+    end_date: Option<&str>,
+    storage: &State<Arc<dyn Storage>>,
+) -> Result<Json<Vec<StateChange>>, (Status, Json<Value>)> {
+    let filter = LogFilter::from_query(devices, start_date, end_date);
+    read_state_changes(storage.as_ref(), &filter).await
+        .map(Json)
+        .map_err(|message| (Status::InternalServerError, Json(json!({ "status": "error", "message": message }))))
+}
+
+/// Check results and state changes as they happen, as server-sent events named `check`,
+/// shaped like the entries of `/logs_json`, and `state_change`, like those of `/api/events`.
+#[get("/api/events/stream")]
+fn event_stream(_auth: Auth, events: &State<broadcast::Sender<LogEvent>>, mut shutdown: Shutdown) -> EventStream![] {
+    let mut events = events.subscribe();
+    EventStream! {
+        loop {
+            let event = tokio::select! {
+                event = events.recv() => match event {
+                    Ok(event) => event,
+                    Err(RecvError::Closed) => break,
+                    // A slow client misses some events rather than holding up the monitor
+                    Err(RecvError::Lagged(_)) => continue,
+                },
+                _ = &mut shutdown => break,
+            };
+            yield match event {
+                LogEvent::Check(record) => Event::json(&log_entry_json(&record)).event("check"),
+                LogEvent::StateChange(change) => Event::json(&change).event("state_change"),
+            };
         }
-        if let Some((timestamp, rest)) = line.split_once(" - ") {
-            let parts: Vec<&str> = rest.splitn(2, ": ").collect();
-            if parts.len() < 2 { continue; }
-            let device = parts[0].trim();
-            let statuses = parts[1];
-            let mut ping = "";
-            let mut http = "";
-            let mut bandwidth = "";
-            for status in statuses.split(", ") {
-                if status.starts_with("Ping:") {
-                    ping = status.trim_start_matches("Ping:").trim();
-                } else if status.starts_with("HTTP:") {
-                    http = status.trim_start_matches("HTTP:").trim();
-                } else if status.starts_with("Bandwidth:") {
-                    bandwidth = status.trim_start_matches("Bandwidth:").trim();
-                }
-            }
-            let down = ping.to_lowercase() == "fail";
-            // Split timestamp into date and time.
-            let ts_parts: Vec<&str> = timestamp.split(' ').collect();
-            let date = ts_parts.get(0).unwrap_or(&"").to_string();
-            let time = ts_parts.get(1).unwrap_or(&"").to_string();
-            entries.push(json!({
-                "timestamp": timestamp,
-                "date": date,
-                "time": time,
-                "device": device,
-                "ping": ping,
-                "http": http,
-                "bandwidth": bandwidth,
-                "down": down
-            }));
-        }
-    }
-    Json(json!(entries))
+    }
+}
+
+// Check results passing `filter`, from the database unless it can't be reached
+async fn read_check_records(storage: &dyn Storage, filter: &LogFilter) -> Result<Vec<CheckRecord>, &'static str> {
+    match storage.check_records(filter).await {
+        Ok(records) => return Ok(records),
+        Err(e) => error!("Failed to query the database, reading the log file instead: {:#}", e),
+    }
+    read_logged_checks(filter)
+}
+
+// One page of the check results passing `filter` and how many there are in all
+async fn read_check_records_page(
+    storage: &dyn Storage,
+    filter: &LogFilter,
+    paging: Paging,
+) -> Result<(Vec<CheckRecord>, usize), &'static str> {
+    match storage.check_records_page(filter, paging).await {
+        Ok(page) => return Ok(page),
+        Err(e) => error!("Failed to query the database, reading the log file instead: {:#}", e),
+    }
+    let records = read_logged_checks(filter)?;
+    let total = records.len();
+    Ok((paging.apply(records), total))
+}
+
+fn read_logged_checks(filter: &LogFilter) -> Result<Vec<CheckRecord>, &'static str> {
+    match log_file::read_checks(LOG_FILE, filter) {
+        Ok(records) => Ok(records),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Err("Log file not found"),
+        Err(e) => {
+            error!("Failed to read log file: {}", e);
+            Err("Failed to read log file")
+        }
+    }
+}
+
+// State changes passing `filter`, from the database unless it can't be reached
+async fn read_state_changes(storage: &dyn Storage, filter: &LogFilter) -> Result<Vec<StateChange>, &'static str> {
+    match storage.state_changes(filter).await {
+        Ok(changes) => return Ok(changes),
+        Err(e) => error!("Failed to query the database, reading the log file instead: {:#}", e),
+    }
+    read_logged_state_changes(filter)
+}
+
+// One page of the state changes passing both filters and how many there are in all
+async fn read_state_changes_page(
+    storage: &dyn Storage,
+    filter: &LogFilter,
+    changes: &ChangeFilter,
+    paging: Paging,
+) -> Result<(Vec<StateChange>, usize), &'static str> {
+    match storage.state_changes_page(filter, changes, paging).await {
+        Ok(page) => return Ok(page),
+        Err(e) => error!("Failed to query the database, reading the log file instead: {:#}", e),
+    }
+    let logged: Vec<StateChange> = read_logged_state_changes(filter)?
+        .into_iter()
+        .filter(|change| changes.matches(change))
+        .collect();
+    let total = logged.len();
+    Ok((paging.apply(logged), total))
+}
+
+fn read_logged_state_changes(filter: &LogFilter) -> Result<Vec<StateChange>, &'static str> {
+    log_file::read_state_changes(LOG_FILE, filter).map_err(|e| {
+        error!("Failed to read log file: {}", e);
+        "Failed to read state changes"
+    })
 }
 
 // Load devices from a JSON file.
@@ -341,8 +379,15 @@ async fn add_devices_from_file(file_path: &str, devices: SharedDevices) {
     let mut file_devices: Vec<ModelDevice> = from_str(&data).expect("JSON was not well-formatted");
     for dev in file_devices.iter_mut() {
         dev.ping_status = None;
+        dev.ping_result = None;
         dev.bandwidth_usage = None;
+        dev.bandwidth_rates = None;
         dev.http_status = None;
+        dev.http_result = None;
+        dev.http_timing = None;
+        dev.state = None;
+        dev.maintenance = None;
+        dev.checks.clear();
     }
     let mut devices_locked = devices.lock().await;
     devices_locked.append(&mut file_devices);
@@ -357,16 +402,10 @@ async fn failed_logs() -> Option<NamedFile> {
 // Add this struct for authentication
 struct Auth;
 
-#[derive(Debug)]
-enum AuthError {
-    Missing,
-    Invalid,
-}
-
 // Implement request guard for Auth
 #[rocket::async_trait]
 impl<'r> FromRequest<'r> for Auth {
-    type Error = AuthError;
+    type Error = ();
 
     async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
         let cookies = request.cookies();
@@ -416,56 +455,122 @@ struct PasswordUpdate {
 }
 
 #[post("/update-password", data = "<update>")]
-async fn update_password(_auth: Auth, update: Json<PasswordUpdate>) -> Status {
-    // Update the password hash in config.js
-    let config_path = Path::new("static/config.js");
-    let config_content = format!(
-        "const AUTH_CONFIG = {{\n    username: 'admin',\n    passwordHash: '{}'\n}};",
-        update.hash
-    );
-    
-    match fs::write(config_path, config_content) {
-        Ok(_) => Status::Ok,
-        Err(_) => Status::InternalServerError,
+async fn update_password(_auth: Auth, update: Json<PasswordUpdate>, storage: &State<Arc<dyn Storage>>) -> Status {
+    // The admin's password, keeping the other users in config.js
+    let mut users = match storage.users().await {
+        Ok(users) => users,
+        Err(e) => {
+            error!("Failed to read users: {:#}", e);
+            return Status::InternalServerError;
+        }
+    };
+    match users.iter_mut().find(|user| user.username == "admin") {
+        Some(admin) => admin.password_hash = update.hash.clone(),
+        None => users.push(User {
+            username: "admin".to_string(),
+            password_hash: update.hash.clone(),
+            role: "admin".to_string(),
+        }),
+    }
+    match replace_users(storage.as_ref(), &users).await {
+        Ok(()) => Status::Ok,
+        Err(e) => {
+            error!("Failed to save users: {:#}", e);
+            Status::InternalServerError
+        }
     }
 }
 
 // Web Device struct - this is separate from the model Device
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, utoipa::ToSchema)]
+#[schema(as = DeviceSettings)]
 struct WebDevice {
+    #[serde(default = "Uuid::new_v4")]
+    id: Uuid,
     name: String,
     ip: String,
     category: String,
-    sensors: Vec<String>,
+    sensors: Vec<SensorType>,
     #[serde(skip_serializing_if = "Option::is_none")]
     http_path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    http: Option<HttpOptions>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    bandwidth: Option<BandwidthSource>,
+    #[serde(flatten)]
+    settings: CheckSettings,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    sensor_settings: BTreeMap<String, CheckSettings>,
+    #[serde(flatten)]
+    state_settings: StateSettings,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    depends_on: Vec<Uuid>,
+}
+
+// Why a device couldn't be added, changed or removed
+#[derive(Debug)]
+enum DeviceError {
+    // With the message from validate_device
+    Invalid(&'static str),
+    Conflict(&'static str),
+    NotFound,
+    // Reading or writing devices.json
+    File(String),
+}
+
+impl DeviceError {
+    fn status(&self) -> Status {
+        match self {
+            DeviceError::Invalid(_) => Status::BadRequest,
+            DeviceError::Conflict(_) => Status::Conflict,
+            DeviceError::NotFound => Status::NotFound,
+            DeviceError::File(_) => Status::InternalServerError,
+        }
+    }
+}
+
+impl std::fmt::Display for DeviceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeviceError::Invalid(message) | DeviceError::Conflict(message) => write!(f, "{}", message),
+            DeviceError::NotFound => write!(f, "No device with this id"),
+            DeviceError::File(message) => write!(f, "{}", message),
+        }
+    }
 }
 
 // Add this validation function
-fn validate_device(device: &WebDevice, devices: &[WebDevice], exclude_index: Option<usize>) -> Result<(), &'static str> {
+fn validate_device(device: &WebDevice, devices: &[WebDevice]) -> Result<(), DeviceError> {
     // Check for empty or invalid values
     if device.name.trim().is_empty() {
-        return Err("Device name cannot be empty");
+        return Err(DeviceError::Invalid("Device name cannot be empty"));
     }
     if device.ip.trim().is_empty() {
-        return Err("IP address cannot be empty");
+        return Err(DeviceError::Invalid("IP address cannot be empty"));
     }
     if device.category.trim().is_empty() {
-        return Err("Category cannot be empty");
+        return Err(DeviceError::Invalid("Category cannot be empty"));
     }
     if device.sensors.is_empty() {
-        return Err("At least one sensor must be selected");
+        return Err(DeviceError::Invalid("At least one sensor must be selected"));
     }
 
-    // Check for duplicates (excluding the current device if updating)
-    for (index, existing) in devices.iter().enumerate() {
-        if let Some(exclude) = exclude_index {
-            if index == exclude {
-                continue;
-            }
+    // Check for duplicates (excluding the current device if updating). Several devices may share an IP.
+    for existing in devices.iter() {
+        if existing.id == device.id {
+            continue;
         }
         if existing.name == device.name {
-            return Err("Device name already exists");
+            return Err(DeviceError::Conflict("Device name already exists"));
+        }
+    }
+
+    for parent in device.depends_on.iter() {
+        if *parent == device.id {
+            return Err(DeviceError::Invalid("A device cannot depend on itself"));
+        }
+        if !devices.iter().any(|d| d.id == *parent) {
+            return Err(DeviceError::Invalid("depends_on lists a device id that does not exist"));
         }
     }
 
@@ -476,132 +581,126 @@ fn validate_device(device: &WebDevice, devices: &[WebDevice], exclude_index: Opt
 impl From<WebDevice> for ModelDevice {
     fn from(web_device: WebDevice) -> Self {
         ModelDevice {
+            id: web_device.id,
             name: web_device.name,
             ip: web_device.ip,
             category: web_device.category,
-            sensors: web_device.sensors.iter()
-                .map(|s| match s.as_str() {
-                    "Ping" => SensorType::Ping,
-                    "Http" => SensorType::Http,
-                    "Https" => SensorType::Https,
-                    _ => SensorType::Ping
-                })
-                .collect(),
+            sensors: web_device.sensors,
             http_path: web_device.http_path,
+            http: web_device.http,
             ping_status: None,
+            ping_result: None,
             http_status: None,
+            http_result: None,
+            http_timing: None,
+            bandwidth: web_device.bandwidth,
             bandwidth_usage: None,
+            bandwidth_rates: None,
+            checks: Vec::new(),
+            settings: web_device.settings,
+            sensor_settings: web_device.sensor_settings,
+            state: None,
+            maintenance: None,
+            state_since: None,
+            sensor_since: BTreeMap::new(),
+            checked_at: BTreeMap::new(),
+            state_settings: web_device.state_settings,
+            depends_on: web_device.depends_on,
         }
     }
 }
 
-// Then modify the add_web_device function to use the conversion
-#[post("/devices", data = "<device>")]
-async fn add_web_device(device: &str, devices: &State<SharedDevices>) -> Status {
-    let new_device: WebDevice = match serde_json::from_str(device) {
-        Ok(dev) => dev,
-        Err(e) => {
-            error!("Failed to parse device JSON: {}", e);
-            return Status::BadRequest;
-        }
-    };
+fn read_devices_file(file_path: &str) -> Result<Vec<WebDevice>, DeviceError> {
+    let content = fs::read_to_string(file_path)
+        .map_err(|e| DeviceError::File(format!("Failed to read {}: {}", file_path, e)))?;
+    serde_json::from_str(&content).map_err(|e| DeviceError::File(format!("Failed to parse {}: {}", file_path, e)))
+}
 
-    // Read and validate against existing devices
-    let file_path = "devices.json";
-    let content = match fs::read_to_string(file_path) {
-        Ok(content) => content,
-        Err(e) => {
-            error!("Failed to read devices.json: {}", e);
-            return Status::InternalServerError;
-        }
-    };
+// Add a device to the file and start monitoring it, under an id of its own whatever was sent
+async fn create_device(mut device: WebDevice, devices: &SharedDevices) -> Result<WebDevice, DeviceError> {
+    let mut file_devices = read_devices_file(DEVICES_FILE)?;
+    device.id = Uuid::new_v4();
+    validate_device(&device, &file_devices)?;
+    file_devices.push(device.clone());
+    write_devices_file(DEVICES_FILE, &file_devices)
+        .map_err(|e| DeviceError::File(format!("Failed to write {}: {}", DEVICES_FILE, e)))?;
+
+    devices.lock().await.push(ModelDevice::from(device.clone()));
+    Ok(device)
+}
 
-    let mut file_devices: Vec<WebDevice> = match serde_json::from_str(&content) {
-        Ok(devices) => devices,
-        Err(e) => {
-            error!("Failed to parse devices.json: {}", e);
-            return Status::InternalServerError;
-        }
-    };
+// Replace the settings of the device with this id, keeping its latest results
+async fn replace_device(id: Uuid, mut device: WebDevice, devices: &SharedDevices) -> Result<WebDevice, DeviceError> {
+    let mut file_devices = read_devices_file(DEVICES_FILE)?;
+    let index = file_devices.iter().position(|d| d.id == id).ok_or(DeviceError::NotFound)?;
+    device.id = id;
+    validate_device(&device, &file_devices)?;
+    file_devices[index] = device.clone();
+    write_devices_file(DEVICES_FILE, &file_devices)
+        .map_err(|e| DeviceError::File(format!("Failed to write {}: {}", DEVICES_FILE, e)))?;
 
-    // Validate new device
-    if let Err(e) = validate_device(&new_device, &file_devices, None) {
-        error!("Validation failed: {}", e);
-        return Status::BadRequest;
+    let mut devices_locked = devices.lock().await;
+    if let Some(existing) = devices_locked.iter_mut().find(|d| d.id == id) {
+        let mut updated = ModelDevice::from(device.clone());
+        updated.carry_over_from(existing);
+        *existing = updated;
     }
+    Ok(device)
+}
 
-    // Add device and write to file atomically
-    file_devices.push(new_device.clone());
-    if let Ok(json) = serde_json::to_string_pretty(&file_devices) {
-        let temp_path = format!("{}.tmp", file_path);
-        if let Err(e) = fs::write(&temp_path, &json) {
-            error!("Failed to write temporary file: {}", e);
-            return Status::InternalServerError;
-        }
-        if let Err(e) = fs::rename(&temp_path, file_path) {
-            error!("Failed to rename temporary file: {}", e);
-            return Status::InternalServerError;
-        }
-    } else {
-        return Status::InternalServerError;
+// Remove the device with this id from the file, and stop monitoring it right away
+// rather than at the next reload. Devices depending on it have to drop it first.
+async fn remove_device(id: Uuid, devices: &SharedDevices) -> Result<(), DeviceError> {
+    let mut file_devices = read_devices_file(DEVICES_FILE)?;
+    let index = file_devices.iter().position(|d| d.id == id).ok_or(DeviceError::NotFound)?;
+    if file_devices.iter().any(|d| d.depends_on.contains(&id)) {
+        return Err(DeviceError::Conflict("Other devices depend on this device"));
     }
+    file_devices.remove(index);
+    write_devices_file(DEVICES_FILE, &file_devices)
+        .map_err(|e| DeviceError::File(format!("Failed to write {}: {}", DEVICES_FILE, e)))?;
 
-    // Update in-memory state using From trait
-    let mut devices_locked = devices.lock().await;
-    devices_locked.push(ModelDevice::from(new_device.clone()));
-
-    Status::Ok
+    devices.lock().await.retain(|d| d.id != id);
+    Ok(())
 }
 
-#[delete("/devices/<index>")]
-async fn delete_web_device(index: usize) -> Status {
-    let file_path = "devices.json";
-    
-    // Read existing devices
-    let content = match fs::read_to_string(file_path) {
-        Ok(content) => content,
+#[post("/devices", data = "<device>")]
+async fn add_web_device(device: &str, devices: &State<SharedDevices>) -> Status {
+    let new_device: WebDevice = match serde_json::from_str(device) {
+        Ok(dev) => dev,
         Err(e) => {
-            error!("Failed to read devices.json: {}", e);
-            return Status::InternalServerError;
+            error!("Failed to parse device JSON: {}", e);
+            return Status::BadRequest;
         }
     };
-    
-    let mut devices: Vec<WebDevice> = match serde_json::from_str(&content) {
-        Ok(devices) => devices,
+    match create_device(new_device, devices).await {
+        Ok(_) => Status::Ok,
         Err(e) => {
-            error!("Failed to parse devices.json: {}", e);
-            return Status::InternalServerError;
+            error!("Failed to add device: {}", e);
+            e.status()
         }
-    };
-    
-    // Check if index is valid
-    if index >= devices.len() {
-        return Status::NotFound;
     }
-    
-    // Remove device at the specified index
-    devices.remove(index);
-    
-    // Write updated devices back to file
-    match serde_json::to_string_pretty(&devices) {
-        Ok(json) => {
-            if let Err(e) = fs::write(file_path, json) {
-                error!("Failed to write devices.json: {}", e);
-                return Status::InternalServerError;
-            }
-        },
+}
+
+#[delete("/devices/<id>")]
+async fn delete_web_device(id: &str, devices: &State<SharedDevices>) -> Status {
+    let Ok(id) = id.parse::<Uuid>() else {
+        return Status::NotFound;
+    };
+    match remove_device(id, devices).await {
+        Ok(()) => Status::Ok,
         Err(e) => {
-            error!("Failed to serialize devices: {}", e);
-            return Status::InternalServerError;
+            error!("Failed to remove device: {}", e);
+            e.status()
         }
     }
-    
-    Status::Ok
 }
 
-// Update the update_device endpoint
 #[put("/devices/<id>", data = "<device>")]
-async fn update_device(id: usize, device: &str, devices: &State<SharedDevices>) -> Status {
+async fn update_device(id: &str, device: &str, devices: &State<SharedDevices>) -> Status {
+    let Ok(id) = id.parse::<Uuid>() else {
+        return Status::NotFound;
+    };
     let updated_device: WebDevice = match serde_json::from_str(device) {
         Ok(dev) => dev,
         Err(e) => {
@@ -609,84 +708,99 @@ async fn update_device(id: usize, device: &str, devices: &State<SharedDevices>)
             return Status::BadRequest;
         }
     };
-
-    // Read current devices from file
-    let file_path = "devices.json";
-    let content = match fs::read_to_string(file_path) {
-        Ok(content) => content,
+    match replace_device(id, updated_device, devices).await {
+        Ok(_) => Status::Ok,
         Err(e) => {
-            error!("Failed to read devices.json: {}", e);
-            return Status::InternalServerError;
+            error!("Failed to update device: {}", e);
+            e.status()
         }
-    };
+    }
+}
 
-    let mut file_devices: Vec<WebDevice> = match serde_json::from_str(&content) {
-        Ok(devices) => devices,
-        Err(e) => {
-            error!("Failed to parse devices.json: {}", e);
-            return Status::InternalServerError;
-        }
-    };
+// Replace the devices file in one go, so the reload never sees half of it
+fn write_devices_file(file_path: &str, devices: &[WebDevice]) -> std::io::Result<()> {
+    let json = serde_json::to_string_pretty(devices)?;
+    let temp_path = format!("{}.tmp", file_path);
+    fs::write(&temp_path, json)?;
+    fs::rename(&temp_path, file_path)
+}
 
-    // Check if index is valid
-    if id >= file_devices.len() {
-        error!("Device index {} out of bounds", id);
-        return Status::NotFound;
+// Give the devices in the file that don't have an id yet one, and write it back so
+// they keep it. A device `known` by the same name keeps the id its history is under.
+// Parents in `depends_on` given by name, as they were before ids, are changed to their ids.
+fn assign_device_ids(file_path: &str, known: &[ModelDevice]) {
+    let Ok(data) = fs::read_to_string(file_path) else { return };
+    let Ok(mut entries) = from_str::<Vec<Value>>(&data) else { return };
+    let mut changed = 0;
+    for entry in entries.iter_mut() {
+        let Some(fields) = entry.as_object_mut().filter(|fields| !fields.contains_key("id")) else { continue };
+        let name = fields.get("name").and_then(Value::as_str);
+        let id = known.iter().find(|d| Some(d.name.as_str()) == name).map_or_else(Uuid::new_v4, |d| d.id);
+        fields.insert("id".to_string(), json!(id));
+        changed += 1;
     }
 
-    // Check for duplicates (excluding current device)
-    if file_devices.iter().enumerate().any(|(i, d)| 
-        i != id && (d.name == updated_device.name || d.ip == updated_device.ip)
-    ) {
-        error!("Device with same name or IP already exists");
-        return Status::Conflict;
+    let ids: HashMap<String, Value> = entries.iter()
+        .filter_map(|entry| Some((entry.get("name")?.as_str()?.to_string(), entry.get("id")?.clone())))
+        .collect();
+    for entry in entries.iter_mut() {
+        let device = entry.get("name").and_then(Value::as_str).unwrap_or_default().to_string();
+        let Some(parents) = entry.get_mut("depends_on").and_then(Value::as_array_mut) else { continue };
+        let before = parents.clone();
+        parents.retain_mut(|parent| match parent.as_str() {
+            Some(parent) if parent.parse::<Uuid>().is_ok() => true,
+            Some(name) => match ids.get(name) {
+                Some(id) => {
+                    *parent = id.clone();
+                    true
+                }
+                None => {
+                    error!("{} depends on {}, which is not in {}; dropping it", device, name, file_path);
+                    false
+                }
+            },
+            None => true,
+        });
+        if *parents != before {
+            changed += 1;
+        }
+    }
+    if changed == 0 {
+        return;
     }
 
-    // Update device in file array
-    file_devices[id] = updated_device.clone();
-
-    // Write to file atomically
-    let temp_path = format!("{}.tmp", file_path);
-    if let Ok(json) = serde_json::to_string_pretty(&file_devices) {
-        if let Err(e) = fs::write(&temp_path, &json) {
-            error!("Failed to write temporary file: {}", e);
-            return Status::InternalServerError;
-        }
-        if let Err(e) = fs::rename(&temp_path, file_path) {
-            error!("Failed to rename temporary file: {}", e);
-            return Status::InternalServerError;
-        }
-    } else {
-        return Status::InternalServerError;
+    let file_devices: Vec<WebDevice> = match serde_json::from_value(Value::Array(entries)) {
+        Ok(file_devices) => file_devices,
+        Err(e) => return error!("Failed to parse {}: {}", file_path, e),
+    };
+    match write_devices_file(file_path, &file_devices) {
+        Ok(()) => info!("Saved device ids in {}", file_path),
+        Err(e) => error!("Failed to save device ids to {}: {}", file_path, e),
     }
+}
 
-    // Update in-memory device
+// Pick up where the monitor left off before a restart
+async fn restore_device_results(storage: &dyn Storage, devices: &SharedDevices) {
+    let saved = match storage.load_devices().await {
+        Ok(saved) => saved,
+        Err(e) => {
+            error!("Failed to load the last device results: {:#}", e);
+            return;
+        }
+    };
     let mut devices_locked = devices.lock().await;
-    if id < devices_locked.len() {
-        devices_locked[id] = ModelDevice {
-            name: updated_device.name,
-            ip: updated_device.ip,
-            category: updated_device.category,
-            sensors: updated_device.sensors.iter()
-                .map(|s| match s.as_str() {
-                    "Ping" => SensorType::Ping,
-                    "Http" => SensorType::Http,
-                    "Https" => SensorType::Https,
-                    _ => SensorType::Ping
-                })
-                .collect(),
-            http_path: updated_device.http_path,
-            ping_status: None,
-            http_status: None,
-            bandwidth_usage: None,
-        };
+    for dev in devices_locked.iter_mut() {
+        if let Some(saved) = saved.iter().find(|d| d.id == dev.id) {
+            dev.copy_results_from(saved);
+        }
     }
-
-    Status::Ok
 }
 
 // Add this function to reload devices from file
 async fn reload_devices_from_file(file_path: &str, devices: SharedDevices) {
+    // Devices added to the file by hand get their id here
+    let known = devices.lock().await.clone();
+    assign_device_ids(file_path, &known);
     match fs::read_to_string(file_path) {
         Ok(data) => {
             match from_str::<Vec<ModelDevice>>(&data) {
@@ -694,12 +808,25 @@ async fn reload_devices_from_file(file_path: &str, devices: SharedDevices) {
                     // Initialize device status fields to None
                     for dev in file_devices.iter_mut() {
                         dev.ping_status = None;
+                        dev.ping_result = None;
                         dev.bandwidth_usage = None;
+                        dev.bandwidth_rates = None;
                         dev.http_status = None;
+                        dev.http_result = None;
+                        dev.http_timing = None;
+                        dev.state = None;
+                        dev.maintenance = None;
+                        dev.checks.clear();
                     }
                     
-                    // Update the shared devices list
+                    // Update the shared devices list, keeping the last results, since
+                    // sensors with long intervals may not run again for a while
                     let mut devices_locked = devices.lock().await;
+                    for dev in file_devices.iter_mut() {
+                        if let Some(existing) = devices_locked.iter().find(|d| d.id == dev.id) {
+                            dev.carry_over_from(existing);
+                        }
+                    }
                     *devices_locked = file_devices;
                     info!("Devices reloaded from file: {}", file_path);
                 },
@@ -710,7 +837,37 @@ async fn reload_devices_from_file(file_path: &str, devices: SharedDevices) {
     }
 }
 
-use env_logger;
+#[get("/api/maintenance")]
+async fn list_maintenance(_auth: Auth, maintenance: &State<Arc<MaintenanceService>>) -> Json<Vec<MaintenanceWindow>> {
+    Json(maintenance.list().await)
+}
+
+#[post("/api/maintenance", data = "<window>")]
+async fn add_maintenance(
+    _auth: Auth,
+    maintenance: &State<Arc<MaintenanceService>>,
+    window: Json<MaintenanceWindow>,
+) -> Result<Json<MaintenanceWindow>, (Status, Json<serde_json::Value>)> {
+    match maintenance.add(window.into_inner()).await {
+        Ok(window) => Ok(Json(window)),
+        Err(e) => {
+            error!("Failed to add maintenance window: {}", e);
+            Err((Status::BadRequest, Json(json!({ "status": "error", "message": e.to_string() }))))
+        }
+    }
+}
+
+#[delete("/api/maintenance/<id>")]
+async fn cancel_maintenance(_auth: Auth, maintenance: &State<Arc<MaintenanceService>>, id: &str) -> Status {
+    match maintenance.cancel(id).await {
+        Ok(true) => Status::NoContent,
+        Ok(false) => Status::NotFound,
+        Err(e) => {
+            error!("Failed to cancel maintenance window: {}", e);
+            Status::InternalServerError
+        }
+    }
+}
 
 #[get("/api/email/config")]
 async fn get_email_config(email_service: &State<Arc<EmailService>>) -> Json<serde_json::Value> {
@@ -766,24 +923,17 @@ async fn email_config_page(_auth: Auth) -> Option<NamedFile> {
 }
 
 #[post("/update-config", data = "<data>")]
-async fn update_config(data: Json<serde_json::Value>) -> Status {
+async fn update_config(data: Json<serde_json::Value>, storage: &State<Arc<dyn Storage>>) -> Status {
     if let Some(content) = data.get("content").and_then(|c| c.as_str()) {
         let config_path = Path::new("static/config.js");
         match fs::write(config_path, content) {
             Ok(_) => {
                 // Also update the in-memory AUTH_CONFIG
-                if let Ok(config_content) = content.parse::<String>() {
-                    if let Some(config_str) = config_content.strip_prefix("const AUTH_CONFIG = ") {
-                        if let Ok(config) = serde_json::from_str::<Value>(config_str) {
-                            // Update the global AUTH_CONFIG
-                            let new_config = Box::new(config);
-                            let old_ptr = AUTH_CONFIG.swap(Box::into_raw(new_config), Ordering::SeqCst);
-                            if !old_ptr.is_null() {
-                                unsafe {
-                                    drop(Box::from_raw(old_ptr));
-                                }
-                            }
-                        }
+                if let Some(config_str) = content.strip_prefix("const AUTH_CONFIG = ") {
+                    if let Ok(config) = serde_json::from_str::<Value>(config_str.trim_end().trim_end_matches(';')) {
+                        save_users_from_config(storage.inner().as_ref(), &config).await;
+                        // Update the global AUTH_CONFIG
+                        set_auth_config(config);
                     }
                 }
                 Status::Ok
@@ -811,10 +961,34 @@ async fn main() {
 
     let devices: SharedDevices = Arc::new(Mutex::new(Vec::new()));
     let email_service = Arc::new(EmailService::new());
+    let maintenance_service = Arc::new(MaintenanceService::new());
+    let monitor_config = MonitorConfig::new();
+    let metrics = Arc::new(Metrics::new(monitor_config.metrics_token.clone()));
+    // Results as they come in, for the event stream
+    let (events, _) = broadcast::channel::<LogEvent>(EVENT_BUFFER);
+
+    // History, last results and users, in SQLite unless DATABASE_URL says otherwise
+    dotenv::dotenv().ok();
+    let storage = match storage::open().await {
+        Ok(storage) => storage,
+        Err(e) => {
+            error!("Failed to open the database: {:#}", e);
+            return;
+        }
+    };
+    if storage.users().await.is_ok_and(|users| users.is_empty()) {
+        if let Some(config) = read_auth_config() {
+            save_users_from_config(storage.as_ref(), &config).await;
+        }
+    }
     
     let rocket_instance = rocket::build()
         .manage(devices.clone())
         .manage(email_service.clone())
+        .manage(maintenance_service.clone())
+        .manage(storage.clone())
+        .manage(events.clone())
+        .manage(metrics.clone())
         .mount("/static", FileServer::from(relative!("static")).rank(2))
         .mount("/", routes![
             index,
@@ -824,6 +998,7 @@ async fn main() {
             protected_log_view,
             add_model_device,
             get_devices,
+            get_device_statuses,
             export_log,
             logs_json,
             failed_logs,
@@ -838,157 +1013,133 @@ async fn main() {
             send_test_email,
             email_config_page,
             update_config,
+            list_maintenance,
+            add_maintenance,
+            cancel_maintenance,
+            get_events,
+            event_stream,
+            get_metrics,
         ])
-        .register("/", catchers![unauthorized]);
+        .mount("/api/v1", api::routes())
+        .register("/", catchers![unauthorized])
+        .register("/api/v1", api::catchers());
 
-    add_devices_from_file("devices.json", devices.clone()).await;
+    // Devices from before ids keep the one their history is stored under
+    match storage.load_devices().await {
+        Ok(saved) => assign_device_ids(DEVICES_FILE, &saved),
+        Err(e) => {
+            error!("Failed to load stored devices: {:#}", e);
+            assign_device_ids(DEVICES_FILE, &[]);
+        }
+    }
+    add_devices_from_file(DEVICES_FILE, devices.clone()).await;
+    restore_device_results(storage.as_ref(), &devices).await;
 
     // Spawn a periodic task to reload devices
     let devices_for_reload = devices.clone();
+    let reload_secs = monitor_config.reload_secs;
     tokio::spawn(async move {
         loop {
-            // Wait for 30 seconds
-            sleep(Duration::from_secs(30)).await;
+            sleep(Duration::from_secs(reload_secs)).await;
             
             // Reload devices
-            reload_devices_from_file("devices.json", devices_for_reload.clone()).await;
+            reload_devices_from_file(DEVICES_FILE, devices_for_reload.clone()).await;
         }
     });
 
-    // Spawn a background task.
-    let devices_clone = devices.clone();
-    let email_service_clone = email_service.clone();
+    // Keep the database from growing without bounds
+    tokio::spawn(storage::retention::run(storage.clone(), monitor_config.retention.clone()));
 
-    tokio::spawn(async move {
-        let mut device_statuses: HashMap<String, DeviceStatus> = HashMap::new();
-        
-        loop {
-            let devices_to_monitor: Vec<ModelDevice> = {
-                let locked = devices_clone.lock().await;
-                locked.clone()
-            };
+    let rocket_instance = match rocket_instance.ignite().await {
+        Ok(rocket_instance) => rocket_instance,
+        Err(e) => {
+            error!("Failed to launch the web server: {}", e);
+            return;
+        }
+    };
 
-            let mut status_changed = false;
+    // Spawn the background monitor, which stops along with the web server.
+    let monitor = tokio::spawn(monitor::run(
+        devices.clone(),
+        email_service.clone(),
+        maintenance_service.clone(),
+        storage,
+        events,
+        metrics,
+        monitor_config,
+        rocket_instance.shutdown(),
+    ));
+
+    match rocket_instance.launch().await {
+        // Let the monitor store what it has before exiting
+        Ok(_) => {
+            let _ = monitor.await;
+        }
+        Err(e) => error!("Failed to launch the web server: {}", e),
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A devices file of the test's own, removed when dropped
+    struct DevicesFile(String);
+
+    impl DevicesFile {
+        fn new(name: &str, contents: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("rustping-{}-{}.json", name, std::process::id()));
+            fs::write(&path, contents).unwrap();
+            Self(path.to_string_lossy().into_owned())
+        }
+    }
 
-            for dev in devices_to_monitor {
-                let status = device_statuses.entry(dev.ip.clone())
-                    .or_insert_with(DeviceStatus::new);
-                
-                // First check ping
-                let ping_result = monitor_ping(&dev.ip).await;
-                if status.update_ping(ping_result) {
-                    status_changed = true;
-                }
+    impl Drop for DevicesFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
 
-                // Update device in shared state
-                let mut devices_locked = devices_clone.lock().await;
-                if let Some(device) = devices_locked.iter_mut().find(|d| d.ip == dev.ip) {
-                    device.ping_status = status.ping_status;
-                    
-                    // Check HTTP and bandwidth if configured and ping is successful
-                    if device.ping_status == Some(true) {
-                        if device.sensors.contains(&SensorType::Http) || 
-                           device.sensors.contains(&SensorType::Https) {
-                            if let Some(ref url) = device.http_path {
-                                match monitor_http(url).await {
-                                    true => {
-                                        device.http_status = Some(true);
-                                        // Simulate bandwidth measurement only for successful HTTP connections
-                                        device.bandwidth_usage = Some(rand::thread_rng().gen_range(10.0..1000.0));
-                                        status_changed = true;
-                                    },
-                                    false => {
-                                        device.http_status = Some(false);
-                                        device.bandwidth_usage = None;
-                                        status_changed = true;
-                                    }
-                                }
-                            }
-                        }
-                    } else {
-                        // If ping fails, mark HTTP as down and clear bandwidth
-                        if device.sensors.contains(&SensorType::Http) || 
-                           device.sensors.contains(&SensorType::Https) {
-                            device.http_status = Some(false);
-                            device.bandwidth_usage = None;
-                            status_changed = true;
-                        }
-                    }
-                }
-            }
+    const ROUTER: &str = r#"{"id":"00000000-0000-0000-0000-000000000001","name":"Router","ip":"10.0.0.1","category":"core","sensors":["Ping"]}"#;
 
-            // Write to log file when status changes
-            if status_changed {
-                if let Ok(mut file) = OpenOptions::new().append(true).create(true).open(LOG_FILE) {
-                    let now = Local::now();
-                    let devices_locked = devices_clone.lock().await;
-                    
-                    for dev in devices_locked.iter() {
-                        let status = device_statuses.get(&dev.ip)
-                            .cloned()
-                            .unwrap_or_else(DeviceStatus::new);
-                        
-                        // Format HTTP status and bandwidth based on sensor configuration
-                        let http_status = if dev.sensors.contains(&SensorType::Http) || 
-                                           dev.sensors.contains(&SensorType::Https) {
-                            dev.http_status.map_or("FAIL", |s| if s { "OK" } else { "FAIL" })
-                        } else {
-                            "N/A"
-                        };
-                        
-                        let bandwidth = if (dev.sensors.contains(&SensorType::Http) || 
-                                         dev.sensors.contains(&SensorType::Https)) && 
-                                         dev.http_status == Some(true) {
-                            dev.bandwidth_usage.map_or("N/A".to_string(), |b| format!("{:.2} Mbps", b))
-                        } else {
-                            "N/A".to_string()
-                        };
-                        
-                        let ping_status_str = status.ping_status.map_or("N/A", |s| if s { "OK" } else { "FAIL" });
-                        
-                        let log_entry = format!(
-                            "{} - {} ({}): Ping: {}, HTTP: {}, Bandwidth: {}\n",
-                            now.format("%Y-%m-%d %H:%M:%S"),
-                            dev.name,
-                            dev.ip,
-                            ping_status_str,
-                            http_status,
-                            bandwidth
-                        );
-                        
-                        if let Err(e) = file.write_all(log_entry.as_bytes()) {
-                            error!("Failed to write log entry: {}", e);
-                        }
-                        
-                        // Send email notification if ping or HTTP status is FAIL
-                        if ping_status_str == "FAIL" || http_status == "FAIL" {
-                            // Create LogData for email
-                            let log_data = email::LogData {
-                                date: now.format("%Y-%m-%d").to_string(),
-                                time: now.format("%H:%M:%S").to_string(),
-                                ping_status: ping_status_str.to_string(),
-                                http_status: http_status.to_string(),
-                                bandwidth: bandwidth.clone(),
-                            };
-                            
-                            // Send email notification in a separate task to avoid blocking
-                            let device_name = dev.name.clone();
-                            let email_service_clone = email_service_clone.clone();
-                            tokio::spawn(async move {
-                                if let Err(e) = email_service_clone.send_email(&device_name, &log_data).await {
-                                    error!("Failed to send email notification: {}", e);
-                                }
-                            });
-                        }
-                    }
-                }
-            }
+    #[tokio::test]
+    async fn reload_keeps_sensor_since() {
+        let file = DevicesFile::new("reload", &format!("[{}]", ROUTER));
+        let since = chrono::Local::now() - chrono::Duration::minutes(5);
+        let mut device: ModelDevice = from_str(ROUTER).unwrap();
+        device.ping_status = Some(true);
+        device.sensor_since.insert("Ping".to_string(), since);
+        let devices: SharedDevices = Arc::new(Mutex::new(vec![device]));
 
-            sleep(Duration::from_secs(5)).await;
-        }
-    });
+        reload_devices_from_file(&file.0, devices.clone()).await;
+
+        let devices = devices.lock().await;
+        assert_eq!(devices[0].ping_status, Some(true));
+        assert_eq!(devices[0].sensor_since.get("Ping"), Some(&since));
+    }
 
-    if let Err(e) = rocket_instance.launch().await {
-        error!("Failed to launch the web server: {}", e);
+    #[test]
+    fn assign_device_ids_keeps_ids_and_translates_dependencies() {
+        let switch_id = Uuid::from_u128(2);
+        let file = DevicesFile::new("ids", &format!(r#"[
+            {{"name":"Router","ip":"10.0.0.1","category":"core","sensors":["Ping"]}},
+            {{"id":"{}","name":"Switch","ip":"10.0.0.2","category":"core","sensors":["Ping"]}},
+            {{"name":"NAS","ip":"10.0.0.3","category":"lab","sensors":["Ping"],"depends_on":["Switch","Router","Gone"]}}
+        ]"#, switch_id));
+        // The router's history is already stored under this id
+        let mut router: ModelDevice = from_str(ROUTER).unwrap();
+        router.id = Uuid::from_u128(1);
+
+        assign_device_ids(&file.0, &[router]);
+        let devices = read_devices_file(&file.0).unwrap();
+        let ids: Vec<Uuid> = devices.iter().map(|device| device.id).collect();
+        assert_eq!(ids[..2], [Uuid::from_u128(1), switch_id]);
+        assert!(!ids[2].is_nil() && !ids[..2].contains(&ids[2]));
+        // Parents by name become ids, and unknown ones are dropped
+        assert_eq!(devices[2].depends_on, [switch_id, Uuid::from_u128(1)]);
+
+        // Once every device has an id the file is left alone
+        let written = fs::read_to_string(&file.0).unwrap();
+        assign_device_ids(&file.0, &[]);
+        assert_eq!(fs::read_to_string(&file.0).unwrap(), written);
     }
-}
\ No newline at end of file
+}
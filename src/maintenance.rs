@@ -0,0 +1,487 @@
+// src/maintenance.rs
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDate, NaiveDateTime, TimeZone, Timelike};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use tokio::sync::RwLock;
+use crate::models::{Device, MaintenanceStatus};
+
+const MAINTENANCE_FILE: &str = "maintenance.json";
+// Recurring windows longer than this are almost certainly a mistake
+const MAX_RECURRING_MINS: u64 = 7 * 24 * 60;
+
+/// Scheduled downtime for one device, a category or everything. Either
+/// `start` and `end` for a one-off window, or `cron` and `duration_mins`
+/// for a recurring one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MaintenanceWindow {
+    #[serde(default)]
+    pub id: String,
+    // Device name or id; with neither this nor `category` set, the window covers all devices
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub category: Option<String>,
+    // Local times, "2024-05-01 22:00" or RFC 3339
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end: Option<String>,
+    // Minute, hour, day of month, month and day of week, e.g. "0 2 * * 0" for Sundays at 02:00
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cron: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_mins: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+impl MaintenanceWindow {
+    // Check the window and parse its times or cron expression
+    fn schedule(&self) -> Result<Schedule> {
+        match (&self.start, &self.end, &self.cron, self.duration_mins) {
+            (Some(start), Some(end), None, None) => {
+                let (start, end) = (parse_time(start)?, parse_time(end)?);
+                if end <= start {
+                    bail!("end must be after start");
+                }
+                Ok(Schedule::Once { start, end })
+            }
+            (None, None, Some(cron), Some(duration_mins)) => {
+                let cron = Cron::parse(cron)?;
+                if duration_mins == 0 || duration_mins > MAX_RECURRING_MINS {
+                    bail!("duration_mins must be between 1 and {}", MAX_RECURRING_MINS);
+                }
+                Ok(Schedule::Recurring { cron, duration: Duration::minutes(duration_mins as i64) })
+            }
+            _ => bail!("Set either start and end, or cron and duration_mins"),
+        }
+    }
+
+    fn covers(&self, device: &Device) -> bool {
+        self.device.as_ref().is_none_or(|name| *name == device.name || *name == device.id.to_string())
+            && self.category.as_ref().is_none_or(|category| *category == device.category)
+    }
+}
+
+enum Schedule {
+    Once { start: DateTime<Local>, end: DateTime<Local> },
+    Recurring { cron: Cron, duration: Duration },
+}
+
+impl Schedule {
+    // When the occurrence `now` falls in ends, if any, and the next time that can change
+    fn at(&self, now: DateTime<Local>) -> (Option<DateTime<Local>>, Option<DateTime<Local>>) {
+        match *self {
+            Schedule::Once { start, .. } if now < start => (None, Some(start)),
+            Schedule::Once { end, .. } if now < end => (Some(end), Some(end)),
+            Schedule::Once { .. } => (None, None),
+            Schedule::Recurring { ref cron, duration } => {
+                // Starts after `now - duration` are still going; the latest of them ends last
+                let mut until = None;
+                let mut next = cron.next_after(now - duration);
+                while let Some(start) = next.filter(|start| *start <= now) {
+                    until = Some(start + duration);
+                    next = cron.next_after(start);
+                }
+                let recheck = match (until, next) {
+                    (Some(until), Some(next)) => Some(until.min(next)),
+                    (until, next) => until.or(next),
+                };
+                (until, recheck)
+            }
+        }
+    }
+}
+
+pub fn parse_time(value: &str) -> Result<DateTime<Local>> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.with_timezone(&Local));
+    }
+    ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%dT%H:%M"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+        .and_then(|time| Local.from_local_datetime(&time).earliest())
+        .ok_or_else(|| anyhow!("Invalid time {:?}, expected e.g. \"2024-05-01 22:00\"", value))
+}
+
+// A five field cron expression: lists, ranges and steps, e.g. "*/15 1-5 * * 1,3"
+struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    // Like cron, restricting both days and weekdays means either may match
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+impl Cron {
+    fn parse(expression: &str) -> Result<Self> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            bail!("cron needs 5 fields (minute hour day month weekday): {:?}", expression);
+        };
+        let mut weekdays_mask = parse_field(weekdays, 0, 7)?;
+        // Both 0 and 7 are Sunday
+        if weekdays_mask & (1 << 7) != 0 {
+            weekdays_mask |= 1;
+        }
+        Ok(Self {
+            minutes: parse_field(minutes, 0, 59)?,
+            hours: parse_field(hours, 0, 23)?,
+            days: parse_field(days, 1, 31)?,
+            months: parse_field(months, 1, 12)?,
+            weekdays: weekdays_mask,
+            days_restricted: days != "*",
+            weekdays_restricted: weekdays != "*",
+        })
+    }
+
+    fn day_matches(&self, date: NaiveDate) -> bool {
+        let day = bit(self.days, date.day());
+        let weekday = bit(self.weekdays, date.weekday().num_days_from_sunday());
+        match (self.days_restricted, self.weekdays_restricted) {
+            (true, true) => day || weekday,
+            _ => day && weekday,
+        }
+    }
+
+    // The first minute after `time` the expression matches, skipping whole months, days
+    // and hours that can't; None if there is none within the next few years
+    fn next_after(&self, time: DateTime<Local>) -> Option<DateTime<Local>> {
+        let mut next = time.naive_local().with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+        let limit = next + Duration::days(5 * 366);
+        while next < limit {
+            let midnight = next.date().and_hms_opt(0, 0, 0)?;
+            if !bit(self.months, next.month()) {
+                let (year, month) = if next.month() == 12 { (next.year() + 1, 1) } else { (next.year(), next.month() + 1) };
+                next = NaiveDate::from_ymd_opt(year, month, 1)?.and_hms_opt(0, 0, 0)?;
+            } else if !self.day_matches(next.date()) {
+                next = midnight + Duration::days(1);
+            } else if !bit(self.hours, next.hour()) {
+                next = midnight + Duration::hours(next.hour() as i64 + 1);
+            } else if !bit(self.minutes, next.minute()) {
+                next += Duration::minutes(1);
+            } else {
+                // Times skipped when the clocks go forward don't happen
+                match Local.from_local_datetime(&next).earliest() {
+                    Some(local) if local > time => return Some(local),
+                    _ => next += Duration::minutes(1),
+                }
+            }
+        }
+        None
+    }
+}
+
+fn bit(mask: u64, value: u32) -> bool {
+    mask & (1 << value) != 0
+}
+
+// One field as a bit mask of the values it allows
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64> {
+    let mut mask = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().map_err(|_| anyhow!("Invalid step in {:?}", field))?),
+            None => (part, 1),
+        };
+        let (low, high) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((low, high)) => (low.parse()?, high.parse()?),
+                // "5/10" means from 5 to the end in steps of 10
+                None if step > 1 => (range.parse()?, max),
+                None => (range.parse()?, range.parse()?),
+            },
+        };
+        if step == 0 || low < min || high > max || low > high {
+            bail!("{:?} is out of range {}-{}", part, min, max);
+        }
+        for value in (low..=high).step_by(step as usize) {
+            mask |= 1 << value;
+        }
+    }
+    Ok(mask)
+}
+
+// A window with its parsed schedule, and where it stood when last looked at
+struct Scheduled {
+    window: MaintenanceWindow,
+    schedule: Schedule,
+    until: Option<DateTime<Local>>,
+    // When `until` has to be worked out again; None before the first time
+    recheck: Option<DateTime<Local>>,
+}
+
+impl Scheduled {
+    fn new(window: MaintenanceWindow) -> Result<Self> {
+        let schedule = window.schedule()?;
+        Ok(Self { window, schedule, until: None, recheck: None })
+    }
+}
+
+/// The windows in effect at one point in time.
+pub struct ActiveWindows(Vec<(MaintenanceWindow, DateTime<Local>)>);
+
+impl ActiveWindows {
+    /// The window the device is in, ending last if there are several.
+    pub fn for_device(&self, device: &Device) -> Option<MaintenanceStatus> {
+        self.0.iter()
+            .filter(|(window, _)| window.covers(device))
+            .max_by_key(|(_, until)| *until)
+            .map(|(window, until)| MaintenanceStatus {
+                window_id: window.id.clone(),
+                reason: window.reason.clone(),
+                until: until.format("%Y-%m-%d %H:%M:%S").to_string(),
+            })
+    }
+}
+
+/// Maintenance windows, kept in `maintenance.json`.
+pub struct MaintenanceService {
+    windows: RwLock<Vec<Scheduled>>,
+}
+
+impl MaintenanceService {
+    pub fn new() -> Self {
+        let windows = Self::load_from_file().unwrap_or_else(|e| {
+            if Path::new(MAINTENANCE_FILE).exists() {
+                error!("Failed to load {}: {}", MAINTENANCE_FILE, e);
+            } else {
+                info!("No maintenance windows defined");
+            }
+            Vec::new()
+        });
+        let windows = windows.into_iter()
+            .filter_map(|window| {
+                let id = window.id.clone();
+                Scheduled::new(window).map_err(|e| error!("Ignoring maintenance window {}: {}", id, e)).ok()
+            })
+            .collect();
+        Self { windows: RwLock::new(windows) }
+    }
+
+    fn load_from_file() -> Result<Vec<MaintenanceWindow>> {
+        let content = fs::read_to_string(MAINTENANCE_FILE)?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    fn save_to_file<'a>(windows: impl Iterator<Item = &'a MaintenanceWindow>) -> Result<()> {
+        let windows: Vec<&MaintenanceWindow> = windows.collect();
+        let temp_path = format!("{}.tmp", MAINTENANCE_FILE);
+        fs::write(&temp_path, serde_json::to_string_pretty(&windows)?)?;
+        fs::rename(&temp_path, MAINTENANCE_FILE)?;
+        Ok(())
+    }
+
+    pub async fn list(&self) -> Vec<MaintenanceWindow> {
+        self.windows.read().await.iter().map(|scheduled| scheduled.window.clone()).collect()
+    }
+
+    pub async fn add(&self, mut window: MaintenanceWindow) -> Result<MaintenanceWindow> {
+        window.id = uuid::Uuid::new_v4().to_string();
+        let scheduled = Scheduled::new(window.clone())?;
+        let mut windows = self.windows.write().await;
+        // Only keep the window once it is saved, so it doesn't vanish on a restart
+        Self::save_to_file(windows.iter().map(|s| &s.window).chain([&window]))?;
+        windows.push(scheduled);
+        info!("Added maintenance window {}", window.id);
+        Ok(window)
+    }
+
+    /// Remove a window; false if there is none with that id.
+    pub async fn cancel(&self, id: &str) -> Result<bool> {
+        let mut windows = self.windows.write().await;
+        if !windows.iter().any(|s| s.window.id == id) {
+            return Ok(false);
+        }
+        Self::save_to_file(windows.iter().map(|s| &s.window).filter(|window| window.id != id))?;
+        windows.retain(|s| s.window.id != id);
+        info!("Cancelled maintenance window {}", id);
+        Ok(true)
+    }
+
+    /// The windows in effect now. Each window is only worked out again once it
+    /// starts or ends, and one-off windows that are over are removed.
+    pub async fn active(&self) -> ActiveWindows {
+        let now = Local::now();
+        let mut windows = self.windows.write().await;
+        let mut expired = false;
+        for scheduled in windows.iter_mut() {
+            if scheduled.recheck.is_none_or(|recheck| recheck <= now) {
+                (scheduled.until, scheduled.recheck) = scheduled.schedule.at(now);
+                expired |= matches!(scheduled.schedule, Schedule::Once { end, .. } if end <= now);
+            }
+        }
+        if expired {
+            windows.retain(|s| !matches!(s.schedule, Schedule::Once { end, .. } if end <= now));
+            match Self::save_to_file(windows.iter().map(|s| &s.window)) {
+                Ok(()) => info!("Removed maintenance windows that are over"),
+                Err(e) => error!("Failed to save {}: {}", MAINTENANCE_FILE, e),
+            }
+        }
+        ActiveWindows(windows.iter()
+            .filter_map(|s| s.until.map(|until| (s.window.clone(), until)))
+            .collect())
+    }
+
+    /// The window the device is in right now, ending last if there are several.
+    pub async fn active_for(&self, device: &Device) -> Option<MaintenanceStatus> {
+        self.active().await.for_device(device)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(year, month, day, hour, minute, 0).unwrap()
+    }
+
+    fn values(mask: u64) -> Vec<u32> {
+        (0..64).filter(|value| bit(mask, *value)).collect()
+    }
+
+    fn recurring(cron: &str, duration_mins: u64) -> MaintenanceWindow {
+        MaintenanceWindow {
+            id: String::new(),
+            device: None,
+            category: None,
+            start: None,
+            end: None,
+            cron: Some(cron.to_string()),
+            duration_mins: Some(duration_mins),
+            reason: None,
+        }
+    }
+
+    fn once(start: &str, end: &str) -> MaintenanceWindow {
+        MaintenanceWindow { start: Some(start.to_string()), end: Some(end.to_string()), cron: None, duration_mins: None, ..recurring("", 0) }
+    }
+
+    #[test]
+    fn fields_take_lists_ranges_and_steps() {
+        assert_eq!(values(parse_field("*", 0, 59).unwrap()), (0..=59).collect::<Vec<_>>());
+        assert_eq!(values(parse_field("7", 0, 59).unwrap()), [7]);
+        assert_eq!(values(parse_field("1,3,5", 0, 59).unwrap()), [1, 3, 5]);
+        assert_eq!(values(parse_field("1-5", 0, 59).unwrap()), [1, 2, 3, 4, 5]);
+        assert_eq!(values(parse_field("*/15", 0, 59).unwrap()), [0, 15, 30, 45]);
+        assert_eq!(values(parse_field("1-10/3", 0, 59).unwrap()), [1, 4, 7, 10]);
+        assert_eq!(values(parse_field("50/5", 0, 59).unwrap()), [50, 55]);
+        assert_eq!(values(parse_field("1-2,20-22/2", 0, 59).unwrap()), [1, 2, 20, 22]);
+        assert_eq!(values(parse_field("*", 1, 12).unwrap()), (1..=12).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn fields_out_of_range_or_malformed_are_rejected() {
+        for field in ["60", "0", "5-1", "*/0", "a", "1-", "", "1,,2", "-1"] {
+            assert!(parse_field(field, 1, 59).is_err(), "{:?} was accepted", field);
+        }
+        assert!(Cron::parse("0 2 * *").is_err());
+        assert!(Cron::parse("0 2 * * * *").is_err());
+        assert!(Cron::parse("0 24 * * *").is_err());
+        assert!(Cron::parse("0 0 0 * *").is_err());
+        assert!(Cron::parse("0 0 * 13 *").is_err());
+    }
+
+    #[test]
+    fn seven_is_sunday_too() {
+        let cron = Cron::parse("0 0 * * 7").unwrap();
+        assert!(cron.day_matches(at(2024, 5, 5, 0, 0).date_naive()));
+        assert!(!cron.day_matches(at(2024, 5, 4, 0, 0).date_naive()));
+    }
+
+    #[test]
+    fn day_of_month_and_weekday_match_either_when_both_are_set() {
+        // The 13th or any Friday
+        let cron = Cron::parse("0 0 13 * 5").unwrap();
+        assert!(cron.day_matches(at(2024, 6, 13, 0, 0).date_naive()));
+        assert!(cron.day_matches(at(2024, 9, 6, 0, 0).date_naive()));
+        assert!(!cron.day_matches(at(2024, 9, 12, 0, 0).date_naive()));
+
+        // Only one of them set must match
+        let cron = Cron::parse("0 0 13 * *").unwrap();
+        assert!(cron.day_matches(at(2024, 6, 13, 0, 0).date_naive()));
+        assert!(!cron.day_matches(at(2024, 9, 6, 0, 0).date_naive()));
+        let cron = Cron::parse("0 0 * * 5").unwrap();
+        assert!(!cron.day_matches(at(2024, 6, 13, 0, 0).date_naive()));
+        assert!(cron.day_matches(at(2024, 9, 13, 0, 0).date_naive()));
+    }
+
+    #[test]
+    fn next_after_finds_the_following_start() {
+        let sundays = Cron::parse("0 2 * * 0").unwrap();
+        // From a Wednesday to the Sunday after
+        assert_eq!(sundays.next_after(at(2024, 5, 1, 12, 0)), Some(at(2024, 5, 5, 2, 0)));
+        // Strictly after: a start at exactly that time is skipped
+        assert_eq!(sundays.next_after(at(2024, 5, 5, 2, 0)), Some(at(2024, 5, 12, 2, 0)));
+        assert_eq!(sundays.next_after(at(2024, 5, 5, 1, 59)), Some(at(2024, 5, 5, 2, 0)));
+
+        let quarter_hours = Cron::parse("*/15 9-17 * * 1-5").unwrap();
+        assert_eq!(quarter_hours.next_after(at(2024, 5, 1, 10, 16)), Some(at(2024, 5, 1, 10, 30)));
+        assert_eq!(quarter_hours.next_after(at(2024, 5, 1, 17, 45)), Some(at(2024, 5, 2, 9, 0)));
+        // Friday evening to Monday morning
+        assert_eq!(quarter_hours.next_after(at(2024, 5, 3, 18, 0)), Some(at(2024, 5, 6, 9, 0)));
+
+        // Over the end of the year, and to the next leap day
+        let new_year = Cron::parse("30 0 1 1 *").unwrap();
+        assert_eq!(new_year.next_after(at(2024, 12, 31, 23, 59)), Some(at(2025, 1, 1, 0, 30)));
+        let leap_day = Cron::parse("0 0 29 2 *").unwrap();
+        assert_eq!(leap_day.next_after(at(2024, 3, 1, 0, 0)), Some(at(2028, 2, 29, 0, 0)));
+
+        // A day that never comes
+        assert_eq!(Cron::parse("0 0 30 2 *").unwrap().next_after(at(2024, 1, 1, 0, 0)), None);
+    }
+
+    #[test]
+    fn recurring_windows_are_active_from_start_to_end() {
+        let schedule = recurring("0 2 * * 0", 60).schedule().unwrap();
+        // Just before: not active, look again at the start
+        assert_eq!(schedule.at(at(2024, 5, 5, 1, 59)), (None, Some(at(2024, 5, 5, 2, 0))));
+        // From the start to just before the end
+        assert_eq!(schedule.at(at(2024, 5, 5, 2, 0)), (Some(at(2024, 5, 5, 3, 0)), Some(at(2024, 5, 5, 3, 0))));
+        let almost_over = at(2024, 5, 5, 2, 59) + Duration::seconds(59);
+        assert_eq!(schedule.at(almost_over).0, Some(at(2024, 5, 5, 3, 0)));
+        // The end itself is outside, and the next start is a week later
+        assert_eq!(schedule.at(at(2024, 5, 5, 3, 0)), (None, Some(at(2024, 5, 12, 2, 0))));
+    }
+
+    #[test]
+    fn overlapping_occurrences_end_with_the_latest_start() {
+        let schedule = recurring("*/15 * * * *", 30).schedule().unwrap();
+        // Started at 10:00 and 10:15; look again when 10:30 starts another
+        assert_eq!(schedule.at(at(2024, 5, 1, 10, 20)), (Some(at(2024, 5, 1, 10, 45)), Some(at(2024, 5, 1, 10, 30))));
+    }
+
+    #[test]
+    fn windows_spanning_midnight_carry_over() {
+        let schedule = recurring("0 23 * * *", 120).schedule().unwrap();
+        assert_eq!(schedule.at(at(2024, 5, 2, 0, 30)).0, Some(at(2024, 5, 2, 1, 0)));
+        assert_eq!(schedule.at(at(2024, 5, 2, 1, 0)).0, None);
+    }
+
+    #[test]
+    fn one_off_windows_end_at_their_end() {
+        let schedule = once("2024-05-01 22:00", "2024-05-02 02:00").schedule().unwrap();
+        assert_eq!(schedule.at(at(2024, 5, 1, 21, 59)), (None, Some(at(2024, 5, 1, 22, 0))));
+        assert_eq!(schedule.at(at(2024, 5, 1, 22, 0)), (Some(at(2024, 5, 2, 2, 0)), Some(at(2024, 5, 2, 2, 0))));
+        // Over for good
+        assert_eq!(schedule.at(at(2024, 5, 2, 2, 0)), (None, None));
+    }
+
+    #[test]
+    fn windows_need_one_kind_of_schedule() {
+        assert!(once("2024-05-02 02:00", "2024-05-01 22:00").schedule().is_err());
+        assert!(recurring("0 2 * * 0", 0).schedule().is_err());
+        assert!(recurring("0 2 * * 0", MAX_RECURRING_MINS + 1).schedule().is_err());
+        assert!(recurring("0 2 * *", 60).schedule().is_err());
+        let both = MaintenanceWindow { start: Some("2024-05-01 22:00".to_string()), ..recurring("0 2 * * 0", 60) };
+        assert!(both.schedule().is_err());
+    }
+}
@@ -0,0 +1,282 @@
+// src/metrics.rs
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use uuid::Uuid;
+use crate::models::{Device, DeviceState, SensorType};
+
+/// Counters kept by the monitor and the last round of checks on each device,
+/// for the Prometheus endpoint.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    checks: AtomicU64,
+    check_errors: AtomicU64,
+    emails_sent: AtomicU64,
+    email_failures: AtomicU64,
+    // How long the last round of checks on each device took
+    durations: Mutex<HashMap<Uuid, Duration>>,
+    // Scrapes must send it as a bearer token when set
+    token: Option<String>,
+}
+
+impl Metrics {
+    pub fn new(token: Option<String>) -> Self {
+        Self { token: token.filter(|token| !token.is_empty()), ..Default::default() }
+    }
+
+    /// A round of checks on a device: how many sensors ran, how many of them failed and how long it took.
+    pub fn record_round(&self, id: Uuid, checks: usize, errors: usize, duration: Duration) {
+        self.checks.fetch_add(checks as u64, Ordering::Relaxed);
+        self.check_errors.fetch_add(errors as u64, Ordering::Relaxed);
+        self.durations.lock().unwrap().insert(id, duration);
+    }
+
+    pub fn record_email(&self, sent: bool) {
+        let counter = if sent { &self.emails_sent } else { &self.email_failures };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Whether a scrape with this `Authorization` header may read the metrics.
+    pub fn allows(&self, authorization: Option<&str>) -> bool {
+        match self.token {
+            None => true,
+            Some(ref token) => authorization.and_then(|value| value.strip_prefix("Bearer ")) == Some(token.as_str()),
+        }
+    }
+
+    /// The metrics in the Prometheus text format, for these devices.
+    pub fn render(&self, devices: &[Device]) -> String {
+        let mut out = Exposition::default();
+        let durations = self.durations.lock().unwrap().clone();
+
+        out.family("rustping_device_up", "gauge", "Whether the device is UP (1) or not (0)");
+        for device in devices {
+            if let Some(state) = device.state {
+                out.sample("rustping_device_up", &labels(device, None), (state == DeviceState::Up) as u8 as f64);
+            }
+        }
+        out.family("rustping_device_state", "gauge", "The device's state, 1 for the one it is in");
+        for device in devices {
+            if let Some(state) = device.state {
+                let mut labels = labels(device, None);
+                labels.push(("state", state.to_string()));
+                out.sample("rustping_device_state", &labels, 1.0);
+            }
+        }
+        out.family("rustping_sensor_up", "gauge", "Whether the sensor's last check passed (1) or failed (0)");
+        for device in devices {
+            for (sensor, up) in device.sensor_states() {
+                out.sample("rustping_sensor_up", &labels(device, Some(&sensor)), up as u8 as f64);
+            }
+        }
+        out.family("rustping_check_duration_seconds", "gauge", "How long the last round of checks on the device took");
+        for device in devices {
+            if let Some(duration) = durations.get(&device.id) {
+                out.sample("rustping_check_duration_seconds", &labels(device, None), duration.as_secs_f64());
+            }
+        }
+
+        out.family("rustping_ping_rtt_seconds", "gauge", "Average round-trip time of the last Ping");
+        for device in devices {
+            if let Some(rtt) = device.ping_result.as_ref().and_then(|r| r.avg_rtt_ms) {
+                out.sample("rustping_ping_rtt_seconds", &labels(device, Some("Ping")), rtt / 1000.0);
+            }
+        }
+        out.family("rustping_ping_packet_loss_ratio", "gauge", "Share of echo requests of the last Ping without a reply");
+        for device in devices {
+            if let Some(ref result) = device.ping_result {
+                out.sample("rustping_ping_packet_loss_ratio", &labels(device, Some("Ping")), result.loss_percent / 100.0);
+            }
+        }
+
+        out.family("rustping_http_status_code", "gauge", "Status code of the last HTTP response");
+        for device in devices {
+            // The response is kept as e.g. "200 OK"
+            let (Some(sensor), Some(result)) = (http_sensor(device), device.http_result.as_ref()) else { continue };
+            let code = result.response.as_deref()
+                .and_then(|response| response.split_whitespace().next())
+                .and_then(|code| code.parse::<u16>().ok());
+            if let Some(code) = code {
+                out.sample("rustping_http_status_code", &labels(device, Some(&sensor)), code as f64);
+            }
+        }
+        out.family("rustping_http_response_seconds", "gauge", "Time until the last HTTP response was read");
+        for device in devices {
+            let (Some(sensor), Some(result)) = (http_sensor(device), device.http_result.as_ref()) else { continue };
+            if let Some(latency) = result.latency_ms {
+                out.sample("rustping_http_response_seconds", &labels(device, Some(&sensor)), latency / 1000.0);
+            }
+        }
+
+        out.family("rustping_check_latency_seconds", "gauge", "Response time of the last TCP, UDP, DNS, TLS or SNMP check");
+        for device in devices {
+            for check in device.checks.iter() {
+                if let Some(latency) = check.latency_ms {
+                    out.sample("rustping_check_latency_seconds", &labels(device, Some(&check.sensor)), latency / 1000.0);
+                }
+            }
+        }
+        out.family("rustping_certificate_expiry_days", "gauge", "Days until the certificate seen by the last TLS check expires");
+        for device in devices {
+            for check in device.checks.iter() {
+                if let Some(ref certificate) = check.certificate {
+                    let labels = labels(device, Some(&check.sensor));
+                    out.sample("rustping_certificate_expiry_days", &labels, certificate.days_until_expiry as f64);
+                }
+            }
+        }
+
+        out.family("rustping_bandwidth_mbps", "gauge", "Traffic in and out of the device's interface, in Mbps");
+        let bandwidth = SensorType::Bandwidth.to_string();
+        for device in devices {
+            if let Some(usage) = device.bandwidth_usage {
+                out.sample("rustping_bandwidth_mbps", &labels(device, Some(&bandwidth)), usage);
+            }
+        }
+
+        for (name, help, counter) in [
+            ("rustping_checks_total", "Sensor checks run", &self.checks),
+            ("rustping_check_errors_total", "Sensor checks that failed", &self.check_errors),
+            ("rustping_emails_sent_total", "Alert emails sent", &self.emails_sent),
+            ("rustping_email_failures_total", "Alert emails that could not be sent", &self.email_failures),
+        ] {
+            out.family(name, "counter", help);
+            out.sample(name, &[], counter.load(Ordering::Relaxed) as f64);
+        }
+        out.text
+    }
+}
+
+// Labels identifying a device, and one of its sensors when given
+fn labels(device: &Device, sensor: Option<&str>) -> Vec<(&'static str, String)> {
+    let mut labels = vec![
+        ("id", device.id.to_string()),
+        ("device", device.name.clone()),
+        ("ip", device.ip.clone()),
+        ("category", device.category.clone()),
+    ];
+    if let Some(sensor) = sensor {
+        labels.push(("sensor", sensor.to_string()));
+    }
+    labels
+}
+
+// The label of the device's HTTP or HTTPS sensor, which its `http_result` is from
+fn http_sensor(device: &Device) -> Option<String> {
+    device.sensors.iter()
+        .find(|sensor| matches!(sensor, SensorType::Http | SensorType::Https))
+        .map(|sensor| sensor.to_string())
+}
+
+#[derive(Default)]
+struct Exposition {
+    text: String,
+}
+
+impl Exposition {
+    fn family(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.text, "# HELP {} {}", name, help);
+        let _ = writeln!(self.text, "# TYPE {} {}", name, kind);
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, String)], value: f64) {
+        self.text.push_str(name);
+        if !labels.is_empty() {
+            let labels: Vec<String> = labels.iter()
+                .map(|(key, value)| format!("{}=\"{}\"", key, escape(value)))
+                .collect();
+            let _ = write!(self.text, "{{{}}}", labels.join(","));
+        }
+        let _ = writeln!(self.text, " {}", value);
+    }
+}
+
+// Backslashes, quotes and line breaks have to be escaped in label values
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{CheckResult, PingResult};
+
+    const LABELS: &str = r#"id="00000000-0000-0000-0000-000000000000",device="Core \"sw\"\\1\nrack",ip="10.0.0.1",category="core""#;
+
+    fn device() -> Device {
+        let mut device: Device = serde_json::from_str(
+            r#"{"name":"Core \"sw\"\\1\nrack","ip":"10.0.0.1","category":"core",
+                "sensors":["Ping","Http",{"Tcp":{"port":22}}],"http_path":"http://10.0.0.1/"}"#,
+        ).unwrap();
+        device.id = Uuid::nil();
+        device.state = Some(DeviceState::Up);
+        device.ping_status = Some(true);
+        device.ping_result = Some(PingResult::from_rtts(4, &[1.0, 3.0], Some(64)));
+        device.http_status = Some(true);
+        device.http_result = Some(CheckResult {
+            sensor: "HTTP".to_string(),
+            success: true,
+            latency_ms: Some(150.0),
+            response: Some("200 OK".to_string()),
+            ..Default::default()
+        });
+        device.checks = vec![CheckResult { sensor: "TCP 22".to_string(), latency_ms: Some(5.0), ..Default::default() }];
+        device
+    }
+
+    #[test]
+    fn renders_devices_and_counters() {
+        let metrics = Metrics::new(None);
+        metrics.record_round(Uuid::nil(), 4, 1, Duration::from_millis(1500));
+        metrics.record_email(true);
+        let text = metrics.render(&[device()]);
+        let lines: Vec<&str> = text.lines().collect();
+
+        for line in [
+            "# HELP rustping_device_up Whether the device is UP (1) or not (0)".to_string(),
+            "# TYPE rustping_device_up gauge".to_string(),
+            format!("rustping_device_up{{{}}} 1", LABELS),
+            format!("rustping_device_state{{{},state=\"UP\"}} 1", LABELS),
+            format!("rustping_sensor_up{{{},sensor=\"Ping\"}} 1", LABELS),
+            format!("rustping_sensor_up{{{},sensor=\"TCP 22\"}} 0", LABELS),
+            format!("rustping_check_duration_seconds{{{}}} 1.5", LABELS),
+            format!("rustping_ping_rtt_seconds{{{},sensor=\"Ping\"}} 0.002", LABELS),
+            format!("rustping_ping_packet_loss_ratio{{{},sensor=\"Ping\"}} 0.5", LABELS),
+            format!("rustping_http_status_code{{{},sensor=\"HTTP\"}} 200", LABELS),
+            format!("rustping_http_response_seconds{{{},sensor=\"HTTP\"}} 0.15", LABELS),
+            format!("rustping_check_latency_seconds{{{},sensor=\"TCP 22\"}} 0.005", LABELS),
+            "# TYPE rustping_checks_total counter".to_string(),
+            "rustping_checks_total 4".to_string(),
+            "rustping_check_errors_total 1".to_string(),
+            "rustping_emails_sent_total 1".to_string(),
+            "rustping_email_failures_total 0".to_string(),
+        ] {
+            assert!(lines.contains(&line.as_str()), "missing {:?} in\n{}", line, text);
+        }
+        // Families without a value still get their help text
+        assert!(lines.contains(&"# TYPE rustping_bandwidth_mbps gauge"));
+        assert!(!text.contains("rustping_bandwidth_mbps{"));
+        assert!(!text.contains("rustping_certificate_expiry_days{"));
+    }
+
+    #[test]
+    fn escapes_label_values() {
+        assert_eq!(escape(r#"a\b"c"#), r#"a\\b\"c"#);
+        assert_eq!(escape("two\nlines"), "two\\nlines");
+        assert_eq!(escape("plain"), "plain");
+    }
+
+    #[test]
+    fn scrapes_need_the_token_when_set() {
+        assert!(Metrics::new(None).allows(None));
+        assert!(Metrics::new(Some(String::new())).allows(None));
+
+        let metrics = Metrics::new(Some("secret".to_string()));
+        assert!(metrics.allows(Some("Bearer secret")));
+        assert!(!metrics.allows(Some("Bearer wrong")));
+        assert!(!metrics.allows(Some("secret")));
+        assert!(!metrics.allows(None));
+    }
+}
@@ -1,16 +1,526 @@
 // src/models.rs
+use chrono::{DateTime, Local, NaiveDate, TimeZone};
 use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use utoipa::ToSchema;
+use uuid::Uuid;
 
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)] // Add Eq
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, ToSchema)]
 pub enum SensorType {
     Ping,
     Http,
     Https,
     Bandwidth,
+    // Serialized as {"Tcp": {"port": 22, ...}}; the plain sensors above stay bare strings.
+    Tcp {
+        port: u16,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timeout_ms: Option<u64>,
+        // Optional payload written right after connecting (e.g. "PING\r\n" for Redis)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        send: Option<String>,
+        // Optional regex the banner / response must match (e.g. "^SSH-2.0")
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        expect: Option<String>,
+    },
+    Udp {
+        port: u16,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timeout_ms: Option<u64>,
+        // Request datagram, plain text or hex prefixed with "hex:" (e.g. "hex:ff00")
+        #[serde(default)]
+        payload: String,
+        // Bytes the reply must contain, in the same format as `payload`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        expect: Option<String>,
+    },
+    // Queries the device itself as a DNS server
+    Dns {
+        name: String,
+        #[serde(default)]
+        record_type: DnsRecordType,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        port: Option<u16>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timeout_ms: Option<u64>,
+        // Value one of the answers must equal (an address, host name or TXT string)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        expect: Option<String>,
+    },
+    // Certificate check on an explicit port, for TLS services other than the web server
+    Tls {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        port: Option<u16>,
+        // Name to send as SNI and match against the certificate; defaults to the device IP
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        server_name: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timeout_ms: Option<u64>,
+        // Days before expiry at which to warn
+        #[serde(default = "default_warn_days")]
+        warn_days: Vec<i64>,
+    },
+    // GET and/or WALK OIDs over SNMP v2c or v3 and check the values
+    Snmp {
+        // Label for logs, e.g. "ups" shows up as "SNMP ups"
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+        #[serde(flatten)]
+        auth: SnmpAuth,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        get: Vec<String>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        walk: Vec<String>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        thresholds: Vec<SnmpThreshold>,
+    },
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+pub fn default_warn_days() -> Vec<i64> {
+    vec![30, 14, 7]
+}
+
+/// How to reach an SNMP agent: v2c with a community, or v3 when `v3` is set.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, ToSchema)]
+pub struct SnmpAuth {
+    #[serde(default = "default_community")]
+    pub community: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub v3: Option<SnmpV3>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+}
+
+fn default_community() -> String {
+    "public".to_string()
+}
+
+/// SNMPv3 user. Leave out the passwords for noAuthNoPriv, or just the
+/// privacy password for authNoPriv.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, ToSchema)]
+pub struct SnmpV3 {
+    pub username: String,
+    #[serde(default)]
+    pub auth_protocol: SnmpAuthProtocol,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth_password: Option<String>,
+    #[serde(default)]
+    pub privacy_protocol: SnmpPrivacyProtocol,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub privacy_password: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context: Option<String>,
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, ToSchema)]
+pub enum SnmpAuthProtocol {
+    MD5,
+    #[default]
+    SHA1,
+    SHA224,
+    SHA256,
+    SHA384,
+    SHA512,
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, ToSchema)]
+pub enum SnmpPrivacyProtocol {
+    DES,
+    #[default]
+    AES128,
+    AES192,
+    AES256,
+}
+
+/// Rule applied to every value at or below `oid`, so one rule can cover a
+/// whole walked column (e.g. every ifOperStatus must equal 1).
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, ToSchema)]
+pub struct SnmpThreshold {
+    pub oid: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub equals: Option<String>,
+}
+
+// Named after the record types so devices.json reads naturally
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, ToSchema)]
+pub enum DnsRecordType {
+    #[default]
+    A,
+    AAAA,
+    MX,
+    TXT,
+    CNAME,
+}
+
+impl SensorType {
+    /// Label of the certificate check an HTTPS sensor runs besides its request.
+    pub const HTTPS_CERTIFICATE: &'static str = "HTTPS certificate";
+
+    /// The label of the result the sensor adds to `Device::checks`. The same as its
+    /// own label, except for HTTPS whose request result is kept in `http_result`.
+    pub fn check_label(&self) -> String {
+        match self {
+            SensorType::Https => Self::HTTPS_CERTIFICATE.to_string(),
+            _ => self.to_string(),
+        }
+    }
+
+    /// The variant name, as used in devices.json.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            SensorType::Ping => "Ping",
+            SensorType::Http => "Http",
+            SensorType::Https => "Https",
+            SensorType::Bandwidth => "Bandwidth",
+            SensorType::Tcp { .. } => "Tcp",
+            SensorType::Udp { .. } => "Udp",
+            SensorType::Dns { .. } => "Dns",
+            SensorType::Tls { .. } => "Tls",
+            SensorType::Snmp { .. } => "Snmp",
+        }
+    }
+}
+
+impl fmt::Display for SensorType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SensorType::Ping => write!(f, "Ping"),
+            SensorType::Http => write!(f, "HTTP"),
+            SensorType::Https => write!(f, "HTTPS"),
+            SensorType::Bandwidth => write!(f, "Bandwidth"),
+            SensorType::Tcp { port, .. } => write!(f, "TCP {}", port),
+            SensorType::Udp { port, .. } => write!(f, "UDP {}", port),
+            SensorType::Dns { name, record_type, .. } => write!(f, "DNS {:?} {}", record_type, name),
+            SensorType::Tls { port, .. } => write!(f, "TLS {}", port.unwrap_or(443)),
+            SensorType::Snmp { name: Some(name), .. } => write!(f, "SNMP {}", name),
+            SensorType::Snmp { .. } => write!(f, "SNMP"),
+        }
+    }
+}
+
+/// Request settings and assertions for the HTTP/HTTPS sensors.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct HttpOptions {
+    // GET when not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub method: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+    // Any 2xx when empty. Redirects are not followed if a 3xx code is listed.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub expected_status: Vec<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub contains: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub not_contains: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub regex: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub json: Option<JsonAssertion>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_response_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+}
+
+/// Value a JSONPath expression (e.g. `$.checks[0].status`) must equal.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct JsonAssertion {
+    pub path: String,
+    pub equals: serde_json::Value,
+}
+
+/// Where the Bandwidth sensor gets its figures from.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub enum BandwidthSource {
+    // Counters of a network interface on the machine running RustPing (/proc/net/dev)
+    Interface { name: String },
+    // ifHCInOctets/ifHCOutOctets of one interface on the device itself
+    Snmp {
+        if_index: u32,
+        #[serde(flatten)]
+        auth: SnmpAuth,
+    },
+    // Timed download of a file
+    Download {
+        url: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        max_bytes: Option<u64>,
+    },
+}
+
+/// Throughput measured by the Bandwidth sensor.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct BandwidthRates {
+    pub in_mbps: f64,
+    // Not known for downloads
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub out_mbps: Option<f64>,
+}
+
+impl BandwidthRates {
+    pub fn total_mbps(&self) -> f64 {
+        self.in_mbps + self.out_mbps.unwrap_or_default()
+    }
+}
+
+/// Where the time went in the last HTTP check. With redirects, the
+/// DNS/connect/TLS figures add up over every hop.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct HttpTiming {
+    pub dns_ms: f64,
+    pub connect_ms: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls_ms: Option<f64>,
+    // From the start of the request until the final response headers arrived
+    pub ttfb_ms: f64,
+    pub total_ms: f64,
+    pub size_bytes: u64,
+    pub final_url: String,
+    #[serde(default)]
+    pub redirects: usize,
+}
+
+/// Details of the certificate presented during a TLS handshake.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CertificateInfo {
+    pub subject: String,
+    pub issuer: String,
+    pub sans: Vec<String>,
+    pub not_after: String,
+    pub days_until_expiry: i64,
+    // Subjects from the leaf up to the last certificate the server sent
+    pub chain: Vec<String>,
+    pub trusted: bool,
+    pub hostname_matches: bool,
+}
+
+/// Outcome of a single sensor check that has no dedicated field on `Device`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct CheckResult {
+    pub sensor: String,
+    pub success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<f64>,
+    // Banner or response received from the device
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    // Set when the check passed but needs attention, e.g. a certificate close to expiry
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub warning: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub certificate: Option<CertificateInfo>,
+    // Values read by the check, keyed by OID for SNMP
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metrics: BTreeMap<String, serde_json::Value>,
+}
+
+impl CheckResult {
+    pub fn failed(sensor: &SensorType, error: String) -> Self {
+        Self {
+            sensor: sensor.check_label(),
+            success: false,
+            error: Some(error),
+            ..Default::default()
+        }
+    }
+
+    // Short status used in the log file and notifications
+    pub fn status(&self) -> &'static str {
+        match (self.success, &self.warning) {
+            (false, _) => "FAIL",
+            (true, Some(_)) => "WARN",
+            (true, None) => "OK",
+        }
+    }
+}
+
+/// Summary of one round of ICMP echo probes.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct PingResult {
+    pub sent: u16,
+    pub received: u16,
+    pub loss_percent: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_rtt_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub avg_rtt_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_rtt_ms: Option<f64>,
+    // Mean deviation of the round-trip times from their average.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jitter_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<u8>,
+}
+
+impl PingResult {
+    pub fn from_rtts(sent: u16, rtts_ms: &[f64], ttl: Option<u8>) -> Self {
+        let received = rtts_ms.len() as u16;
+        let loss_percent = if sent > 0 {
+            (sent - received) as f64 / sent as f64 * 100.0
+        } else {
+            100.0
+        };
+
+        if rtts_ms.is_empty() {
+            return Self { sent, received, loss_percent, ttl, ..Default::default() };
+        }
+
+        let min = rtts_ms.iter().cloned().fold(f64::INFINITY, f64::min);
+        let max = rtts_ms.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+        let avg = rtts_ms.iter().sum::<f64>() / rtts_ms.len() as f64;
+        let jitter = rtts_ms.iter().map(|rtt| (rtt - avg).abs()).sum::<f64>() / rtts_ms.len() as f64;
+
+        Self {
+            sent,
+            received,
+            loss_percent,
+            min_rtt_ms: Some(min),
+            avg_rtt_ms: Some(avg),
+            max_rtt_ms: Some(max),
+            jitter_ms: Some(jitter),
+            ttl,
+        }
+    }
+
+    // Up if more than 50% of the probes were answered
+    pub fn is_up(&self) -> bool {
+        self.received * 2 > self.sent
+    }
+
+    // Up, but some probes were lost along the way
+    pub fn is_degraded(&self) -> bool {
+        self.is_up() && self.received < self.sent
+    }
+}
+
+/// How often and how persistently to check. Unset fields fall back from the
+/// sensor to its device, then to `monitor_config.json`, then to the sensor's
+/// built-in defaults.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct CheckSettings {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interval_secs: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+    // Extra attempts after a failure; for Ping, extra echo requests per round
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retries: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_delay_ms: Option<u64>,
+}
+
+impl CheckSettings {
+    /// These settings, with the unset ones taken from `fallback`.
+    pub fn or(&self, fallback: &CheckSettings) -> CheckSettings {
+        CheckSettings {
+            interval_secs: self.interval_secs.or(fallback.interval_secs),
+            timeout_ms: self.timeout_ms.or(fallback.timeout_ms),
+            retries: self.retries.or(fallback.retries),
+            retry_delay_ms: self.retry_delay_ms.or(fallback.retry_delay_ms),
+        }
+    }
+}
+
+/// When a device counts as down or flapping. Unset fields fall back to
+/// `monitor_config.json`, then to the built-in defaults.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct StateSettings {
+    // Consecutive failed rounds before the device goes DOWN
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub down_after: Option<u32>,
+    // Consecutive good rounds before it's UP again
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub up_after: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flap_window_secs: Option<u64>,
+    // Changes between UP and DOWN within the window that make it FLAPPING; 0 turns this off
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flap_threshold: Option<u32>,
+}
+
+impl StateSettings {
+    /// These settings, with the unset ones taken from `fallback`.
+    pub fn or(&self, fallback: &StateSettings) -> StateSettings {
+        StateSettings {
+            down_after: self.down_after.or(fallback.down_after),
+            up_after: self.up_after.or(fallback.up_after),
+            flap_window_secs: self.flap_window_secs.or(fallback.flap_window_secs),
+            flap_threshold: self.flap_threshold.or(fallback.flap_threshold),
+        }
+    }
+}
+
+/// Overall state of a device, after the up/down thresholds and flap detection.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum DeviceState {
+    Up,
+    Down,
+    Flapping,
+    // Down, but so is a device it depends on
+    Unreachable,
+    // In a maintenance window; checks still run but don't alert
+    Maintenance,
+}
+
+impl fmt::Display for DeviceState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceState::Up => write!(f, "UP"),
+            DeviceState::Down => write!(f, "DOWN"),
+            DeviceState::Flapping => write!(f, "FLAPPING"),
+            DeviceState::Unreachable => write!(f, "UNREACHABLE"),
+            DeviceState::Maintenance => write!(f, "MAINTENANCE"),
+        }
+    }
+}
+
+impl FromStr for DeviceState {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "UP" => Ok(DeviceState::Up),
+            "DOWN" => Ok(DeviceState::Down),
+            "FLAPPING" => Ok(DeviceState::Flapping),
+            "UNREACHABLE" => Ok(DeviceState::Unreachable),
+            "MAINTENANCE" => Ok(DeviceState::Maintenance),
+            _ => Err(format!("Unknown device state: {}", s)),
+        }
+    }
+}
+
+/// The maintenance window a device is currently in.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct MaintenanceStatus {
+    pub window_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    pub until: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct Device {
+    // Stays the same when the device is renamed or moved to another address
+    #[serde(default = "Uuid::new_v4")]
+    pub id: Uuid,
     pub name: String,
     pub ip: String,
     pub category: String,  // Add this field
@@ -20,7 +530,642 @@ pub struct Device {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub ping_status: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub ping_result: Option<PingResult>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http: Option<HttpOptions>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub http_status: Option<bool>,
+    // Status code, response time and the failed assertion, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http_result: Option<CheckResult>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http_timing: Option<HttpTiming>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bandwidth: Option<BandwidthSource>,
+    // In + out, in Mbps
     #[serde(skip_serializing_if = "Option::is_none")]
     pub bandwidth_usage: Option<f64>,
-}
\ No newline at end of file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bandwidth_rates: Option<BandwidthRates>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub checks: Vec<CheckResult>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub state: Option<DeviceState>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub maintenance: Option<MaintenanceStatus>,
+    // When `state` last changed, for the time spent in each state
+    #[serde(skip)]
+    pub state_since: Option<DateTime<Local>>,
+    // The same for each sensor, by label
+    #[serde(skip)]
+    pub sensor_since: BTreeMap<String, DateTime<Local>>,
+    // When each sensor, by label, last ran
+    #[serde(skip)]
+    pub checked_at: BTreeMap<String, DateTime<Local>>,
+    // Ids of the devices this one is reached through, e.g. its switch or WAN router
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub depends_on: Vec<Uuid>,
+    #[serde(flatten)]
+    pub settings: CheckSettings,
+    // Overrides keyed by sensor label ("TCP 22") or kind ("Tcp")
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub sensor_settings: BTreeMap<String, CheckSettings>,
+    #[serde(flatten)]
+    pub state_settings: StateSettings,
+}
+
+impl Device {
+    /// Take over the latest results of `other`, leaving the configuration alone.
+    pub fn copy_results_from(&mut self, other: &Device) {
+        self.ping_status = other.ping_status;
+        self.ping_result = other.ping_result.clone();
+        self.checks = other.checks.clone();
+        self.bandwidth_usage = other.bandwidth_usage;
+        self.bandwidth_rates = other.bandwidth_rates.clone();
+        self.http_status = other.http_status;
+        self.http_result = other.http_result.clone();
+        self.http_timing = other.http_timing.clone();
+        self.state = other.state;
+        self.state_since = other.state_since;
+        self.checked_at = other.checked_at.clone();
+        self.maintenance = other.maintenance.clone();
+    }
+
+    /// Take over the results of `other`, the same device before a reload or an edit,
+    /// along with when each of its sensors last changed.
+    pub fn carry_over_from(&mut self, other: &Device) {
+        self.copy_results_from(other);
+        self.sensor_since = other.sensor_since.clone();
+    }
+
+    /// Each sensor with a result, by label, and whether it passed.
+    pub fn sensor_states(&self) -> BTreeMap<String, bool> {
+        let mut states = BTreeMap::new();
+        if let Some(up) = self.ping_status {
+            states.insert(SensorType::Ping.to_string(), up);
+        }
+        if let Some(up) = self.http_status {
+            let http = self.sensors.iter().find(|s| matches!(s, SensorType::Http | SensorType::Https));
+            states.insert(http.unwrap_or(&SensorType::Http).to_string(), up);
+        }
+        for check in self.checks.iter() {
+            states.insert(check.sensor.clone(), check.success);
+        }
+        states
+    }
+
+    /// Where each sensor stands now, for the status API.
+    pub fn status_report(&self, now: DateTime<Local>) -> DeviceStatusReport {
+        let record = CheckRecord::new(self, now);
+        let states = self.sensor_states();
+        let seconds_since = |since: Option<&DateTime<Local>>| since.map(|since| (now - *since).num_milliseconds() as f64 / 1000.0);
+        // An HTTPS sensor's certificate check is reported as a sensor of its own
+        let certificates = self.sensors.iter()
+            .filter(|sensor| **sensor == SensorType::Https)
+            .map(|sensor| (sensor.check_label(), sensor));
+        let sensors = self.sensors.iter()
+            .map(|sensor| (sensor.to_string(), sensor))
+            .chain(certificates)
+            .map(|(label, sensor)| {
+                let check = match sensor {
+                    SensorType::Http | SensorType::Https if label == sensor.to_string() => self.http_result.as_ref(),
+                    _ => self.checks.iter().find(|c| c.sensor == label),
+                };
+                let (latency_ms, error) = match sensor {
+                    SensorType::Ping => (
+                        self.ping_result.as_ref().and_then(|r| r.avg_rtt_ms),
+                        self.ping_result.as_ref()
+                            .filter(|r| !r.is_up())
+                            .map(|r| format!("{:.0}% packet loss", r.loss_percent)),
+                    ),
+                    _ => (check.and_then(|c| c.latency_ms), check.and_then(|c| c.error.clone())),
+                };
+                let report = SensorStatusReport {
+                    state: states.get(&label).map(|up| if *up { DeviceState::Up } else { DeviceState::Down }),
+                    last_check: self.checked_at.get(&sensor.to_string()).copied(),
+                    since: self.sensor_since.get(&label).copied(),
+                    seconds_in_state: seconds_since(self.sensor_since.get(&label)),
+                    latency_ms,
+                    error,
+                    warning: check.and_then(|c| c.warning.clone()),
+                };
+                (label, report)
+            })
+            .collect();
+        DeviceStatusReport {
+            id: self.id,
+            name: self.name.clone(),
+            ip: self.ip.clone(),
+            category: self.category.clone(),
+            state: self.state,
+            since: self.state_since,
+            seconds_in_state: seconds_since(self.state_since.as_ref()),
+            last_check: self.checked_at.values().max().copied(),
+            down: matches!(self.state, Some(DeviceState::Down) | Some(DeviceState::Unreachable)),
+            ping_status: record.ping.clone(),
+            http_status: record.http.clone(),
+            bandwidth_usage: record.bandwidth_text(),
+            latency_ms: record.latency_ms,
+            maintenance: self.maintenance.clone(),
+            sensors,
+        }
+    }
+
+    /// Whether Ping, HTTP or any other check failed in the latest results.
+    pub fn is_failing(&self) -> bool {
+        self.ping_status == Some(false) || self.http_status == Some(false) || self.checks.iter().any(|c| !c.success)
+    }
+
+    /// Settings for one of the device's sensors, before global defaults.
+    pub fn settings_for(&self, sensor: &SensorType) -> CheckSettings {
+        self.sensor_settings.get(&sensor.to_string())
+            .or_else(|| self.sensor_settings.get(sensor.kind()))
+            .map_or_else(|| self.settings.clone(), |settings| settings.or(&self.settings))
+    }
+}
+
+/// A device's current state, as served by `/api/devices/status`.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct DeviceStatusReport {
+    pub id: Uuid,
+    pub name: String,
+    pub ip: String,
+    pub category: String,
+    pub state: Option<DeviceState>,
+    // When the device entered `state`
+    pub since: Option<DateTime<Local>>,
+    pub seconds_in_state: Option<f64>,
+    pub last_check: Option<DateTime<Local>>,
+    // DOWN or UNREACHABLE
+    pub down: bool,
+    // As in the log: OK, DEGRADED, FAIL or N/A
+    pub ping_status: String,
+    pub http_status: String,
+    pub bandwidth_usage: String,
+    pub latency_ms: Option<f64>,
+    pub maintenance: Option<MaintenanceStatus>,
+    // By sensor label, e.g. "TCP 22"
+    pub sensors: BTreeMap<String, SensorStatusReport>,
+}
+
+/// One sensor's latest result. `state` is UP or DOWN, or None before its first check.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct SensorStatusReport {
+    pub state: Option<DeviceState>,
+    pub last_check: Option<DateTime<Local>>,
+    pub since: Option<DateTime<Local>>,
+    pub seconds_in_state: Option<f64>,
+    pub latency_ms: Option<f64>,
+    pub error: Option<String>,
+    pub warning: Option<String>,
+}
+
+/// One logged round of checks on a device, as kept in the log file and the database.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CheckRecord {
+    pub timestamp: DateTime<Local>,
+    // None in logs written before devices had ids
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_id: Option<Uuid>,
+    pub device: String,
+    pub ip: String,
+    #[serde(default)]
+    pub category: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub state: Option<DeviceState>,
+    // OK, DEGRADED, FAIL or N/A
+    pub ping: String,
+    // OK, FAIL or N/A
+    pub http: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bandwidth: Option<BandwidthRates>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jitter_ms: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub loss_percent: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http_timing: Option<HttpTiming>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub checks: Vec<CheckResult>,
+}
+
+impl CheckRecord {
+    /// The latest results of `device`.
+    pub fn new(device: &Device, timestamp: DateTime<Local>) -> Self {
+        let ping = match device.ping_status {
+            Some(true) if device.ping_result.as_ref().is_some_and(|r| r.is_degraded()) => "DEGRADED",
+            Some(true) => "OK",
+            Some(false) => "FAIL",
+            None => "N/A",
+        };
+        // Format HTTP status based on sensor configuration
+        let http = if device.sensors.contains(&SensorType::Http) || device.sensors.contains(&SensorType::Https) {
+            device.http_status.map_or("FAIL", |s| if s { "OK" } else { "FAIL" })
+        } else {
+            "N/A"
+        };
+        Self {
+            timestamp,
+            device_id: Some(device.id),
+            device: device.name.clone(),
+            ip: device.ip.clone(),
+            category: device.category.clone(),
+            state: device.state,
+            ping: ping.to_string(),
+            http: http.to_string(),
+            bandwidth: device.bandwidth_rates.clone(),
+            latency_ms: device.ping_result.as_ref().and_then(|r| r.avg_rtt_ms),
+            jitter_ms: device.ping_result.as_ref().and_then(|r| r.jitter_ms),
+            loss_percent: device.ping_result.as_ref().map(|r| r.loss_percent),
+            http_timing: if http == "N/A" { None } else { device.http_timing.clone() },
+            checks: device.checks.clone(),
+        }
+    }
+
+    pub fn bandwidth_text(&self) -> String {
+        match self.bandwidth {
+            Some(ref rates) => match rates.out_mbps {
+                Some(out) => format!("In {:.2} Mbps / Out {:.2} Mbps", rates.in_mbps, out),
+                None => format!("{:.2} Mbps", rates.in_mbps),
+            },
+            None => "N/A".to_string(),
+        }
+    }
+
+    pub fn state_text(&self) -> String {
+        self.state.map_or("N/A".to_string(), |state| state.to_string())
+    }
+
+    pub fn latency_text(&self) -> String {
+        ms_text(self.latency_ms)
+    }
+
+    pub fn jitter_text(&self) -> String {
+        ms_text(self.jitter_ms)
+    }
+
+    pub fn loss_text(&self) -> String {
+        self.loss_percent.map_or("N/A".to_string(), |loss| format!("{:.0}%", loss))
+    }
+
+    // Response timing breakdown from the last HTTP check
+    pub fn http_timing_text(&self) -> Option<String> {
+        self.http_timing.as_ref().map(|t| format!(
+            "DNS {:.2} ms / Connect {:.2} ms / TLS {} / TTFB {:.2} ms / Total {:.2} ms / {} B",
+            t.dns_ms, t.connect_ms, ms_text(t.tls_ms), t.ttfb_ms, t.total_ms, t.size_bytes
+        ))
+    }
+
+    /// Whether the device was down, leaving out failures during maintenance.
+    pub fn is_down(&self) -> bool {
+        self.ping == "FAIL" && self.state != Some(DeviceState::Maintenance)
+    }
+
+    /// Still answering, but dropping some of the probes.
+    pub fn is_degraded(&self) -> bool {
+        self.ping == "DEGRADED" && self.state != Some(DeviceState::Maintenance)
+    }
+}
+
+fn ms_text(value: Option<f64>) -> String {
+    value.map_or("N/A".to_string(), |v| format!("{:.2} ms", v))
+}
+
+// The line the log file used to have, now used for text exports
+impl fmt::Display for CheckRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} - {} ({}): State: {}, Ping: {}, HTTP: {}, Bandwidth: {}, Latency: {}, Jitter: {}, Loss: {}",
+            self.timestamp.format("%Y-%m-%d %H:%M:%S"),
+            self.device,
+            self.ip,
+            self.state_text(),
+            self.ping,
+            self.http,
+            self.bandwidth_text(),
+            self.latency_text(),
+            self.jitter_text(),
+            self.loss_text(),
+        )?;
+        if let (Some(timing), Some(ref t)) = (self.http_timing_text(), &self.http_timing) {
+            write!(f, ", HTTP Timing: {}", timing)?;
+            if t.redirects > 0 {
+                write!(f, ", Final URL: {}", t.final_url)?;
+            }
+        }
+        // Additional sensor checks, e.g. ", TCP 22: OK" or ", HTTPS certificate: WARN"
+        for check in self.checks.iter() {
+            write!(f, ", {}: {}", check.sensor, check.status())?;
+        }
+        Ok(())
+    }
+}
+
+/// A device, or one of its sensors, moving from one state to another.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct StateChange {
+    pub timestamp: DateTime<Local>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_id: Option<Uuid>,
+    pub device: String,
+    pub ip: String,
+    #[serde(default)]
+    pub category: String,
+    // The sensor's label, or None for the device as a whole. Sensors are only UP or DOWN.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sensor: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub old_state: Option<DeviceState>,
+    pub new_state: DeviceState,
+    // How long the device had been in `old_state`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_secs: Option<f64>,
+}
+
+/// A device's check rounds over five minutes or an hour, maintenance left out.
+#[derive(Debug, Clone)]
+pub struct Rollup {
+    pub device_id: Uuid,
+    pub device: String,
+    pub ip: String,
+    pub category: String,
+    // 300 or 3600
+    pub period_secs: i64,
+    pub start: DateTime<Local>,
+    pub samples: u32,
+    // Rounds in which nothing failed
+    pub up_samples: u32,
+    // Rounds with a Ping round-trip time
+    pub latency_samples: u32,
+    pub latency_min_ms: Option<f64>,
+    pub latency_avg_ms: Option<f64>,
+    pub latency_max_ms: Option<f64>,
+}
+
+impl Rollup {
+    pub const FIVE_MINUTES: i64 = 300;
+    pub const HOURLY: i64 = 3600;
+
+    /// An empty rollup for the period `time` falls in.
+    pub fn new(device: &Device, period_secs: i64, time: DateTime<Local>) -> Self {
+        Self {
+            device_id: device.id,
+            device: device.name.clone(),
+            ip: device.ip.clone(),
+            category: device.category.clone(),
+            period_secs,
+            start: period_start(time, period_secs),
+            samples: 0,
+            up_samples: 0,
+            latency_samples: 0,
+            latency_min_ms: None,
+            latency_avg_ms: None,
+            latency_max_ms: None,
+        }
+    }
+
+    pub fn end(&self) -> DateTime<Local> {
+        self.start + chrono::Duration::seconds(self.period_secs)
+    }
+
+    pub fn add(&mut self, up: bool, latency_ms: Option<f64>) {
+        self.merge(&Rollup {
+            samples: 1,
+            up_samples: up as u32,
+            latency_samples: latency_ms.is_some() as u32,
+            latency_min_ms: latency_ms,
+            latency_avg_ms: latency_ms,
+            latency_max_ms: latency_ms,
+            ..self.clone()
+        });
+    }
+
+    /// Take in the rounds of `other`, e.g. a five minute rollup within this hour.
+    pub fn merge(&mut self, other: &Rollup) {
+        let latency_samples = self.latency_samples + other.latency_samples;
+        if latency_samples > 0 {
+            let total = |rollup: &Rollup| rollup.latency_avg_ms.unwrap_or(0.0) * rollup.latency_samples as f64;
+            self.latency_avg_ms = Some((total(self) + total(other)) / latency_samples as f64);
+        }
+        self.latency_min_ms = [self.latency_min_ms, other.latency_min_ms].into_iter().flatten().reduce(f64::min);
+        self.latency_max_ms = [self.latency_max_ms, other.latency_max_ms].into_iter().flatten().reduce(f64::max);
+        self.latency_samples = latency_samples;
+        self.samples += other.samples;
+        self.up_samples += other.up_samples;
+    }
+
+    pub fn uptime_percent(&self) -> Option<f64> {
+        (self.samples > 0).then(|| self.up_samples as f64 * 100.0 / self.samples as f64)
+    }
+}
+
+/// The start of the `period_secs` long period, counted from local midnight, that `time` falls in.
+pub fn period_start(time: DateTime<Local>, period_secs: i64) -> DateTime<Local> {
+    let offset = time.offset().local_minus_utc() as i64;
+    let seconds = time.timestamp() - (time.timestamp() + offset).rem_euclid(period_secs);
+    Local.timestamp_opt(seconds, 0).single().unwrap_or(time)
+}
+
+/// Time range and device filters for the log APIs.
+#[derive(Debug, Clone, Default)]
+pub struct LogFilter {
+    pub start: Option<DateTime<Local>>,
+    // Exclusive
+    pub end: Option<DateTime<Local>>,
+    // Lowercase parts of device names, IP addresses or ids, any of which may match
+    pub devices: Option<Vec<String>>,
+}
+
+impl LogFilter {
+    /// From query parameters; dates are YYYY-MM-DD, both days included, and devices
+    /// a comma separated list.
+    pub fn from_query(devices: Option<&str>, start_date: Option<&str>, end_date: Option<&str>) -> Self {
+        let date = |s: &str| NaiveDate::parse_from_str(s, "%Y-%m-%d").ok();
+        Self {
+            start: start_date.and_then(date).and_then(local_midnight),
+            end: end_date.and_then(date).and_then(|date| date.succ_opt()).and_then(local_midnight),
+            devices: devices.map(|d| {
+                d.split(',')
+                    .map(|s| s.trim().to_lowercase())
+                    .filter(|s| !s.is_empty())
+                    .collect::<Vec<_>>()
+            }).filter(|devices| !devices.is_empty()),
+        }
+    }
+
+    /// Whether a record from `time` for the device with this id, name and IP passes the filter.
+    pub fn matches(&self, time: DateTime<Local>, id: Option<Uuid>, name: &str, ip: &str) -> bool {
+        if self.start.is_some_and(|start| time < start) || self.end.is_some_and(|end| time >= end) {
+            return false;
+        }
+        let (name, ip) = (name.to_lowercase(), ip.to_lowercase());
+        let id = id.map(|id| id.to_string()).unwrap_or_default();
+        self.devices.as_ref().is_none_or(|filters| {
+            filters.iter().any(|f| name.contains(f.as_str()) || ip.contains(f.as_str()) || id.contains(f.as_str()))
+        })
+    }
+}
+
+/// Which state changes to list, on top of a `LogFilter`.
+#[derive(Debug, Clone, Default)]
+pub struct ChangeFilter {
+    // A sensor's label, ignoring case, or "device" for changes of whole devices
+    pub sensor: Option<String>,
+    // Only changes into this state
+    pub state: Option<DeviceState>,
+}
+
+impl ChangeFilter {
+    pub fn matches(&self, change: &StateChange) -> bool {
+        let sensor = match self.sensor {
+            Some(ref sensor) if sensor.eq_ignore_ascii_case("device") => change.sensor.is_none(),
+            Some(ref sensor) => change.sensor.as_ref().is_some_and(|label| label.eq_ignore_ascii_case(sensor)),
+            None => true,
+        };
+        sensor && self.state.is_none_or(|state| change.new_state == state)
+    }
+}
+
+/// Part of a longer list: `limit` items from `offset` on.
+#[derive(Debug, Clone, Copy)]
+pub struct Paging {
+    pub offset: usize,
+    pub limit: usize,
+}
+
+impl Paging {
+    /// The items of `items` on this page.
+    pub fn apply<T>(&self, items: Vec<T>) -> Vec<T> {
+        items.into_iter().skip(self.offset).take(self.limit).collect()
+    }
+}
+
+fn local_midnight(date: NaiveDate) -> Option<DateTime<Local>> {
+    Local.from_local_datetime(&date.and_hms_opt(0, 0, 0)?).earliest()
+}
+
+/// A dashboard login, as listed in `static/config.js`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct User {
+    pub username: String,
+    // SHA-256 of the password, hex encoded
+    pub password_hash: String,
+    #[serde(default = "default_role")]
+    pub role: String,
+}
+
+fn default_role() -> String {
+    "admin".to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn local(hour: u32, minute: u32, second: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(2024, 3, 5, hour, minute, second).unwrap()
+    }
+
+    fn rollup(samples: u32, up_samples: u32, latencies: &[f64]) -> Rollup {
+        let mut rollup = Rollup {
+            device_id: Uuid::nil(),
+            device: "Router".to_string(),
+            ip: "10.0.0.1".to_string(),
+            category: "core".to_string(),
+            period_secs: Rollup::FIVE_MINUTES,
+            start: local(10, 0, 0),
+            samples: 0,
+            up_samples: 0,
+            latency_samples: 0,
+            latency_min_ms: None,
+            latency_avg_ms: None,
+            latency_max_ms: None,
+        };
+        for latency in latencies {
+            rollup.add(true, Some(*latency));
+        }
+        for _ in latencies.len()..samples as usize {
+            rollup.add(true, None);
+        }
+        rollup.up_samples = up_samples;
+        rollup
+    }
+
+    #[test]
+    fn rollups_merge_weighted_by_samples() {
+        let mut hour = rollup(3, 3, &[5.0, 15.0]);
+        hour.merge(&rollup(6, 4, &[12.0, 20.0, 20.0, 20.0, 20.0, 28.0]));
+        assert_eq!((hour.samples, hour.up_samples, hour.latency_samples), (9, 7, 8));
+        // (5 + 15 + 12 + 4 * 20 + 28) / 8, rather than the mean of the two averages
+        assert_eq!(hour.latency_avg_ms, Some(17.5));
+        assert_eq!((hour.latency_min_ms, hour.latency_max_ms), (Some(5.0), Some(28.0)));
+    }
+
+    #[test]
+    fn rollups_merge_without_latencies() {
+        // Rounds without a Ping leave the latencies alone
+        let mut hour = rollup(2, 2, &[8.0, 12.0]);
+        hour.merge(&rollup(4, 0, &[]));
+        assert_eq!((hour.samples, hour.latency_samples), (6, 2));
+        assert_eq!((hour.latency_min_ms, hour.latency_avg_ms, hour.latency_max_ms), (Some(8.0), Some(10.0), Some(12.0)));
+        assert_eq!(hour.uptime_percent(), Some(2.0 * 100.0 / 6.0));
+
+        let mut empty = rollup(0, 0, &[]);
+        empty.merge(&rollup(1, 1, &[]));
+        assert_eq!((empty.latency_min_ms, empty.latency_avg_ms, empty.latency_max_ms), (None, None, None));
+        assert_eq!(rollup(0, 0, &[]).uptime_percent(), None);
+    }
+
+    #[test]
+    fn periods_start_on_their_boundary() {
+        assert_eq!(period_start(local(10, 0, 0), Rollup::FIVE_MINUTES), local(10, 0, 0));
+        assert_eq!(period_start(local(10, 4, 59), Rollup::FIVE_MINUTES), local(10, 0, 0));
+        assert_eq!(period_start(local(10, 5, 0), Rollup::FIVE_MINUTES), local(10, 5, 0));
+        assert_eq!(period_start(local(10, 59, 59), Rollup::HOURLY), local(10, 0, 0));
+        assert_eq!(period_start(local(11, 0, 0), Rollup::HOURLY), local(11, 0, 0));
+        // Counted from local midnight rather than UTC's
+        assert_eq!(period_start(local(0, 30, 0), 86400), local(0, 0, 0));
+        assert_eq!(period_start(local(23, 59, 59), 86400), local(0, 0, 0));
+    }
+
+    #[test]
+    fn ping_with_every_probe_lost() {
+        let result = PingResult::from_rtts(3, &[], None);
+        assert_eq!((result.sent, result.received, result.loss_percent), (3, 0, 100.0));
+        assert_eq!((result.min_rtt_ms, result.avg_rtt_ms, result.max_rtt_ms, result.jitter_ms), (None, None, None, None));
+        assert!(!result.is_up() && !result.is_degraded());
+        assert_eq!(PingResult::from_rtts(0, &[], None).loss_percent, 100.0);
+    }
+
+    #[test]
+    fn ping_with_a_single_reply() {
+        let result = PingResult::from_rtts(3, &[12.5], Some(57));
+        assert_eq!(result.received, 1);
+        assert!((result.loss_percent - 200.0 / 3.0).abs() < 1e-9);
+        assert_eq!((result.min_rtt_ms, result.avg_rtt_ms, result.max_rtt_ms), (Some(12.5), Some(12.5), Some(12.5)));
+        assert_eq!((result.jitter_ms, result.ttl), (Some(0.0), Some(57)));
+        // One reply out of three is not enough
+        assert!(!result.is_up());
+        assert!(PingResult::from_rtts(1, &[12.5], None).is_up());
+    }
+
+    #[test]
+    fn ping_with_some_probes_lost() {
+        let result = PingResult::from_rtts(4, &[10.0, 30.0, 20.0], Some(64));
+        assert_eq!((result.received, result.loss_percent), (3, 25.0));
+        assert_eq!((result.min_rtt_ms, result.avg_rtt_ms, result.max_rtt_ms), (Some(10.0), Some(20.0), Some(30.0)));
+        // Mean deviation from the average: (10 + 10 + 0) / 3
+        assert!((result.jitter_ms.unwrap() - 20.0 / 3.0).abs() < 1e-9);
+        assert!(result.is_up() && result.is_degraded());
+
+        let result = PingResult::from_rtts(2, &[5.0, 5.0], None);
+        assert_eq!((result.loss_percent, result.jitter_ms), (0.0, Some(0.0)));
+        assert!(result.is_up() && !result.is_degraded());
+        // Half the probes answered isn't a majority
+        assert!(!PingResult::from_rtts(4, &[1.0, 2.0], None).is_up());
+    }
+}
@@ -0,0 +1,923 @@
+// src/monitor.rs
+use chrono::{DateTime, Local};
+use log::{debug, error};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::future::Future;
+use std::sync::Arc;
+use tokio::sync::{broadcast, mpsc, Semaphore};
+use tokio::time::{sleep, sleep_until, Duration, Instant};
+use uuid::Uuid;
+use crate::config::{CheckResults, MonitorConfig};
+use crate::storage::{Storage, StorageWriter};
+use crate::email::{self, EmailService};
+use crate::log_file::{LogEvent, LogFile};
+use crate::maintenance::MaintenanceService;
+use crate::metrics::Metrics;
+use crate::models::{
+    period_start, CheckRecord, CheckResult, CheckSettings, Device, DeviceState, LogFilter, Rollup, SensorType,
+    StateChange, StateSettings,
+};
+use crate::sensors::{
+    monitor_bandwidth, monitor_dns, monitor_http, monitor_ping, monitor_snmp, monitor_tcp, monitor_tls,
+    monitor_udp, CounterSample,
+};
+use crate::{SharedDevices, LOG_FILE};
+
+const DEFAULT_INTERVAL_SECS: u64 = 5;
+const DEFAULT_RETRY_DELAY: Duration = Duration::from_secs(1);
+// A single failed round takes a device down unless the configuration asks for more
+const DEFAULT_DOWN_AFTER: u32 = 1;
+const DEFAULT_UP_AFTER: u32 = 1;
+const DEFAULT_FLAP_WINDOW_SECS: u64 = 600;
+const DEFAULT_FLAP_THRESHOLD: u32 = 5;
+// How often the device list is checked for new devices
+const RESCAN_INTERVAL: Duration = Duration::from_secs(1);
+
+// Define a struct to track device status.
+#[derive(Debug, Clone)]
+struct DeviceStatus {
+    ping_status: Option<bool>,
+    // Previous interface counters for the Bandwidth sensor
+    last_counters: Option<CounterSample>,
+    // When each sensor, by label, should run next
+    sensor_due: HashMap<String, Instant>,
+    // UP or DOWN after the thresholds, and the rounds so far that disagree with it
+    up: Option<bool>,
+    streak: u32,
+    // When the device went UP or DOWN, for flap detection
+    transitions: VecDeque<Instant>,
+    state: Option<DeviceState>,
+}
+
+impl DeviceStatus {
+    // Carry on from the device's last known state, e.g. from before a restart
+    fn new(device: &Device) -> Self {
+        let (up, state) = match device.state {
+            Some(DeviceState::Up) => (Some(true), Some(DeviceState::Up)),
+            Some(DeviceState::Down) | Some(DeviceState::Unreachable) => (Some(false), Some(DeviceState::Down)),
+            _ => (None, None),
+        };
+        Self {
+            ping_status: device.ping_status,
+            last_counters: None,
+            sensor_due: HashMap::new(),
+            up,
+            streak: 0,
+            transitions: VecDeque::new(),
+            state,
+        }
+    }
+
+    fn update_ping(&mut self, new_status: bool) -> bool {
+        let changed = self.ping_status != Some(new_status);
+        self.ping_status = Some(new_status);
+        changed
+    }
+
+    // Count one round towards going UP or DOWN; true when the reported state changed
+    fn update_state(&mut self, healthy: bool, settings: &StateSettings, now: Instant) -> bool {
+        match self.up {
+            // The first result decides right away
+            None => self.up = Some(healthy),
+            Some(up) if up == healthy => self.streak = 0,
+            Some(up) => {
+                self.streak += 1;
+                let needed = if up {
+                    settings.down_after.unwrap_or(DEFAULT_DOWN_AFTER)
+                } else {
+                    settings.up_after.unwrap_or(DEFAULT_UP_AFTER)
+                };
+                if self.streak >= needed {
+                    self.up = Some(healthy);
+                    self.streak = 0;
+                    self.transitions.push_back(now);
+                }
+            }
+        }
+
+        let window = Duration::from_secs(settings.flap_window_secs.unwrap_or(DEFAULT_FLAP_WINDOW_SECS));
+        while self.transitions.front().is_some_and(|at| now.duration_since(*at) > window) {
+            self.transitions.pop_front();
+        }
+        let threshold = settings.flap_threshold.unwrap_or(DEFAULT_FLAP_THRESHOLD) as usize;
+
+        let state = if threshold > 0 && self.transitions.len() >= threshold {
+            DeviceState::Flapping
+        } else if self.up == Some(true) {
+            DeviceState::Up
+        } else {
+            DeviceState::Down
+        };
+        let changed = self.state != Some(state);
+        self.state = Some(state);
+        changed
+    }
+
+    // The earliest time one of the sensors is due
+    fn next_due(&self) -> Instant {
+        self.sensor_due.values().copied().min()
+            .unwrap_or_else(|| Instant::now() + Duration::from_secs(DEFAULT_INTERVAL_SECS))
+    }
+}
+
+// The outcome of one round of checks on a device
+struct Probe {
+    device: Device,
+    status: DeviceStatus,
+    changed: bool,
+    // A check started warning this round
+    new_warning: bool,
+    // Average Ping round-trip time, when Ping ran this round
+    latency_ms: Option<f64>,
+    // Checks that ran this round, an HTTPS sensor's certificate and response separately,
+    // how many of them failed and how long the round took
+    checks: usize,
+    errors: usize,
+    duration: Duration,
+    started_at: DateTime<Local>,
+    // Set by the scheduler once the device's parents have been checked again for it
+    parents_checked: bool,
+}
+
+// Where results go: the database, the log file if there is one and anyone listening
+// for events, with check results only as often as `check_results` says
+struct Recorder {
+    storage: StorageWriter,
+    log_file: Option<LogFile>,
+    events: broadcast::Sender<LogEvent>,
+    check_results: CheckResults,
+}
+
+impl Recorder {
+    fn state_change(&self, change: StateChange) {
+        self.storage.insert_state_change(change.clone());
+        self.publish(LogEvent::StateChange(Box::new(change)));
+    }
+
+    fn check(&self, record: &CheckRecord, changed: bool) {
+        let wanted = match self.check_results {
+            CheckResults::All => true,
+            CheckResults::Changes => changed,
+            CheckResults::Off => false,
+        };
+        if !wanted {
+            return;
+        }
+        self.storage.insert_check(record.clone());
+        self.publish(LogEvent::Check(Box::new(record.clone())));
+    }
+
+    fn publish(&self, event: LogEvent) {
+        // Only the scheduler task writes here, so entries never interleave
+        if let Some(ref log_file) = self.log_file {
+            log_file.append(&event);
+        }
+        // Nobody may be listening
+        let _ = self.events.send(event);
+    }
+}
+
+// Global fallbacks from monitor_config.json
+struct Defaults {
+    checks: CheckSettings,
+    states: StateSettings,
+}
+
+/// Probe every sensor of every device at its own interval, running at most
+/// `max_concurrent_checks` devices at a time so a slow device or a large
+/// fleet doesn't hold up the others. Returns once `shutdown` completes and
+/// what has been collected so far is stored.
+#[allow(clippy::too_many_arguments)]
+pub async fn run(
+    devices: SharedDevices,
+    email_service: Arc<EmailService>,
+    maintenance: Arc<MaintenanceService>,
+    storage: Arc<dyn Storage>,
+    events: broadcast::Sender<LogEvent>,
+    metrics: Arc<Metrics>,
+    config: MonitorConfig,
+    shutdown: impl Future<Output = ()>,
+) {
+    let permits = Arc::new(Semaphore::new(config.max_concurrent_checks));
+    let defaults = Arc::new(Defaults { checks: config.defaults, states: config.state_defaults });
+    let (results_tx, mut results) = mpsc::unbounded_channel::<Probe>();
+    // The current five minutes of each device, carrying on from those stored at the last shutdown
+    let mut rollups = current_rollups(storage.as_ref()).await;
+    let recorder = Recorder {
+        storage: StorageWriter::spawn(storage),
+        log_file: config.log_file.then(|| LogFile::new(LOG_FILE, config.log_rotation)),
+        events,
+        check_results: config.check_results,
+    };
+
+    // Everything per device is kept by its id, as several devices may share an address
+    let mut statuses: HashMap<Uuid, DeviceStatus> = HashMap::new();
+    let mut next_due: HashMap<Uuid, Instant> = HashMap::new();
+    let mut in_flight: HashSet<Uuid> = HashSet::new();
+    // Devices that started failing, and the parents being checked again before they're
+    // judged; none left once they all have been
+    let mut settling: HashMap<Uuid, HashSet<Uuid>> = HashMap::new();
+    tokio::pin!(shutdown);
+
+    loop {
+        let now = Instant::now();
+
+        // Store the rollups whose five minutes are over
+        let finished: Vec<Uuid> = rollups.iter()
+            .filter(|(_, rollup)| rollup.end() <= Local::now())
+            .map(|(id, _)| *id)
+            .collect();
+        if !finished.is_empty() {
+            recorder.storage.save_rollups(finished.iter().filter_map(|id| rollups.remove(id)).collect());
+        }
+        let windows = maintenance.active().await;
+        let devices_to_monitor: Vec<Device> = {
+            let mut devices_locked = devices.lock().await;
+            // Check devices right away when a window starts or ends, so that going into
+            // and out of maintenance is recorded like any other change of state
+            for device in devices_locked.iter_mut() {
+                let window = windows.for_device(device);
+                if device.maintenance.is_some() != window.is_some() {
+                    next_due.insert(device.id, now);
+                    if let Some(status) = statuses.get_mut(&device.id) {
+                        status.sensor_due.clear();
+                    }
+                }
+                device.maintenance = window;
+            }
+            devices_locked.clone()
+        };
+
+        // Forget devices that have been removed
+        next_due.retain(|id, _| devices_to_monitor.iter().any(|d| d.id == *id));
+        statuses.retain(|id, _| devices_to_monitor.iter().any(|d| d.id == *id));
+        settling.retain(|id, parents| {
+            parents.retain(|parent| devices_to_monitor.iter().any(|d| d.id == *parent));
+            devices_to_monitor.iter().any(|d| d.id == *id)
+        });
+
+        // Start every device with a sensor due that is not still being probed
+        for dev in devices_to_monitor {
+            if in_flight.contains(&dev.id) || next_due.get(&dev.id).is_some_and(|due| *due > now) {
+                continue;
+            }
+            in_flight.insert(dev.id);
+
+            let status = statuses.remove(&dev.id).unwrap_or_else(|| DeviceStatus::new(&dev));
+            let permits = permits.clone();
+            let defaults = defaults.clone();
+            let results_tx = results_tx.clone();
+            tokio::spawn(async move {
+                let Ok(_permit) = permits.acquire_owned().await else { return };
+                let _ = results_tx.send(probe_device(dev, status, &defaults).await);
+            });
+        }
+
+        // Record results until the next sensor is due, looking for new devices now and then
+        let wake = next_due.iter()
+            .filter(|(id, _)| !in_flight.contains(*id))
+            .map(|(_, due)| *due)
+            .fold(now + RESCAN_INTERVAL, Instant::min);
+        let timer = sleep_until(wake);
+        tokio::pin!(timer);
+        loop {
+            tokio::select! {
+                _ = &mut timer => break,
+                _ = &mut shutdown => {
+                    // Keep the five minutes so far rather than losing them
+                    recorder.storage.save_rollups(rollups.into_values().collect());
+                    recorder.storage.flush().await;
+                    return;
+                }
+                Some(mut probe) = results.recv() => {
+                    let id = probe.device.id;
+                    if settling.get(&id).is_some_and(|parents| parents.is_empty()) {
+                        settling.remove(&id);
+                        probe.parents_checked = true;
+                    }
+                    let due = probe.status.next_due();
+                    in_flight.remove(&id);
+                    next_due.insert(id, due);
+                    statuses.insert(id, probe.status.clone());
+                    metrics.record_round(id, probe.checks, probe.errors, probe.duration);
+                    // Time in maintenance doesn't count against uptime
+                    if probe.device.maintenance.is_none() {
+                        let at = Local::now();
+                        let rollup = rollups.entry(id).or_insert_with(|| Rollup::new(&probe.device, Rollup::FIVE_MINUTES, at));
+                        if rollup.end() <= at {
+                            recorder.storage.save_rollups(vec![rollup.clone()]);
+                            *rollup = Rollup::new(&probe.device, Rollup::FIVE_MINUTES, at);
+                        }
+                        rollup.add(!probe.device.is_failing(), probe.latency_ms);
+                    }
+                    let stale_parents = record(&devices, &email_service, &maintenance, &metrics, &recorder, probe).await;
+                    let mut start_now = !stale_parents.is_empty();
+                    // Check the parents right away, and the device again once they've all reported
+                    for parent in stale_parents.iter() {
+                        next_due.insert(*parent, Instant::now());
+                        if let Some(status) = statuses.get_mut(parent) {
+                            status.sensor_due.clear();
+                        }
+                    }
+                    if !stale_parents.is_empty() {
+                        settling.insert(id, stale_parents.into_iter().collect());
+                    }
+                    for (child, parents) in settling.iter_mut() {
+                        if parents.remove(&id) && parents.is_empty() {
+                            next_due.insert(*child, Instant::now());
+                            if let Some(status) = statuses.get_mut(child) {
+                                status.sensor_due.clear();
+                            }
+                            start_now = true;
+                        }
+                    }
+                    // The probe took longer than the interval, so start it again right away
+                    if due <= Instant::now() || start_now {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+async fn current_rollups(storage: &dyn Storage) -> HashMap<Uuid, Rollup> {
+    let filter = LogFilter {
+        start: Some(period_start(Local::now(), Rollup::FIVE_MINUTES)),
+        end: None,
+        devices: None,
+    };
+    match storage.rollups(Rollup::FIVE_MINUTES, &filter).await {
+        Ok(rollups) => rollups.into_iter().map(|rollup| (rollup.device_id, rollup)).collect(),
+        Err(e) => {
+            error!("Failed to load the current rollups: {:#}", e);
+            HashMap::new()
+        }
+    }
+}
+
+fn interval(settings: &CheckSettings) -> Duration {
+    Duration::from_secs(settings.interval_secs.unwrap_or(DEFAULT_INTERVAL_SECS).max(1))
+}
+
+// Fill in the timeout of sensors that don't set their own
+fn with_timeout(sensor: &SensorType, timeout: Option<u64>) -> SensorType {
+    let mut sensor = sensor.clone();
+    match sensor {
+        SensorType::Tcp { ref mut timeout_ms, .. }
+        | SensorType::Udp { ref mut timeout_ms, .. }
+        | SensorType::Dns { ref mut timeout_ms, .. }
+        | SensorType::Tls { ref mut timeout_ms, .. } => *timeout_ms = timeout_ms.or(timeout),
+        SensorType::Snmp { ref mut auth, .. } => auth.timeout_ms = auth.timeout_ms.or(timeout),
+        _ => {}
+    }
+    sensor
+}
+
+// Run `check` again after a failure, up to `retries` more times
+async fn with_retries<T, F, Fut>(settings: &CheckSettings, failed: impl Fn(&T) -> bool, mut check: F) -> T
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = T>,
+{
+    let delay = settings.retry_delay_ms.map_or(DEFAULT_RETRY_DELAY, Duration::from_millis);
+    let mut result = check().await;
+    for attempt in 1..=settings.retries.unwrap_or(0) {
+        if !failed(&result) {
+            break;
+        }
+        sleep(delay).await;
+        debug!("Retry {} of a failed check", attempt);
+        result = check().await;
+    }
+    result
+}
+
+async fn probe_device(mut dev: Device, mut status: DeviceStatus, defaults: &Defaults) -> Probe {
+    let started = Instant::now();
+    let started_at = Local::now();
+    let mut changed = false;
+    // Whether something that ran this round failed, as opposed to an older result
+    let mut fresh_failure = false;
+    let mut latency_ms = None;
+    // Checks run this round and how many of them failed, for the metrics
+    let (mut ran, mut failed) = (0, 0);
+
+    // Only the sensors whose interval has passed run this round
+    let due: Vec<SensorType> = dev.sensors.iter()
+        .filter(|sensor| status.sensor_due.get(&sensor.to_string()).is_none_or(|at| *at <= started))
+        .cloned()
+        .collect();
+    let settings = |dev: &Device, sensor: &SensorType| dev.settings_for(sensor).or(&defaults.checks);
+
+    // First check ping (only for devices that use the Ping sensor)
+    if !dev.sensors.contains(&SensorType::Ping) {
+        dev.ping_result = None;
+    } else if due.contains(&SensorType::Ping) {
+        let result = monitor_ping(&dev.ip, &settings(&dev, &SensorType::Ping)).await;
+        if status.update_ping(result.is_up()) {
+            changed = true;
+        }
+        fresh_failure |= !result.is_up();
+        ran += 1;
+        failed += !result.is_up() as usize;
+        latency_ms = result.avg_rtt_ms;
+        dev.ping_result = Some(result);
+    }
+    dev.ping_status = status.ping_status;
+
+    // Port checks run regardless of ping, since many devices block ICMP
+    let mut checks = Vec::new();
+    for sensor in dev.sensors.iter() {
+        if !matches!(sensor, SensorType::Tcp { .. } | SensorType::Udp { .. } | SensorType::Dns { .. }
+            | SensorType::Snmp { .. } | SensorType::Https | SensorType::Tls { .. })
+        {
+            continue;
+        }
+        if !due.contains(sensor) {
+            // Keep the last result until the sensor is due again
+            let label = sensor.check_label();
+            checks.extend(dev.checks.iter().find(|c| c.sensor == label).cloned());
+            continue;
+        }
+
+        let sensor_settings = settings(&dev, sensor);
+        let sensor = with_timeout(sensor, sensor_settings.timeout_ms);
+        let result = with_retries(&sensor_settings, |result: &CheckResult| !result.success, || async {
+            match sensor {
+                SensorType::Tcp { .. } => monitor_tcp(&dev.ip, &sensor).await,
+                SensorType::Udp { .. } => monitor_udp(&dev.ip, &sensor).await,
+                SensorType::Dns { .. } => monitor_dns(&dev.ip, &sensor).await,
+                SensorType::Snmp { .. } => monitor_snmp(&dev.ip, &sensor).await,
+                _ => monitor_tls(&dev.ip, &sensor, dev.http_path.as_deref()).await,
+            }
+        }).await;
+        fresh_failure |= !result.success;
+        ran += 1;
+        failed += !result.success as usize;
+        checks.push(result);
+    }
+
+    // Log whenever a check goes up or down, or starts/stops warning
+    let checks_changed = checks.len() != dev.checks.len()
+        || checks.iter().zip(dev.checks.iter())
+            .any(|(new, old)| new.sensor != old.sensor || new.status() != old.status()
+                || new.warning != old.warning);
+    if checks_changed {
+        changed = true;
+    }
+    // Warnings are mailed once, when they first show up
+    let new_warning = checks.iter().any(|new| {
+        new.status() == "WARN" && !dev.checks.iter().any(|old| old.sensor == new.sensor && old.status() == "WARN")
+    });
+    dev.checks = checks;
+
+    if !dev.sensors.contains(&SensorType::Bandwidth) {
+        dev.bandwidth_usage = None;
+        dev.bandwidth_rates = None;
+    } else if due.contains(&SensorType::Bandwidth) {
+        let bandwidth_settings = settings(&dev, &SensorType::Bandwidth);
+        let delay = bandwidth_settings.retry_delay_ms.map_or(DEFAULT_RETRY_DELAY, Duration::from_millis);
+        let mut result = monitor_bandwidth(&dev, &mut status.last_counters, bandwidth_settings.timeout_ms).await;
+        for _ in 0..bandwidth_settings.retries.unwrap_or(0) {
+            if result.is_ok() {
+                break;
+            }
+            sleep(delay).await;
+            result = monitor_bandwidth(&dev, &mut status.last_counters, bandwidth_settings.timeout_ms).await;
+        }
+        ran += 1;
+        failed += result.is_err() as usize;
+        let bandwidth = result.ok().flatten();
+        let bandwidth_usage = bandwidth.as_ref().map(|rates| rates.total_mbps());
+        if bandwidth_usage.is_some() != dev.bandwidth_usage.is_some() {
+            changed = true;
+        }
+        dev.bandwidth_usage = bandwidth_usage;
+        dev.bandwidth_rates = bandwidth;
+    }
+
+    // Check HTTP if configured and ping is successful (or not monitored)
+    let http_sensor = dev.sensors.iter()
+        .find(|sensor| matches!(sensor, SensorType::Http | SensorType::Https))
+        .cloned();
+    if let Some(http_sensor) = http_sensor {
+        if dev.ping_status == Some(false) {
+            // If ping fails, mark HTTP as down
+            if dev.http_status != Some(false) {
+                changed = true;
+            }
+            dev.http_status = Some(false);
+            dev.http_result = None;
+            dev.http_timing = None;
+            fresh_failure = true;
+        } else if due.contains(&http_sensor) {
+            if let Some(ref url) = dev.http_path {
+                let http_settings = settings(&dev, &http_sensor);
+                let mut options = dev.http.clone().unwrap_or_default();
+                options.timeout_ms = options.timeout_ms.or(http_settings.timeout_ms);
+                let (result, timing) = with_retries(
+                    &http_settings,
+                    |(result, _): &(CheckResult, _)| !result.success,
+                    || monitor_http(url, Some(&options)),
+                ).await;
+                if dev.http_status != Some(result.success) {
+                    changed = true;
+                }
+                dev.http_timing = timing;
+                dev.http_status = Some(result.success);
+                fresh_failure |= !result.success;
+                ran += 1;
+                failed += !result.success as usize;
+                dev.http_result = Some(result);
+            }
+        }
+    }
+
+    // Schedule the sensors that just ran
+    let checked_at = Local::now();
+    for sensor in due.iter() {
+        status.sensor_due.insert(sensor.to_string(), started + interval(&settings(&dev, sensor)));
+        dev.checked_at.insert(sensor.to_string(), checked_at);
+    }
+    status.sensor_due.retain(|label, _| dev.sensors.iter().any(|sensor| sensor.to_string() == *label));
+    dev.checked_at.retain(|label, _| dev.sensors.iter().any(|sensor| sensor.to_string() == *label));
+
+    // Only move towards UP once nothing is failing any more, older results included
+    let failing = dev.is_failing();
+    let state_settings = dev.state_settings.or(&defaults.states);
+    let state_changed = match (fresh_failure, failing) {
+        (true, _) => status.update_state(false, &state_settings, Instant::now()),
+        (false, false) => status.update_state(true, &state_settings, Instant::now()),
+        (false, true) => false,
+    };
+    dev.state = status.state;
+
+    Probe {
+        device: dev,
+        status,
+        changed: changed || state_changed,
+        new_warning,
+        latency_ms,
+        checks: ran,
+        errors: failed,
+        duration: started.elapsed(),
+        started_at,
+        parents_checked: false,
+    }
+}
+
+// Copy a round's results onto the shared device, returning a change for each sensor
+// that passed before and failed now or the other way round
+fn apply_results(device: &mut Device, result: &Device, now: DateTime<Local>) -> Vec<StateChange> {
+    let previous_state = device.state;
+    let previous_sensors = device.sensor_states();
+    device.copy_results_from(result);
+    if device.state != previous_state {
+        device.state_since = Some(now);
+    }
+    device.sensor_states().into_iter()
+        .filter(|(sensor, up)| previous_sensors.get(sensor) != Some(up))
+        .map(|(sensor, up)| {
+            let since = device.sensor_since.insert(sensor.clone(), now);
+            StateChange {
+                timestamp: now,
+                device_id: Some(device.id),
+                device: device.name.clone(),
+                ip: device.ip.clone(),
+                category: device.category.clone(),
+                old_state: previous_sensors.get(&sensor).map(|up| sensor_state(*up)),
+                new_state: sensor_state(up),
+                duration_secs: duration_secs(since, now),
+                sensor: Some(sensor),
+            }
+        })
+        .collect()
+}
+
+// Seconds spent in the previous state, when it's known when that started
+fn duration_secs(since: Option<DateTime<Local>>, now: DateTime<Local>) -> Option<f64> {
+    since.map(|since| (now - since).num_milliseconds() as f64 / 1000.0)
+}
+
+fn sensor_state(up: bool) -> DeviceState {
+    if up { DeviceState::Up } else { DeviceState::Down }
+}
+
+// Whether a device listed in `depends_on` is down itself or unreachable behind its own
+// parent, or None when some of them haven't been checked yet
+fn parent_state(devices: &[Device], depends_on: &[Uuid]) -> Option<bool> {
+    let parents: Vec<&Device> = devices.iter().filter(|d| depends_on.contains(&d.id)).collect();
+    let down = |d: &Device| match d.state {
+        Some(DeviceState::Down) | Some(DeviceState::Unreachable) => true,
+        // A parent being worked on may well be why its children are failing
+        Some(DeviceState::Maintenance) => d.is_failing(),
+        _ => false,
+    };
+    if parents.iter().any(|d| down(d)) {
+        Some(true)
+    } else if parents.iter().any(|d| d.state.is_none()) {
+        None
+    } else {
+        Some(false)
+    }
+}
+
+// Parents in `depends_on` whose last check finished before `time`
+fn checked_before(devices: &[Device], depends_on: &[Uuid], time: DateTime<Local>) -> Vec<Uuid> {
+    devices.iter()
+        .filter(|d| depends_on.contains(&d.id))
+        .filter(|d| d.checked_at.values().max().is_none_or(|checked| *checked < time))
+        .map(|d| d.id)
+        .collect()
+}
+
+// Copy the results onto the shared device, then log, store and notify when something changed.
+// Returns the parents to check again before the device can be judged.
+async fn record(
+    devices: &SharedDevices,
+    email_service: &Arc<EmailService>,
+    maintenance: &MaintenanceService,
+    metrics: &Arc<Metrics>,
+    recorder: &Recorder,
+    probe: Probe,
+) -> Vec<Uuid> {
+    let Probe { device: mut result, mut changed, new_warning, started_at, parents_checked, .. } = probe;
+    result.maintenance = maintenance.active_for(&result).await;
+    let now = Local::now();
+    let mut stale_parents = Vec::new();
+    let (dev, previous_state, previous_since, sensor_changes) = {
+        let mut devices_locked = devices.lock().await;
+        let current_state = devices_locked.iter().find(|d| d.id == result.id).and_then(|d| d.state);
+        // Behind a parent that is down, a failing device is unreachable rather than down.
+        // Until its parents have been checked we can't tell which, so don't say yet.
+        if result.state == Some(DeviceState::Down) {
+            result.state = match parent_state(&devices_locked, &result.depends_on) {
+                Some(true) => Some(DeviceState::Unreachable),
+                // Parents that looked fine before this round may have failed since, so
+                // check them again before the device goes DOWN and alerts
+                Some(false) if current_state != Some(DeviceState::Down) && !parents_checked => {
+                    stale_parents = checked_before(&devices_locked, &result.depends_on, started_at);
+                    if stale_parents.is_empty() { Some(DeviceState::Down) } else { current_state }
+                }
+                Some(false) => Some(DeviceState::Down),
+                None => None,
+            };
+        }
+        if result.maintenance.is_some() {
+            result.state = Some(DeviceState::Maintenance);
+        }
+        // The device may have been removed or edited while it was being probed
+        let Some(device) = devices_locked.iter_mut().find(|d| d.id == result.id) else { return Vec::new() };
+        let previous_state = device.state;
+        let previous_since = device.state_since;
+        let sensor_changes = apply_results(device, &result, now);
+        (device.clone(), previous_state, previous_since, sensor_changes)
+    };
+    for change in sensor_changes {
+        recorder.state_change(change);
+    }
+    if dev.state != previous_state {
+        changed = true;
+        if let Some(new_state) = dev.state {
+            recorder.state_change(StateChange {
+                timestamp: now,
+                device_id: Some(dev.id),
+                device: dev.name.clone(),
+                ip: dev.ip.clone(),
+                category: dev.category.clone(),
+                sensor: None,
+                old_state: previous_state,
+                new_state,
+                duration_secs: duration_secs(previous_since, now),
+            });
+        }
+    }
+
+    let record = CheckRecord::new(&dev, now);
+    recorder.check(&record, changed);
+    if !changed {
+        return stale_parents;
+    }
+    recorder.storage.save_device(dev.clone());
+
+    // Mail when the device goes DOWN, including once its parent or a maintenance window
+    // is over but it's still failing, and when a check starts warning. Flapping and
+    // unreachable devices and those in maintenance stay quiet.
+    let notify = match dev.state {
+        Some(DeviceState::Down) => previous_state != Some(DeviceState::Down) || new_warning,
+        Some(DeviceState::Up) => new_warning,
+        Some(DeviceState::Flapping) | Some(DeviceState::Unreachable) | Some(DeviceState::Maintenance) | None => false,
+    };
+
+    // Send email notification when the device goes DOWN or a check starts warning
+    if notify {
+        // Create LogData for email
+        let log_data = email::LogData {
+            date: now.format("%Y-%m-%d").to_string(),
+            time: now.format("%H:%M:%S").to_string(),
+            ping_status: record.ping.clone(),
+            http_status: match dev.http_result.as_ref().and_then(|r| r.error.as_ref()) {
+                Some(error) if record.http == "FAIL" => format!("FAIL ({})", error),
+                _ => record.http.clone(),
+            },
+            bandwidth: record.bandwidth_text(),
+            checks: dev.checks.iter()
+                .map(|c| match c.error.as_ref().or(c.warning.as_ref()) {
+                    Some(detail) => format!("{}: {} ({})", c.sensor, c.status(), detail),
+                    None => format!("{}: {}", c.sensor, c.status()),
+                })
+                .collect::<Vec<_>>()
+                .join(", "),
+            state: dev.state.map_or("N/A".to_string(), |state| state.to_string()),
+        };
+
+        // Send email notification in a separate task to avoid blocking
+        let device_name = dev.name.clone();
+        let email_service = email_service.clone();
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+            let result = email_service.send_email(&device_name, &log_data).await;
+            metrics.record_email(result.is_ok());
+            if let Err(e) = result {
+                error!("Failed to send email notification: {}", e);
+            }
+        });
+    }
+    stale_parents
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_status() -> DeviceStatus {
+        let device: Device = serde_json::from_str(r#"{"name":"a","ip":"10.0.0.1","category":"c","sensors":[]}"#).unwrap();
+        DeviceStatus::new(&device)
+    }
+
+    fn settings(down_after: u32, up_after: u32) -> StateSettings {
+        StateSettings { down_after: Some(down_after), up_after: Some(up_after), ..Default::default() }
+    }
+
+    // Feed rounds at `at` seconds after `start`, returning the state after each
+    fn rounds(status: &mut DeviceStatus, settings: &StateSettings, start: Instant, rounds: &[(u64, bool)]) -> Vec<DeviceState> {
+        rounds.iter()
+            .map(|(at, healthy)| {
+                status.update_state(*healthy, settings, start + Duration::from_secs(*at));
+                status.state.unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn first_result_decides_right_away() {
+        let mut status = new_status();
+        assert!(status.update_state(false, &settings(3, 3), Instant::now()));
+        assert_eq!(status.state, Some(DeviceState::Down));
+
+        let mut status = new_status();
+        assert!(status.update_state(true, &settings(3, 3), Instant::now()));
+        assert_eq!(status.state, Some(DeviceState::Up));
+    }
+
+    #[test]
+    fn one_failed_round_takes_a_device_down_by_default() {
+        let mut status = new_status();
+        let start = Instant::now();
+        let states = rounds(&mut status, &StateSettings::default(), start, &[(0, true), (5, false), (10, true)]);
+        assert_eq!(states, [DeviceState::Up, DeviceState::Down, DeviceState::Up]);
+    }
+
+    #[test]
+    fn down_after_and_up_after_count_consecutive_rounds() {
+        let mut status = new_status();
+        let start = Instant::now();
+        let states = rounds(&mut status, &settings(3, 2), start, &[
+            (0, true),
+            (5, false),
+            (10, false),
+            // A good round starts the count again
+            (15, true),
+            (20, false),
+            (25, false),
+            (30, false),
+            (35, true),
+            (40, true),
+        ]);
+        use DeviceState::{Down, Up};
+        assert_eq!(states, [Up, Up, Up, Up, Up, Up, Down, Down, Up]);
+    }
+
+    #[test]
+    fn only_changes_of_the_reported_state_count_as_changed() {
+        let mut status = new_status();
+        let settings = settings(2, 1);
+        let now = Instant::now();
+        assert!(status.update_state(true, &settings, now));
+        assert!(!status.update_state(false, &settings, now + Duration::from_secs(5)));
+        assert!(status.update_state(false, &settings, now + Duration::from_secs(10)));
+        assert!(!status.update_state(false, &settings, now + Duration::from_secs(15)));
+    }
+
+    #[test]
+    fn five_changes_within_ten_minutes_are_flapping() {
+        let mut status = new_status();
+        let start = Instant::now();
+        let states = rounds(&mut status, &StateSettings::default(), start, &[
+            (0, true),
+            (60, false),
+            (120, true),
+            (180, false),
+            (240, true),
+        ]);
+        assert_eq!(states.last(), Some(&DeviceState::Up));
+        // The fifth change within 600 seconds
+        let states = rounds(&mut status, &StateSettings::default(), start, &[(300, false)]);
+        assert_eq!(states, [DeviceState::Flapping]);
+        // Further changes don't report UP or DOWN while it flaps
+        let states = rounds(&mut status, &StateSettings::default(), start, &[(360, true), (420, false)]);
+        assert_eq!(states, [DeviceState::Flapping, DeviceState::Flapping]);
+    }
+
+    #[test]
+    fn changes_spread_over_more_than_the_window_are_not_flapping() {
+        let mut status = new_status();
+        let start = Instant::now();
+        let states = rounds(&mut status, &StateSettings::default(), start, &[
+            (0, true),
+            (200, false),
+            (400, true),
+            (600, false),
+            (800, true),
+            (1000, false),
+        ]);
+        assert!(!states.contains(&DeviceState::Flapping));
+    }
+
+    #[test]
+    fn flapping_ends_once_the_changes_leave_the_window() {
+        let mut status = new_status();
+        let start = Instant::now();
+        let rounds_so_far = [(0, true), (10, false), (20, true), (30, false), (40, true), (50, false)];
+        let states = rounds(&mut status, &StateSettings::default(), start, &rounds_so_far);
+        assert_eq!(states.last(), Some(&DeviceState::Flapping));
+
+        // Still within 600 seconds of the first change
+        let states = rounds(&mut status, &StateSettings::default(), start, &[(600, false)]);
+        assert_eq!(states, [DeviceState::Flapping]);
+        // Once the changes are more than 600 seconds old it settles on its real state
+        assert!(status.update_state(false, &StateSettings::default(), start + Duration::from_secs(651)));
+        assert_eq!(status.state, Some(DeviceState::Down));
+    }
+
+    #[test]
+    fn a_flap_threshold_of_zero_turns_detection_off() {
+        let mut status = new_status();
+        let settings = StateSettings { flap_threshold: Some(0), ..Default::default() };
+        let start = Instant::now();
+        let healthy: Vec<(u64, bool)> = (0..20).map(|i| (i * 10, i % 2 == 0)).collect();
+        let states = rounds(&mut status, &settings, start, &healthy);
+        assert!(!states.contains(&DeviceState::Flapping));
+    }
+
+    #[test]
+    fn sensor_change_after_reload_has_duration() {
+        let config = r#"{"name":"a","ip":"10.0.0.1","category":"c","sensors":["Ping"]}"#;
+        let now = Local::now();
+        let mut live: Device = serde_json::from_str(config).unwrap();
+        live.ping_status = Some(true);
+        live.sensor_since.insert("Ping".to_string(), now - chrono::Duration::seconds(90));
+
+        // The device as devices.json is read again
+        let mut reloaded: Device = serde_json::from_str(config).unwrap();
+        reloaded.id = live.id;
+        reloaded.carry_over_from(&live);
+
+        let mut result = reloaded.clone();
+        result.ping_status = Some(false);
+        let changes = apply_results(&mut reloaded, &result, now);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].sensor.as_deref(), Some("Ping"));
+        assert_eq!(changes[0].old_state, Some(DeviceState::Up));
+        assert_eq!(changes[0].new_state, DeviceState::Down);
+        assert_eq!(changes[0].duration_secs, Some(90.0));
+        assert_eq!(reloaded.sensor_since.get("Ping"), Some(&now));
+    }
+
+    #[tokio::test]
+    async fn failed_checks_are_counted_from_their_results() {
+        // A port nothing listens on
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let device: Device = serde_json::from_str(&format!(
+            r#"{{"name":"a","ip":"127.0.0.1","category":"c","sensors":["Https",{{"Tcp":{{"port":{0}}}}}],
+                "http_path":"https://127.0.0.1:{0}/","timeout_ms":1000}}"#,
+            port
+        )).unwrap();
+        let defaults = Defaults { checks: CheckSettings::default(), states: StateSettings::default() };
+        let probe = probe_device(device.clone(), DeviceStatus::new(&device), &defaults).await;
+        // The HTTPS certificate, the HTTPS response and the TCP port
+        assert_eq!((probe.checks, probe.errors), (3, 3));
+        assert_eq!(probe.device.checks.iter().filter(|check| !check.success).count(), 2);
+    }
+}
@@ -1,79 +1,1009 @@
-use std::process::Command;
-use log::{info, error, debug};
-use reqwest;
-use tokio::time::{sleep, Duration};
+use hickory_resolver::config::{NameServerConfigGroup, ResolverConfig, ResolverOpts};
+use hickory_resolver::error::ResolveErrorKind;
+use hickory_resolver::proto::rr::{RData, RecordType};
+use hickory_resolver::TokioAsyncResolver;
+use log::{info, warn, error, debug};
+use regex::Regex;
+use std::collections::BTreeMap;
+use hyper::Method;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, Interest};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::time::{timeout, Duration, Instant};
+use crate::{http_client, icmp, snmp, tls};
+use crate::http_client::HttpRequest;
+use crate::models::{default_warn_days, BandwidthRates, BandwidthSource, CheckResult, CheckSettings, Device, DnsRecordType, HttpOptions, HttpTiming, JsonAssertion, PingResult, SensorType, SnmpAuth, SnmpThreshold};
 
-pub async fn monitor_ping(ip: &str) -> bool {
+const DEFAULT_HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_TCP_TIMEOUT: Duration = Duration::from_secs(5);
+const DEFAULT_UDP_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_DATAGRAM_SIZE: usize = 65535;
+const DEFAULT_DNS_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_BANNER_SIZE: usize = 4096;
+const DEFAULT_TLS_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(30);
+// Three echo requests 200 ms apart, each given 2 seconds to come back
+const DEFAULT_PING_RETRIES: u32 = 2;
+const DEFAULT_PING_TIMEOUT: Duration = Duration::from_secs(2);
+const DEFAULT_PING_INTERVAL: Duration = Duration::from_millis(200);
+const DEFAULT_DOWNLOAD_BYTES: u64 = 10 * 1024 * 1024;
+
+/// Send one round of echo requests: the first plus `retries` more, all of
+/// them counted towards loss and jitter.
+pub async fn monitor_ping(ip: &str, settings: &CheckSettings) -> PingResult {
     debug!("Pinging {}", ip);
+
+    let attempts = settings.retries.unwrap_or(DEFAULT_PING_RETRIES).saturating_add(1).min(u16::MAX as u32) as u16;
+    let reply_timeout = settings.timeout_ms.map_or(DEFAULT_PING_TIMEOUT, Duration::from_millis);
+    let gap = settings.retry_delay_ms.map_or(DEFAULT_PING_INTERVAL, Duration::from_millis);
+
+    let replies = match icmp::ping(ip, attempts, reply_timeout, gap).await {
+        Ok(replies) => replies,
+        Err(e) => {
+            error!("Ping error for {}: {}", ip, e);
+            return PingResult::from_rtts(attempts, &[], None);
+        }
+    };
+
+    for (i, reply) in replies.iter().enumerate() {
+        match reply {
+            Some(reply) => debug!(
+                "Ping attempt {} successful for {}: seq={} rtt={:.2} ms ttl={}",
+                i + 1, ip, reply.seq, reply.rtt.as_secs_f64() * 1000.0,
+                reply.ttl.map_or("N/A".to_string(), |t| t.to_string())
+            ),
+            None => debug!("Ping attempt {} timed out for {}", i + 1, ip),
+        }
+    }
+
+    let rtts: Vec<f64> = replies.iter()
+        .flatten()
+        .map(|reply| reply.rtt.as_secs_f64() * 1000.0)
+        .collect();
+    let ttl = replies.iter().flatten().last().and_then(|reply| reply.ttl);
+    let result = PingResult::from_rtts(attempts, &rtts, ttl);
+
+    if result.is_up() {
+        info!(
+            "Ping UP for {} ({}/{} successful, {:.0}% loss, avg {:.2} ms, jitter {:.2} ms)",
+            ip, result.received, result.sent, result.loss_percent,
+            result.avg_rtt_ms.unwrap_or_default(), result.jitter_ms.unwrap_or_default()
+        );
+    } else {
+        error!(
+            "Ping DOWN for {} ({}/{} failed, {:.0}% loss)",
+            ip, result.sent - result.received, result.sent, result.loss_percent
+        );
+    }
     
-    let mut success_count = 0;
-    let attempts = 3; // Try 3 times before deciding status
-
-    for i in 1..=attempts {
-        let output = if cfg!(target_os = "macos") || cfg!(target_os = "linux") {
-            Command::new("ping")
-                .arg("-c")
-                .arg("1")
-                .arg("-t")  // Use -t for macOS timeout
-                .arg("2")   // 2 second timeout
-                .arg(ip)
-                .output()
+    result
+}
+
+/// Run the HTTP check; the timing breakdown is only there when a response came back.
+pub async fn monitor_http(url: &str, options: Option<&HttpOptions>) -> (CheckResult, Option<HttpTiming>) {
+    debug!("Checking HTTP status for {}", url);
+    let default_options = HttpOptions::default();
+    let options = options.unwrap_or(&default_options);
+    let failed = |error: String| CheckResult { sensor: "HTTP".to_string(), error: Some(error), ..Default::default() };
+
+    let regex = match options.regex.as_deref().map(Regex::new).transpose() {
+        Ok(regex) => regex,
+        Err(e) => {
+            error!("Invalid body regex for {}: {}", url, e);
+            return (failed(format!("Invalid body regex: {}", e)), None);
+        }
+    };
+    let method = match Method::from_bytes(options.method.as_deref().unwrap_or("GET").to_uppercase().as_bytes()) {
+        Ok(method) => method,
+        Err(_) => {
+            let method = options.method.as_deref().unwrap_or_default();
+            return (failed(format!("Invalid HTTP method {:?}", method)), None);
+        }
+    };
+
+    let request = HttpRequest {
+        url,
+        method,
+        headers: &options.headers,
+        body: options.body.as_deref(),
+        // Expecting a redirect only makes sense if we stop at it
+        follow_redirects: !options.expected_status.iter().any(|code| (300..400).contains(code)),
+    };
+    let request_timeout = options.timeout_ms.map_or(DEFAULT_HTTP_TIMEOUT, Duration::from_millis);
+
+    let response = match timeout(request_timeout, http_client::send(request)).await {
+        Ok(Ok(response)) => response,
+        Ok(Err(e)) => {
+            error!("HTTP request failed for {}: {}", url, e);
+            return (failed(e.to_string()), None);
+        }
+        Err(_) => {
+            error!("HTTP request to {} timed out", url);
+            return (failed(format!("Request timed out after {} ms", request_timeout.as_millis())), None);
+        }
+    };
+    let status = response.status;
+    let body = String::from_utf8_lossy(&response.body);
+    let latency_ms = response.timing.total_ms;
+    debug!(
+        "HTTP timing for {}: dns {:.2} ms, connect {:.2} ms, tls {:.2} ms, ttfb {:.2} ms, total {:.2} ms, {} bytes",
+        response.timing.final_url, response.timing.dns_ms, response.timing.connect_ms,
+        response.timing.tls_ms.unwrap_or_default(), response.timing.ttfb_ms, latency_ms, response.timing.size_bytes
+    );
+
+    let mut result = CheckResult {
+        sensor: "HTTP".to_string(),
+        success: true,
+        latency_ms: Some(latency_ms),
+        response: Some(status.to_string()),
+        ..Default::default()
+    };
+
+    let status_ok = if options.expected_status.is_empty() {
+        status.is_success()
+    } else {
+        options.expected_status.contains(&status.as_u16())
+    };
+    result.error = if !status_ok {
+        Some(format!("Unexpected status {}", status))
+    } else if options.contains.as_ref().is_some_and(|text| !body.contains(text.as_str())) {
+        Some(format!("Body does not contain {:?}", options.contains.as_deref().unwrap_or_default()))
+    } else if options.not_contains.as_ref().is_some_and(|text| body.contains(text.as_str())) {
+        Some(format!("Body contains {:?}", options.not_contains.as_deref().unwrap_or_default()))
+    } else if regex.as_ref().is_some_and(|regex| !regex.is_match(&body)) {
+        Some(format!("Body did not match /{}/", options.regex.as_deref().unwrap_or_default()))
+    } else if let Some(ref assertion) = options.json {
+        check_json(&body, assertion).err()
+    } else {
+        None
+    };
+    if result.error.is_none() {
+        if let Some(max_ms) = options.max_response_ms {
+            if latency_ms > max_ms as f64 {
+                result.error = Some(format!("Response took {:.0} ms (limit {} ms)", latency_ms, max_ms));
+            }
+        }
+    }
+    result.success = result.error.is_none();
+
+    if result.success {
+        info!("HTTP check successful for {}: {} ({:.2} ms)", url, status, latency_ms);
+    } else {
+        error!("HTTP check failed for {}: {}", url, result.error.as_deref().unwrap_or("unknown error"));
+    }
+    (result, Some(response.timing))
+}
+
+fn check_json(body: &str, assertion: &JsonAssertion) -> Result<(), String> {
+    let document: serde_json::Value = serde_json::from_str(body)
+        .map_err(|e| format!("Response is not valid JSON: {}", e))?;
+    let actual = json_path(&document, &assertion.path)?
+        .ok_or_else(|| format!("Nothing found at {}", assertion.path))?;
+
+    // Let "200" in the config match a numeric 200 in the response and vice versa
+    let matches = *actual == assertion.equals || match (actual, &assertion.equals) {
+        (serde_json::Value::String(text), other) | (other, serde_json::Value::String(text)) if !other.is_string() => {
+            serde_json::from_str::<serde_json::Value>(text).is_ok_and(|value| value == *other)
+        }
+        _ => false,
+    };
+    if matches {
+        Ok(())
+    } else {
+        Err(format!("{} is {}, expected {}", assertion.path, actual, assertion.equals))
+    }
+}
+
+// Minimal JSONPath: `$.key.nested`, `$.list[0]` and `$['odd key']`, no filters or wildcards.
+// Err for a path that can't be read, None when the document has nothing there.
+fn json_path<'a>(document: &'a serde_json::Value, path: &str) -> Result<Option<&'a serde_json::Value>, String> {
+    let invalid = |reason: &str| format!("Invalid JSON path {}: {}", path, reason);
+    let mut rest = path.trim().strip_prefix('$').unwrap_or(path.trim());
+    // Keep reading the path once nothing is found, so a malformed path is always reported
+    let mut current = Some(document);
+
+    while !rest.is_empty() {
+        current = if let Some(after) = rest.strip_prefix('.') {
+            let end = after.find(['.', '[']).unwrap_or(after.len());
+            if end == 0 {
+                return Err(invalid("empty key"));
+            }
+            rest = &after[end..];
+            current.and_then(|value| value.get(&after[..end]))
+        } else if let Some(after) = rest.strip_prefix('[') {
+            let after = after.trim_start();
+            match after.chars().next().filter(|c| *c == '\'' || *c == '"') {
+                // A quoted key runs to the same quote, which must be followed by ']'
+                Some(quote) => {
+                    let quoted = &after[1..];
+                    let close = quoted.find(quote).ok_or_else(|| invalid("missing closing quote"))?;
+                    rest = quoted[close + 1..].trim_start().strip_prefix(']')
+                        .ok_or_else(|| invalid("missing ']' after quoted key"))?;
+                    current.and_then(|value| value.get(&quoted[..close]))
+                }
+                None => {
+                    let end = after.find(']').ok_or_else(|| invalid("missing ']'"))?;
+                    let index = after[..end].trim().parse::<usize>()
+                        .map_err(|_| invalid("array index is not a number"))?;
+                    rest = &after[end + 1..];
+                    current.and_then(|value| value.get(index))
+                }
+            }
         } else {
-            Command::new("ping")
-                .arg("-n")
-                .arg("1")
-                .arg("-w")
-                .arg("2000")
-                .arg(ip)
-                .output()
+            // Allow a leading bare key, e.g. "status.code"
+            let end = rest.find(['.', '[']).unwrap_or(rest.len());
+            let key = &rest[..end];
+            rest = &rest[end..];
+            current.and_then(|value| value.get(key))
         };
+    }
+    Ok(current)
+}
 
-        match output {
-            Ok(output) => {
-                if output.status.success() {
-                    success_count += 1;
-                    debug!("Ping attempt {} successful for {}", i, ip);
-                } else {
-                    debug!("Ping attempt {} failed for {}", i, ip);
+pub async fn monitor_tcp(host: &str, sensor: &SensorType) -> CheckResult {
+    let SensorType::Tcp { port, timeout_ms, send, expect } = sensor else {
+        return CheckResult::failed(sensor, "Not a TCP sensor".to_string());
+    };
+    debug!("Checking TCP port {} on {}", port, host);
+
+    let expect = match expect.as_deref().map(Regex::new).transpose() {
+        Ok(expect) => expect,
+        Err(e) => {
+            error!("Invalid banner regex for {}:{}: {}", host, port, e);
+            return CheckResult::failed(sensor, format!("Invalid banner regex: {}", e));
+        }
+    };
+    let check_timeout = timeout_ms.map_or(DEFAULT_TCP_TIMEOUT, Duration::from_millis);
+    let deadline = Instant::now() + check_timeout;
+
+    let started = Instant::now();
+    let mut stream = match timeout(check_timeout, TcpStream::connect((host, *port))).await {
+        Ok(Ok(stream)) => stream,
+        Ok(Err(e)) => {
+            error!("TCP connect to {}:{} failed: {}", host, port, e);
+            return CheckResult::failed(sensor, e.to_string());
+        }
+        Err(_) => {
+            error!("TCP connect to {}:{} timed out", host, port);
+            return CheckResult::failed(sensor, format!("Connect timed out after {} ms", check_timeout.as_millis()));
+        }
+    };
+    let latency_ms = started.elapsed().as_secs_f64() * 1000.0;
+
+    let mut result = CheckResult {
+        sensor: sensor.to_string(),
+        success: true,
+        latency_ms: Some(latency_ms),
+        response: None,
+        ..Default::default()
+    };
+
+    if let Some(payload) = send {
+        if let Err(e) = stream.write_all(payload.as_bytes()).await {
+            error!("Failed to send payload to {}:{}: {}", host, port, e);
+            result.success = false;
+            result.error = Some(format!("Failed to send payload: {}", e));
+            return result;
+        }
+    }
+
+    if let Some(expect) = expect {
+        // Keep reading until the banner matches, the peer closes or we run out of time
+        let mut banner = Vec::new();
+        let mut buffer = [0u8; 1024];
+        let matched = loop {
+            let text = String::from_utf8_lossy(&banner);
+            if expect.is_match(&text) {
+                break true;
+            }
+            if banner.len() >= MAX_BANNER_SIZE {
+                break false;
+            }
+            match tokio::time::timeout_at(deadline, stream.read(&mut buffer)).await {
+                Ok(Ok(0)) | Err(_) => break false,
+                Ok(Ok(n)) => banner.extend_from_slice(&buffer[..n]),
+                Ok(Err(e)) => {
+                    result.error = Some(format!("Failed to read banner: {}", e));
+                    break false;
                 }
             }
-            Err(e) => {
-                error!("Ping attempt {} error for {}: {}", i, ip, e);
+        };
+
+        result.response = Some(String::from_utf8_lossy(&banner).trim().to_string());
+        if !matched {
+            result.success = false;
+            result.error.get_or_insert_with(|| format!("Banner did not match /{}/", expect.as_str()));
+        }
+    }
+
+    if result.success {
+        info!("TCP check successful for {}:{} ({:.2} ms)", host, port, latency_ms);
+    } else {
+        error!("TCP check failed for {}:{}: {}", host, port, result.error.as_deref().unwrap_or("unknown error"));
+    }
+    result
+}
+
+// Decode a sensor payload: "hex:0a0b..." for binary data, anything else is sent as text
+fn decode_payload(value: &str) -> Result<Vec<u8>, String> {
+    let Some(hex) = value.strip_prefix("hex:") else {
+        return Ok(value.as_bytes().to_vec());
+    };
+    let hex: String = hex.chars().filter(|c| !c.is_whitespace()).collect();
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("Invalid hex digits in {:?}", value));
+    }
+    if !hex.len().is_multiple_of(2) {
+        return Err(format!("Odd number of hex digits in {:?}", value));
+    }
+    Ok((0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap_or_default())
+        .collect())
+}
+
+pub async fn monitor_udp(host: &str, sensor: &SensorType) -> CheckResult {
+    let SensorType::Udp { port, timeout_ms, payload, expect } = sensor else {
+        return CheckResult::failed(sensor, "Not a UDP sensor".to_string());
+    };
+    debug!("Checking UDP port {} on {}", port, host);
+
+    let (payload, expect) = match (decode_payload(payload), expect.as_deref().map(decode_payload).transpose()) {
+        (Ok(payload), Ok(expect)) => (payload, expect),
+        (Err(e), _) | (_, Err(e)) => {
+            error!("Invalid UDP sensor settings for {}:{}: {}", host, port, e);
+            return CheckResult::failed(sensor, e);
+        }
+    };
+    let check_timeout = timeout_ms.map_or(DEFAULT_UDP_TIMEOUT, Duration::from_millis);
+
+    let addr = match icmp::resolve(host).await {
+        Ok(addr) => addr,
+        Err(e) => return CheckResult::failed(sensor, e.to_string()),
+    };
+    let bind_addr = if addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+
+    // A connected socket lets the kernel report ICMP port unreachable back to us
+    let socket = match UdpSocket::bind(bind_addr).await {
+        Ok(socket) => socket,
+        Err(e) => return CheckResult::failed(sensor, e.to_string()),
+    };
+    if let Err(e) = socket.connect((addr, *port)).await {
+        return CheckResult::failed(sensor, e.to_string());
+    }
+
+    let started = Instant::now();
+    if let Err(e) = socket.send(&payload).await {
+        error!("UDP send to {}:{} failed: {}", host, port, e);
+        return CheckResult::failed(sensor, e.to_string());
+    }
+
+    // Wait for errors as well as data, otherwise a port unreachable never wakes us up
+    let mut buffer = vec![0u8; MAX_DATAGRAM_SIZE];
+    let recv = async {
+        loop {
+            let ready = socket.ready(Interest::READABLE | Interest::ERROR).await?;
+            if ready.is_error() {
+                // The ICMP error (e.g. port unreachable) is pending in SO_ERROR
+                return Err(socket.take_error()?
+                    .unwrap_or_else(|| std::io::Error::other("Socket error")));
+            }
+            match socket.try_recv(&mut buffer) {
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => continue,
+                other => return other,
             }
         }
+    };
+    let reply = match timeout(check_timeout, recv).await {
+        Ok(Ok(len)) => &buffer[..len],
+        Ok(Err(e)) if e.kind() == std::io::ErrorKind::ConnectionRefused => {
+            error!("UDP port {} on {} is unreachable", port, host);
+            return CheckResult::failed(sensor, "Port unreachable".to_string());
+        }
+        Ok(Err(e)) => {
+            error!("UDP receive from {}:{} failed: {}", host, port, e);
+            return CheckResult::failed(sensor, e.to_string());
+        }
+        Err(_) => {
+            error!("No UDP reply from {}:{} within {} ms", host, port, check_timeout.as_millis());
+            return CheckResult::failed(sensor, format!("No reply within {} ms", check_timeout.as_millis()));
+        }
+    };
+    let latency_ms = started.elapsed().as_secs_f64() * 1000.0;
+
+    let mut result = CheckResult {
+        sensor: sensor.to_string(),
+        success: true,
+        latency_ms: Some(latency_ms),
+        response: Some(String::from_utf8_lossy(reply).trim().to_string()),
+        ..Default::default()
+    };
 
-        // Short delay between attempts
-        sleep(Duration::from_millis(200)).await;
+    if let Some(expect) = expect {
+        let matched = expect.is_empty() || reply.windows(expect.len()).any(|window| window == expect.as_slice());
+        if !matched {
+            result.success = false;
+            result.error = Some("Reply did not contain the expected bytes".to_string());
+        }
     }
 
-    // Consider it up if more than 50% attempts succeeded
-    let status = success_count > attempts / 2;
-    if status {
-        info!("Ping UP for {} ({}/{} successful)", ip, success_count, attempts);
+    if result.success {
+        info!("UDP check successful for {}:{} ({} bytes, {:.2} ms)", host, port, reply.len(), latency_ms);
     } else {
-        error!("Ping DOWN for {} ({}/{} failed)", ip, attempts - success_count, attempts);
+        error!("UDP check failed for {}:{}: {}", host, port, result.error.as_deref().unwrap_or("unknown error"));
     }
-    
-    status
+    result
 }
 
-pub async fn monitor_http(url: &str) -> bool {
-    debug!("Checking HTTP status for {}", url);
-    
-    match reqwest::get(url).await {
-        Ok(response) => {
-            let status = response.status();
-            let success = status.is_success();
-            if success {
-                info!("HTTP check successful for {}: {}", url, status);
-            } else {
-                error!("HTTP check failed for {}: {}", url, status);
-            }
-            success
-        },
+// Render an answer the way it would be written in `expect`
+fn format_rdata(rdata: &RData) -> String {
+    match rdata {
+        RData::A(addr) => addr.to_string(),
+        RData::AAAA(addr) => addr.to_string(),
+        RData::CNAME(name) => name.to_string().trim_end_matches('.').to_string(),
+        RData::MX(mx) => format!("{} {}", mx.preference(), mx.exchange().to_string().trim_end_matches('.')),
+        RData::TXT(txt) => txt.txt_data()
+            .iter()
+            .map(|part| String::from_utf8_lossy(part).into_owned())
+            .collect(),
+        other => other.to_string(),
+    }
+}
+
+fn answer_matches(answer: &str, expected: &str) -> bool {
+    let expected = expected.trim().trim_end_matches('.');
+    // MX answers match on either "10 mail.example.com" or just the exchange
+    answer.eq_ignore_ascii_case(expected)
+        || answer.split_once(' ').is_some_and(|(_, exchange)| exchange.eq_ignore_ascii_case(expected))
+}
+
+pub async fn monitor_dns(server: &str, sensor: &SensorType) -> CheckResult {
+    let SensorType::Dns { name, record_type, port, timeout_ms, expect } = sensor else {
+        return CheckResult::failed(sensor, "Not a DNS sensor".to_string());
+    };
+    debug!("Resolving {} {:?} via {}", name, record_type, server);
+
+    let server_addr = match icmp::resolve(server).await {
+        Ok(addr) => addr,
+        Err(e) => return CheckResult::failed(sensor, e.to_string()),
+    };
+    let check_timeout = timeout_ms.map_or(DEFAULT_DNS_TIMEOUT, Duration::from_millis);
+
+    // Ask only this server, once, without caching or the hosts file getting in the way
+    let mut options = ResolverOpts::default();
+    options.timeout = check_timeout;
+    options.attempts = 1;
+    options.cache_size = 0;
+    options.use_hosts_file = false;
+    let config = ResolverConfig::from_parts(
+        None,
+        vec![],
+        NameServerConfigGroup::from_ips_clear(&[server_addr], port.unwrap_or(53), true),
+    );
+    let resolver = TokioAsyncResolver::tokio(config, options);
+
+    let query_type = match record_type {
+        DnsRecordType::A => RecordType::A,
+        DnsRecordType::AAAA => RecordType::AAAA,
+        DnsRecordType::MX => RecordType::MX,
+        DnsRecordType::TXT => RecordType::TXT,
+        DnsRecordType::CNAME => RecordType::CNAME,
+    };
+
+    let started = Instant::now();
+    let lookup = match timeout(check_timeout, resolver.lookup(name.as_str(), query_type)).await {
+        Ok(Ok(lookup)) => lookup,
+        Ok(Err(e)) => {
+            error!("DNS lookup of {} {:?} via {} failed: {}", name, record_type, server, e);
+            let message = match e.kind() {
+                ResolveErrorKind::NoRecordsFound { response_code, .. } => {
+                    format!("No {:?} records for {} ({})", record_type, name, response_code)
+                }
+                _ => e.to_string(),
+            };
+            return CheckResult::failed(sensor, message);
+        }
+        Err(_) => {
+            error!("DNS lookup of {} {:?} via {} timed out", name, record_type, server);
+            return CheckResult::failed(sensor, format!("No answer within {} ms", check_timeout.as_millis()));
+        }
+    };
+    let latency_ms = started.elapsed().as_secs_f64() * 1000.0;
+
+    let answers: Vec<String> = lookup.iter().map(format_rdata).collect();
+    let mut result = CheckResult {
+        sensor: sensor.to_string(),
+        success: !answers.is_empty(),
+        latency_ms: Some(latency_ms),
+        response: Some(answers.join(", ")),
+        ..Default::default()
+    };
+
+    if answers.is_empty() {
+        result.error = Some("No records returned".to_string());
+    } else if let Some(expected) = expect {
+        if !answers.iter().any(|answer| answer_matches(answer, expected)) {
+            result.success = false;
+            result.error = Some(format!("Expected {} in answers", expected));
+        }
+    }
+
+    if result.success {
+        info!("DNS check successful for {} via {}: {} ({:.2} ms)", name, server, answers.join(", "), latency_ms);
+    } else {
+        error!("DNS check failed for {} via {}: {}", name, server, result.error.as_deref().unwrap_or("unknown error"));
+    }
+    result
+}
+
+
+// Where to run the certificate check: the HTTPS sensor follows the device's
+// http_path, an explicit Tls sensor connects to the device IP.
+fn tls_target(ip: &str, sensor: &SensorType, http_path: Option<&str>) -> (String, u16, String) {
+    if let SensorType::Tls { port, server_name, .. } = sensor {
+        let server_name = server_name.clone().unwrap_or_else(|| ip.to_string());
+        return (ip.to_string(), port.unwrap_or(443), server_name);
+    }
+
+    let url = http_path
+        .and_then(|path| reqwest::Url::parse(path).ok())
+        .filter(|url| url.scheme() == "https");
+    match url.as_ref().and_then(|url| url.host_str()) {
+        Some(host) => {
+            let host = host.trim_start_matches('[').trim_end_matches(']').to_string();
+            (host.clone(), url.as_ref().and_then(|url| url.port()).unwrap_or(443), host)
+        }
+        None => (ip.to_string(), 443, ip.to_string()),
+    }
+}
+
+pub async fn monitor_tls(ip: &str, sensor: &SensorType, http_path: Option<&str>) -> CheckResult {
+    let (timeout_ms, warn_days) = match sensor {
+        SensorType::Tls { timeout_ms, warn_days, .. } => (*timeout_ms, warn_days.clone()),
+        SensorType::Https => (None, default_warn_days()),
+        _ => return CheckResult::failed(sensor, "Not a TLS sensor".to_string()),
+    };
+    let (host, port, server_name) = tls_target(ip, sensor, http_path);
+    debug!("Checking TLS certificate for {} on {}:{}", server_name, host, port);
+
+    let check_timeout = timeout_ms.map_or(DEFAULT_TLS_TIMEOUT, Duration::from_millis);
+    let started = Instant::now();
+    let handshake = async {
+        let stream = TcpStream::connect((host.as_str(), port)).await?;
+        tls::handshake(stream, &server_name).await
+    };
+    let peer = match timeout(check_timeout, handshake).await {
+        Ok(Ok((_, peer))) => peer,
+        Ok(Err(e)) => {
+            error!("TLS handshake with {}:{} failed: {}", host, port, e);
+            return CheckResult::failed(sensor, format!("Handshake failed: {}", e));
+        }
+        Err(_) => {
+            error!("TLS handshake with {}:{} timed out", host, port);
+            return CheckResult::failed(sensor, format!("Handshake timed out after {} ms", check_timeout.as_millis()));
+        }
+    };
+    let latency_ms = started.elapsed().as_secs_f64() * 1000.0;
+
+    let cert = match tls::inspect(&peer, &server_name) {
+        Ok(cert) => cert,
         Err(e) => {
-            error!("HTTP request failed for {}: {}", url, e);
-            false
+            error!("Unable to read certificate from {}:{}: {}", host, port, e);
+            return CheckResult::failed(sensor, e.to_string());
+        }
+    };
+
+    let mut result = CheckResult {
+        sensor: sensor.check_label(),
+        success: true,
+        latency_ms: Some(latency_ms),
+        response: Some(cert.subject.clone()),
+        ..Default::default()
+    };
+
+    if cert.days_until_expiry < 0 {
+        result.error = Some(format!("Certificate expired on {}", cert.not_after));
+    } else if !cert.trusted {
+        let reason = peer.verify_error.as_ref().map_or("unknown".to_string(), |e| e.to_string());
+        result.error = Some(format!("Certificate chain is not trusted ({})", reason));
+    } else if !cert.hostname_matches {
+        result.error = Some(format!("Certificate is not valid for {}", server_name));
+    } else if let Some(threshold) = warn_days.iter().filter(|days| cert.days_until_expiry <= **days).min() {
+        result.warning = Some(format!(
+            "Certificate expires in {} days ({}-day threshold)",
+            cert.days_until_expiry, threshold
+        ));
+    }
+    result.success = result.error.is_none();
+
+    if let Some(ref error) = result.error {
+        error!("TLS check failed for {}:{}: {}", host, port, error);
+    } else if let Some(ref warning) = result.warning {
+        warn!("TLS check for {}:{}: {}", host, port, warning);
+    } else {
+        info!(
+            "TLS check successful for {}:{}: {} days until expiry ({:.2} ms)",
+            host, port, cert.days_until_expiry, latency_ms
+        );
+    }
+    result.certificate = Some(cert);
+    result
+}
+
+/// Interface byte counters at one point in time, kept between rounds so
+/// the Bandwidth sensor can turn them into rates.
+#[derive(Debug, Clone, Copy)]
+pub struct CounterSample {
+    pub at: Instant,
+    pub in_octets: u64,
+    pub out_octets: u64,
+}
+
+impl CounterSample {
+    // None when the counters went backwards (wrap or interface reset)
+    fn rates_since(&self, previous: &CounterSample) -> Option<BandwidthRates> {
+        let seconds = self.at.duration_since(previous.at).as_secs_f64();
+        if seconds <= 0.0 {
+            return None;
+        }
+        let mbps = |now: u64, before: u64| now.checked_sub(before).map(|delta| delta as f64 * 8.0 / seconds / 1_000_000.0);
+        Some(BandwidthRates {
+            in_mbps: mbps(self.in_octets, previous.in_octets)?,
+            out_mbps: Some(mbps(self.out_octets, previous.out_octets)?),
+        })
+    }
+}
+
+// Read the receive/transmit byte counters of `interface` from /proc/net/dev
+async fn read_interface_counters(interface: &str) -> Result<CounterSample, String> {
+    let content = tokio::fs::read_to_string("/proc/net/dev").await
+        .map_err(|e| format!("Unable to read /proc/net/dev: {}", e))?;
+    let at = Instant::now();
+
+    for line in content.lines().skip(2) {
+        let Some((name, fields)) = line.split_once(':') else { continue };
+        if name.trim() != interface {
+            continue;
+        }
+        // Receive bytes is the first column, transmit bytes the ninth
+        let fields: Vec<u64> = fields.split_whitespace().filter_map(|f| f.parse().ok()).collect();
+        return match (fields.first(), fields.get(8)) {
+            (Some(&in_octets), Some(&out_octets)) => Ok(CounterSample { at, in_octets, out_octets }),
+            _ => Err(format!("Unexpected /proc/net/dev format for {}", interface)),
+        };
+    }
+    Err(format!("Interface {} not found", interface))
+}
+
+// Read the 64-bit octet counters of one interface over SNMP
+async fn read_snmp_counters(host: &str, if_index: u32, auth: &SnmpAuth) -> Result<CounterSample, String> {
+    let counter = |value: serde_json::Value, name: &str| value.as_u64()
+        .ok_or_else(|| format!("{}.{} is not a counter: {}", name, if_index, value));
+
+    let mut session = snmp::Session::open(host, auth).await.map_err(|e| e.to_string())?;
+    let in_octets = session.get(&format!("{}.{}", snmp::IF_HC_IN_OCTETS, if_index)).await.map_err(|e| e.to_string())?;
+    let out_octets = session.get(&format!("{}.{}", snmp::IF_HC_OUT_OCTETS, if_index)).await.map_err(|e| e.to_string())?;
+
+    Ok(CounterSample {
+        at: Instant::now(),
+        in_octets: counter(in_octets, "ifHCInOctets")?,
+        out_octets: counter(out_octets, "ifHCOutOctets")?,
+    })
+}
+
+async fn measure_download(url: &str, max_bytes: u64, download_timeout: Duration) -> Result<BandwidthRates, String> {
+    let client = reqwest::Client::builder()
+        .timeout(download_timeout)
+        .build()
+        .map_err(|e| e.to_string())?;
+
+    let started = Instant::now();
+    let mut response = client.get(url).send().await.map_err(|e| e.to_string())?;
+    if !response.status().is_success() {
+        return Err(format!("Download returned {}", response.status()));
+    }
+    let mut received: u64 = 0;
+    while received < max_bytes {
+        match response.chunk().await {
+            Ok(Some(chunk)) => received += chunk.len() as u64,
+            Ok(None) => break,
+            // Out of time is fine, we measure what arrived so far
+            Err(e) if e.is_timeout() && received > 0 => break,
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+    let seconds = started.elapsed().as_secs_f64();
+    debug!("Downloaded {} bytes from {} in {:.2} s", received, url, seconds);
+
+    Ok(BandwidthRates {
+        in_mbps: received as f64 * 8.0 / seconds / 1_000_000.0,
+        out_mbps: None,
+    })
+}
+
+/// Measure throughput for a device with the Bandwidth sensor. Counter based
+/// sources need two rounds, so the first call only records `last_sample`
+/// and returns `Ok(None)`. `timeout_ms` applies to downloads and SNMP
+/// requests that don't set their own.
+pub async fn monitor_bandwidth(
+    device: &Device,
+    last_sample: &mut Option<CounterSample>,
+    timeout_ms: Option<u64>,
+) -> Result<Option<BandwidthRates>, String> {
+    let Some(ref source) = device.bandwidth else {
+        return Err("No bandwidth source configured".to_string());
+    };
+    debug!("Measuring bandwidth for {} via {:?}", device.name, source);
+
+    let result = match source {
+        BandwidthSource::Interface { name } => read_interface_counters(name).await.map(|sample| {
+            let rates = last_sample.as_ref().and_then(|previous| sample.rates_since(previous));
+            *last_sample = Some(sample);
+            rates
+        }),
+        BandwidthSource::Snmp { if_index, auth } => {
+            let auth = SnmpAuth { timeout_ms: auth.timeout_ms.or(timeout_ms), ..auth.clone() };
+            read_snmp_counters(&device.ip, *if_index, &auth).await.map(|sample| {
+                let rates = last_sample.as_ref().and_then(|previous| sample.rates_since(previous));
+                *last_sample = Some(sample);
+                rates
+            })
+        }
+        BandwidthSource::Download { url, max_bytes } => {
+            let download_timeout = timeout_ms.map_or(DEFAULT_DOWNLOAD_TIMEOUT, Duration::from_millis);
+            measure_download(url, max_bytes.unwrap_or(DEFAULT_DOWNLOAD_BYTES), download_timeout).await.map(Some)
+        }
+    };
+
+    match result {
+        Ok(Some(ref rates)) => info!(
+            "Bandwidth for {}: in {:.2} Mbps, out {}",
+            device.name, rates.in_mbps,
+            rates.out_mbps.map_or("N/A".to_string(), |out| format!("{:.2} Mbps", out))
+        ),
+        Ok(None) => debug!("First counter sample for {}, no rate yet", device.name),
+        Err(ref e) => error!("Bandwidth measurement failed for {}: {}", device.name, e),
+    }
+    result
+}
+
+pub async fn monitor_snmp(host: &str, sensor: &SensorType) -> CheckResult {
+    let SensorType::Snmp { auth, get, walk, thresholds, .. } = sensor else {
+        return CheckResult::failed(sensor, "Not an SNMP sensor".to_string());
+    };
+    debug!("Polling {} OIDs and walking {} subtrees on {} over SNMP", get.len(), walk.len(), host);
+
+    let started = Instant::now();
+    let mut session = match snmp::Session::open(host, auth).await {
+        Ok(session) => session,
+        Err(e) => {
+            error!("SNMP session to {} failed: {}", host, e);
+            return CheckResult::failed(sensor, e.to_string());
+        }
+    };
+
+    let mut result = CheckResult {
+        sensor: sensor.to_string(),
+        ..Default::default()
+    };
+    let mut errors = Vec::new();
+    for oid in get {
+        match session.get(oid).await {
+            Ok(value) => {
+                result.metrics.insert(oid.trim_start_matches('.').to_string(), value);
+            }
+            Err(e) => errors.push(e.to_string()),
+        }
+    }
+    for root in walk {
+        match session.walk(root).await {
+            Ok(values) if values.is_empty() => errors.push(format!("Nothing found below {}", root)),
+            Ok(values) => result.metrics.extend(values),
+            Err(e) => errors.push(e.to_string()),
+        }
+    }
+    result.latency_ms = Some(started.elapsed().as_secs_f64() * 1000.0);
+
+    // Only judge the thresholds when every value could be read
+    if errors.is_empty() {
+        errors.extend(thresholds.iter().flat_map(|threshold| check_threshold(threshold, &result.metrics)));
+    }
+    result.response = Some(format!("{} values", result.metrics.len()));
+    result.success = errors.is_empty();
+    if !errors.is_empty() {
+        result.error = Some(errors.join("; "));
+    }
+
+    if result.success {
+        info!("SNMP check successful for {}: {} values", host, result.metrics.len());
+    } else {
+        error!("SNMP check failed for {}: {}", host, result.error.as_deref().unwrap_or("unknown error"));
+    }
+    result
+}
+
+// Apply one threshold rule to every metric at or below its OID
+fn check_threshold(threshold: &SnmpThreshold, metrics: &BTreeMap<String, serde_json::Value>) -> Vec<String> {
+    let root = threshold.oid.trim_start_matches('.');
+    let prefix = format!("{}.", root);
+    let matching: Vec<_> = metrics.iter()
+        .filter(|(oid, _)| *oid == root || oid.starts_with(&prefix))
+        .collect();
+    if matching.is_empty() {
+        return vec![format!("No values for {}", root)];
+    }
+
+    let mut violations = Vec::new();
+    for (oid, value) in matching {
+        let text = value.as_str().map_or_else(|| value.to_string(), str::to_string);
+        if let Some(ref expected) = threshold.equals {
+            if text != *expected {
+                violations.push(format!("{} is {}, expected {}", oid, text, expected));
+            }
+        }
+        if threshold.min.is_none() && threshold.max.is_none() {
+            continue;
+        }
+        let Some(number) = value.as_f64().or_else(|| text.parse().ok()) else {
+            violations.push(format!("{} is not numeric: {}", oid, text));
+            continue;
+        };
+        if threshold.min.is_some_and(|min| number < min) {
+            violations.push(format!("{} is {} (min {})", oid, number, threshold.min.unwrap_or_default()));
+        }
+        if threshold.max.is_some_and(|max| number > max) {
+            violations.push(format!("{} is {} (max {})", oid, number, threshold.max.unwrap_or_default()));
+        }
+    }
+    violations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn document() -> serde_json::Value {
+        json!({
+            "status": "ok",
+            "code": 200,
+            "data": {"nested": {"deep": true}, "items": [{"id": 1}, {"id": 2, "tags": ["a", "b"]}]},
+            "odd key": {"with.dot": "yes"},
+        })
+    }
+
+    fn assertion(path: &str, equals: serde_json::Value) -> JsonAssertion {
+        JsonAssertion { path: path.to_string(), equals }
+    }
+
+    #[test]
+    fn json_path_follows_nested_keys() {
+        let document = document();
+        assert_eq!(json_path(&document, "$.status"), Ok(Some(&json!("ok"))));
+        assert_eq!(json_path(&document, "$.data.nested.deep"), Ok(Some(&json!(true))));
+        assert_eq!(json_path(&document, "data.nested.deep"), Ok(Some(&json!(true))));
+        assert_eq!(json_path(&document, "$"), Ok(Some(&document)));
+        assert_eq!(json_path(&document, "$['odd key']['with.dot']"), Ok(Some(&json!("yes"))));
+        assert_eq!(json_path(&document, r#"$["odd key"].with"#), Ok(None));
+    }
+
+    #[test]
+    fn json_path_indexes_arrays() {
+        let document = document();
+        assert_eq!(json_path(&document, "$.data.items[0].id"), Ok(Some(&json!(1))));
+        assert_eq!(json_path(&document, "$.data.items[1].tags[1]"), Ok(Some(&json!("b"))));
+        assert_eq!(json_path(&document, "$.data.items[ 1 ].id"), Ok(Some(&json!(2))));
+    }
+
+    #[test]
+    fn json_path_finds_nothing_for_missing_paths_and_wrong_types() {
+        let document = document();
+        assert_eq!(json_path(&document, "$.missing"), Ok(None));
+        assert_eq!(json_path(&document, "$.missing.deeper[3]"), Ok(None));
+        assert_eq!(json_path(&document, "$.data.items[5]"), Ok(None));
+        // An index into an object and a key into an array or a number
+        assert_eq!(json_path(&document, "$.data[0]"), Ok(None));
+        assert_eq!(json_path(&document, "$.data.items.id"), Ok(None));
+        assert_eq!(json_path(&document, "$.code.value"), Ok(None));
+    }
+
+    #[test]
+    fn json_path_rejects_malformed_paths() {
+        let document = document();
+        for path in [
+            "$['odd key]",
+            "$['odd key\"]",
+            "$['odd key'",
+            "$['odd key'x]",
+            "$.data.items[0",
+            "$.data.items[first]",
+            "$.data..nested",
+            "$.",
+            // Even after a missing key
+            "$.missing['x",
+        ] {
+            assert!(json_path(&document, path).is_err(), "{:?} was accepted", path);
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn check_json_compares_values() {
+        let body = document().to_string();
+        assert_eq!(check_json(&body, &assertion("$.status", json!("ok"))), Ok(()));
+        assert_eq!(check_json(&body, &assertion("$.data.items[1].tags", json!(["a", "b"]))), Ok(()));
+        // Numbers written as strings on either side
+        assert_eq!(check_json(&body, &assertion("$.code", json!("200"))), Ok(()));
+        assert_eq!(check_json(r#"{"code":"200"}"#, &assertion("$.code", json!(200))), Ok(()));
+        assert_eq!(
+            check_json(&body, &assertion("$.status", json!("down"))),
+            Err(r#"$.status is "ok", expected "down""#.to_string())
+        );
+        assert_eq!(check_json(&body, &assertion("$.code", json!(true))), Err("$.code is 200, expected true".to_string()));
+    }
+
+    #[test]
+    fn check_json_reports_missing_values_bad_paths_and_bad_bodies() {
+        let body = document().to_string();
+        assert_eq!(check_json(&body, &assertion("$.nope", json!(1))), Err("Nothing found at $.nope".to_string()));
+        assert_eq!(
+            check_json(&body, &assertion("$['status", json!(1))),
+            Err("Invalid JSON path $['status: missing closing quote".to_string())
+        );
+        assert!(check_json("<html>", &assertion("$.status", json!("ok"))).unwrap_err().starts_with("Response is not valid JSON"));
+    }
+
+    fn threshold(oid: &str, min: Option<f64>, max: Option<f64>, equals: Option<&str>) -> SnmpThreshold {
+        SnmpThreshold { oid: oid.to_string(), min, max, equals: equals.map(str::to_string) }
+    }
+
+    fn metrics(values: &[(&str, serde_json::Value)]) -> BTreeMap<String, serde_json::Value> {
+        values.iter().map(|(oid, value)| (oid.to_string(), value.clone())).collect()
+    }
+
+    #[test]
+    fn snmp_thresholds_check_numbers_and_text() {
+        let metrics = metrics(&[
+            ("1.3.6.1.4.1.2021.10.1.5.1", json!(250)),
+            ("1.3.6.1.2.1.1.5.0", json!("core-sw")),
+            ("1.3.6.1.4.1.9.9.13.1.3.1.3.1", json!("41")),
+        ]);
+        assert!(check_threshold(&threshold("1.3.6.1.4.1.2021.10.1.5.1", None, Some(300.0), None), &metrics).is_empty());
+        assert_eq!(
+            check_threshold(&threshold(".1.3.6.1.4.1.2021.10.1.5.1", Some(300.0), Some(200.0), None), &metrics),
+            vec!["1.3.6.1.4.1.2021.10.1.5.1 is 250 (min 300)", "1.3.6.1.4.1.2021.10.1.5.1 is 250 (max 200)"]
+        );
+        // Numbers the agent sends as strings
+        assert_eq!(
+            check_threshold(&threshold("1.3.6.1.4.1.9.9.13.1.3.1.3.1", None, Some(40.0), None), &metrics),
+            vec!["1.3.6.1.4.1.9.9.13.1.3.1.3.1 is 41 (max 40)"]
+        );
+        assert!(check_threshold(&threshold("1.3.6.1.2.1.1.5.0", None, None, Some("core-sw")), &metrics).is_empty());
+        assert_eq!(
+            check_threshold(&threshold("1.3.6.1.2.1.1.5.0", None, None, Some("edge-sw")), &metrics),
+            vec!["1.3.6.1.2.1.1.5.0 is core-sw, expected edge-sw"]
+        );
+        assert_eq!(
+            check_threshold(&threshold("1.3.6.1.2.1.1.5.0", Some(1.0), None, None), &metrics),
+            vec!["1.3.6.1.2.1.1.5.0 is not numeric: core-sw"]
+        );
+        assert_eq!(
+            check_threshold(&threshold("1.3.6.1.2.1.1.6.0", None, None, Some("x")), &metrics),
+            vec!["No values for 1.3.6.1.2.1.1.6.0"]
+        );
+    }
+
+    #[test]
+    fn snmp_thresholds_cover_walked_subtrees() {
+        // ifOperStatus of three interfaces, and a neighbouring column that shares the prefix
+        let metrics = metrics(&[
+            ("1.3.6.1.2.1.2.2.1.8.1", json!(1)),
+            ("1.3.6.1.2.1.2.2.1.8.2", json!(2)),
+            ("1.3.6.1.2.1.2.2.1.8.3", json!(1)),
+            ("1.3.6.1.2.1.2.2.1.80.1", json!(7)),
+        ]);
+        assert_eq!(
+            check_threshold(&threshold("1.3.6.1.2.1.2.2.1.8", None, None, Some("1")), &metrics),
+            vec!["1.3.6.1.2.1.2.2.1.8.2 is 2, expected 1"]
+        );
+        assert!(check_threshold(&threshold("1.3.6.1.2.1.2.2.1.8", Some(1.0), Some(2.0), None), &metrics).is_empty());
+    }
+}
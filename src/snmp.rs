@@ -0,0 +1,207 @@
+// src/snmp.rs
+use anyhow::{anyhow, bail, Result};
+use log::debug;
+use serde_json::{json, Value as JsonValue};
+use snmp2::v3::{Auth, AuthProtocol, Cipher, Security};
+use snmp2::{AsyncSession, Oid, Pdu, Value};
+use std::str::FromStr;
+use tokio::time::{timeout, Duration};
+use crate::models::{SnmpAuth, SnmpAuthProtocol, SnmpPrivacyProtocol};
+
+const DEFAULT_SNMP_PORT: u16 = 161;
+const DEFAULT_SNMP_TIMEOUT: Duration = Duration::from_secs(5);
+// Stop walks that never leave their subtree (broken agents) at some point
+const MAX_WALK_ENTRIES: usize = 1000;
+
+pub const IF_HC_IN_OCTETS: &str = "1.3.6.1.2.1.31.1.1.1.6";
+pub const IF_HC_OUT_OCTETS: &str = "1.3.6.1.2.1.31.1.1.1.10";
+
+/// An SNMP session with a per-request timeout.
+pub struct Session {
+    inner: AsyncSession,
+    timeout: Duration,
+}
+
+pub fn parse_oid(oid: &str) -> Result<Oid<'static>> {
+    Oid::from_str(oid.trim().trim_start_matches('.'))
+        .map_err(|e| anyhow!("Invalid OID {:?}: {:?}", oid, e))
+}
+
+impl Session {
+    /// Open a session to `host`, running the v3 engine discovery when needed.
+    pub async fn open(host: &str, auth: &SnmpAuth) -> Result<Self> {
+        let target = (host, auth.port.unwrap_or(DEFAULT_SNMP_PORT));
+        let request_timeout = auth.timeout_ms.map_or(DEFAULT_SNMP_TIMEOUT, Duration::from_millis);
+
+        let inner = match auth.v3 {
+            None => AsyncSession::new_v2c(target, auth.community.as_bytes(), 0).await?,
+            Some(ref v3) => {
+                let auth_protocol = match v3.auth_protocol {
+                    SnmpAuthProtocol::MD5 => AuthProtocol::Md5,
+                    SnmpAuthProtocol::SHA1 => AuthProtocol::Sha1,
+                    SnmpAuthProtocol::SHA224 => AuthProtocol::Sha224,
+                    SnmpAuthProtocol::SHA256 => AuthProtocol::Sha256,
+                    SnmpAuthProtocol::SHA384 => AuthProtocol::Sha384,
+                    SnmpAuthProtocol::SHA512 => AuthProtocol::Sha512,
+                };
+                let level = match (&v3.auth_password, &v3.privacy_password) {
+                    (None, _) => Auth::NoAuthNoPriv,
+                    (Some(_), None) => Auth::AuthNoPriv,
+                    (Some(_), Some(privacy_password)) => Auth::AuthPriv {
+                        cipher: match v3.privacy_protocol {
+                            SnmpPrivacyProtocol::DES => Cipher::Des,
+                            SnmpPrivacyProtocol::AES128 => Cipher::Aes128,
+                            SnmpPrivacyProtocol::AES192 => Cipher::Aes192,
+                            SnmpPrivacyProtocol::AES256 => Cipher::Aes256,
+                        },
+                        privacy_password: privacy_password.as_bytes().to_vec(),
+                    },
+                };
+                let mut security = Security::new(
+                    v3.username.as_bytes(),
+                    v3.auth_password.as_deref().unwrap_or_default().as_bytes(),
+                )
+                .with_auth(level)
+                .with_auth_protocol(auth_protocol);
+                if let Some(ref context) = v3.context {
+                    security = security.with_context_name(context);
+                }
+
+                let mut session = AsyncSession::new_v3(target, 0, security).await?;
+                match timeout(request_timeout, session.init()).await {
+                    Ok(result) => result.map_err(|e| anyhow!("SNMPv3 discovery failed: {:?}", e))?,
+                    Err(_) => bail!("SNMPv3 discovery timed out after {} ms", request_timeout.as_millis()),
+                }
+                session
+            }
+        };
+        Ok(Self { inner, timeout: request_timeout })
+    }
+
+    pub async fn get(&mut self, oid: &str) -> Result<JsonValue> {
+        let oid = parse_oid(oid)?;
+        let (_, value) = self.request(&oid, false).await?
+            .ok_or_else(|| anyhow!("No value returned for {}", oid))?;
+        value.ok_or_else(|| anyhow!("No such object {}", oid))
+    }
+
+    /// Every value below `root`, in OID order.
+    pub async fn walk(&mut self, root: &str) -> Result<Vec<(String, JsonValue)>> {
+        let root = parse_oid(root)?;
+        let mut values = Vec::new();
+        let mut current = root.clone();
+
+        while values.len() < MAX_WALK_ENTRIES {
+            let Some((oid, value)) = self.request(&current, true).await? else { break };
+            if !oid.starts_with(&root) || oid == current {
+                break;
+            }
+            if let Some(value) = value {
+                values.push((oid.to_id_string(), value));
+            }
+            current = oid;
+        }
+        debug!("Walked {} values below {}", values.len(), root);
+        Ok(values)
+    }
+
+    // One GET or GETNEXT; the value is None for noSuchObject and friends
+    async fn request(&mut self, oid: &Oid<'_>, next: bool) -> Result<Option<(Oid<'static>, Option<JsonValue>)>> {
+        // v3 agents may ask us to resync engine time first, in which case we retry once
+        for _ in 0..2 {
+            let response = if next {
+                timeout(self.timeout, self.inner.getnext(oid)).await
+            } else {
+                timeout(self.timeout, self.inner.get(oid)).await
+            };
+            match response {
+                Ok(Ok(pdu)) => return read_varbind(pdu),
+                Ok(Err(snmp2::Error::AuthUpdated)) => continue,
+                Ok(Err(e)) => bail!("SNMP request failed: {:?}", e),
+                Err(_) => bail!("SNMP request timed out after {} ms", self.timeout.as_millis()),
+            }
+        }
+        bail!("SNMP agent kept rejecting our engine time")
+    }
+}
+
+fn read_varbind(mut pdu: Pdu<'_>) -> Result<Option<(Oid<'static>, Option<JsonValue>)>> {
+    if pdu.error_status != 0 {
+        bail!("SNMP error status {} at index {}", pdu.error_status, pdu.error_index);
+    }
+    Ok(pdu.varbinds.next().map(|(oid, value)| (oid.to_owned(), to_json(&value))))
+}
+
+fn to_json(value: &Value<'_>) -> Option<JsonValue> {
+    Some(match *value {
+        Value::Boolean(v) => json!(v),
+        Value::Integer(v) => json!(v),
+        Value::Counter32(v) | Value::Unsigned32(v) | Value::Timeticks(v) => json!(v),
+        Value::Counter64(v) => json!(v),
+        Value::IpAddress(ip) => json!(std::net::Ipv4Addr::from(ip).to_string()),
+        Value::ObjectIdentifier(ref oid) => json!(oid.to_id_string()),
+        Value::OctetString(bytes) | Value::Opaque(bytes) => match std::str::from_utf8(bytes) {
+            Ok(text) if !text.chars().any(|c| c.is_control() && !c.is_whitespace()) => json!(text),
+            // Binary strings such as MAC addresses
+            _ => json!(bytes.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(":")),
+        },
+        Value::Null | Value::EndOfMibView | Value::NoSuchObject | Value::NoSuchInstance => return None,
+        _ => json!(format!("{:?}", value)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_values_to_json() {
+        assert_eq!(to_json(&Value::Boolean(true)), Some(json!(true)));
+        assert_eq!(to_json(&Value::Integer(-5)), Some(json!(-5)));
+        assert_eq!(to_json(&Value::Counter32(7)), Some(json!(7)));
+        assert_eq!(to_json(&Value::Timeticks(123456)), Some(json!(123456)));
+        assert_eq!(to_json(&Value::Counter64(u64::MAX)), Some(json!(u64::MAX)));
+        assert_eq!(to_json(&Value::IpAddress([10, 0, 0, 1])), Some(json!("10.0.0.1")));
+        let oid = parse_oid(".1.3.6.1.2.1.1.2.0").unwrap();
+        assert_eq!(to_json(&Value::ObjectIdentifier(oid)), Some(json!("1.3.6.1.2.1.1.2.0")));
+    }
+
+    #[test]
+    fn converts_strings_and_binary_to_json() {
+        assert_eq!(to_json(&Value::OctetString(b"Linux core-sw 5.15\n")), Some(json!("Linux core-sw 5.15\n")));
+        assert_eq!(to_json(&Value::Opaque(b"ok")), Some(json!("ok")));
+        // A MAC address
+        assert_eq!(
+            to_json(&Value::OctetString(&[0x00, 0x1b, 0x21, 0x0a, 0xff, 0x01])),
+            Some(json!("00:1b:21:0a:ff:01"))
+        );
+        assert_eq!(to_json(&Value::OctetString(&[0xc3, 0x28])), Some(json!("c3:28")));
+    }
+
+    #[test]
+    fn missing_values_have_no_json() {
+        for value in [Value::Null, Value::EndOfMibView, Value::NoSuchObject, Value::NoSuchInstance] {
+            assert_eq!(to_json(&value), None);
+        }
+    }
+
+    // Needs an agent answering to the "public" community, such as a local snmpd:
+    // SNMP_TEST_HOST=127.0.0.1 cargo test -- --ignored snmp
+    #[tokio::test]
+    #[ignore]
+    async fn reads_from_snmp_agent() {
+        let host = std::env::var("SNMP_TEST_HOST").unwrap_or_else(|_| "127.0.0.1".to_string());
+        let auth: SnmpAuth = serde_json::from_str("{}").unwrap();
+        let mut session = Session::open(&host, &auth).await.unwrap();
+
+        // sysDescr and sysObjectID
+        assert!(session.get("1.3.6.1.2.1.1.1.0").await.unwrap().is_string());
+        assert!(session.get(".1.3.6.1.2.1.1.2.0").await.unwrap().as_str().unwrap().starts_with("1.3.6.1."));
+        assert!(session.get("1.3.6.1.2.1.1.99.0").await.is_err());
+
+        let system = session.walk("1.3.6.1.2.1.1").await.unwrap();
+        assert!(system.iter().any(|(oid, _)| oid == "1.3.6.1.2.1.1.1.0"));
+        assert!(system.iter().all(|(oid, _)| oid.starts_with("1.3.6.1.2.1.1.")));
+        assert!(session.walk("1.3.6.1.2.1.1.99").await.unwrap().is_empty());
+    }
+}
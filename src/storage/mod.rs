@@ -0,0 +1,145 @@
+// src/storage/mod.rs
+mod postgres;
+pub mod retention;
+mod sqlite;
+
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use log::{error, info};
+use std::sync::Arc;
+use tokio::sync::{mpsc, oneshot};
+use chrono::{DateTime, Local};
+use crate::models::{ChangeFilter, CheckRecord, Device, LogFilter, Paging, Rollup, StateChange, User};
+use postgres::PostgresStorage;
+use sqlite::SqliteStorage;
+
+const DEFAULT_SQLITE_PATH: &str = "rustping.db";
+
+/// Where devices' last results, the monitoring history and users are kept.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Devices as last saved with `save_device`, results included.
+    async fn load_devices(&self) -> Result<Vec<Device>>;
+    /// Remember a device's latest results so they survive a restart.
+    async fn save_device(&self, device: &Device) -> Result<()>;
+    async fn insert_check(&self, record: &CheckRecord) -> Result<()>;
+    /// Check results passing `filter`, oldest first.
+    async fn check_records(&self, filter: &LogFilter) -> Result<Vec<CheckRecord>>;
+    /// One page of the check results passing `filter`, oldest first, and how many pass it in all.
+    async fn check_records_page(&self, filter: &LogFilter, paging: Paging) -> Result<(Vec<CheckRecord>, usize)>;
+    async fn insert_state_change(&self, change: &StateChange) -> Result<()>;
+    /// State changes of devices and sensors passing `filter`, oldest first.
+    async fn state_changes(&self, filter: &LogFilter) -> Result<Vec<StateChange>>;
+    /// One page of the state changes passing both filters, oldest first, and how many pass them in all.
+    async fn state_changes_page(
+        &self,
+        filter: &LogFilter,
+        changes: &ChangeFilter,
+        paging: Paging,
+    ) -> Result<(Vec<StateChange>, usize)>;
+    /// Add rollups, replacing any for the same device and period.
+    async fn save_rollups(&self, rollups: &[Rollup]) -> Result<()>;
+    /// Rollups of `period_secs` starting within `filter`, oldest first.
+    async fn rollups(&self, period_secs: i64, filter: &LogFilter) -> Result<Vec<Rollup>>;
+    /// When the latest rollup of `period_secs` starts.
+    async fn last_rollup_start(&self, period_secs: i64) -> Result<Option<DateTime<Local>>>;
+    /// Remove check results and state changes from before `time`; the number removed.
+    async fn delete_history_before(&self, time: DateTime<Local>) -> Result<u64>;
+    /// Remove rollups of `period_secs` starting before `time`; the number removed.
+    async fn delete_rollups_before(&self, period_secs: i64, time: DateTime<Local>) -> Result<u64>;
+    async fn users(&self) -> Result<Vec<User>>;
+    /// Replace the stored users.
+    async fn save_users(&self, users: &[User]) -> Result<()>;
+}
+
+/// The storage `DATABASE_URL` points to: PostgreSQL for a `postgres://` URL, otherwise
+/// an SQLite file, `rustping.db` unless given as `sqlite://path`.
+pub async fn open() -> Result<Arc<dyn Storage>> {
+    let url = std::env::var("DATABASE_URL").unwrap_or_default();
+    if url.starts_with("postgres://") || url.starts_with("postgresql://") {
+        let storage = PostgresStorage::new(&url);
+        // Carry on without it for now; every write and query tries to connect again
+        match storage.migrate().await {
+            Ok(()) => info!("Storing monitoring history in PostgreSQL"),
+            Err(e) => error!("{:#}; will keep trying as results come in", e),
+        }
+        return Ok(Arc::new(storage));
+    }
+    let path = match url.strip_prefix("sqlite://").or_else(|| url.strip_prefix("sqlite:")) {
+        Some(path) if !path.is_empty() => path,
+        _ if url.is_empty() => DEFAULT_SQLITE_PATH,
+        _ => bail!("Unsupported DATABASE_URL {:?}, expected postgres://... or sqlite://...", url),
+    };
+    let storage = SqliteStorage::open(path).await?;
+    info!("Storing monitoring history in {}", path);
+    Ok(Arc::new(storage))
+}
+
+// A LIKE pattern matching `value` anywhere, taken literally
+fn like_pattern(value: &str) -> String {
+    format!("%{}%", value.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_"))
+}
+
+enum Write {
+    Device(Box<Device>),
+    Check(Box<CheckRecord>),
+    StateChange(Box<StateChange>),
+    Rollups(Vec<Rollup>),
+    // Answered once everything sent before it has been written
+    Flush(oneshot::Sender<()>),
+}
+
+/// Saves results in the background, in the order they come in, so slow storage
+/// doesn't hold up the monitor.
+#[derive(Clone)]
+pub struct StorageWriter {
+    writes: mpsc::UnboundedSender<Write>,
+}
+
+impl StorageWriter {
+    pub fn spawn(storage: Arc<dyn Storage>) -> Self {
+        let (writes, mut pending) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Some(write) = pending.recv().await {
+                let (what, result) = match write {
+                    Write::Device(device) => ("device", storage.save_device(&device).await),
+                    Write::Check(record) => ("check result", storage.insert_check(&record).await),
+                    Write::StateChange(change) => ("state change", storage.insert_state_change(&change).await),
+                    Write::Rollups(rollups) => ("rollups", storage.save_rollups(&rollups).await),
+                    Write::Flush(done) => {
+                        let _ = done.send(());
+                        continue;
+                    }
+                };
+                if let Err(e) = result {
+                    error!("Failed to store {}: {:#}", what, e);
+                }
+            }
+        });
+        Self { writes }
+    }
+
+    pub fn save_device(&self, device: Device) {
+        let _ = self.writes.send(Write::Device(Box::new(device)));
+    }
+
+    pub fn insert_check(&self, record: CheckRecord) {
+        let _ = self.writes.send(Write::Check(Box::new(record)));
+    }
+
+    pub fn insert_state_change(&self, change: StateChange) {
+        let _ = self.writes.send(Write::StateChange(Box::new(change)));
+    }
+
+    pub fn save_rollups(&self, rollups: Vec<Rollup>) {
+        let _ = self.writes.send(Write::Rollups(rollups));
+    }
+
+    /// Wait until everything sent so far has been written.
+    pub async fn flush(&self) {
+        let (done, written) = oneshot::channel();
+        if self.writes.send(Write::Flush(done)).is_ok() {
+            let _ = written.await;
+        }
+    }
+}
@@ -0,0 +1,558 @@
+// src/storage/postgres.rs
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use log::{error, info};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use native_tls::TlsConnector;
+use postgres_native_tls::MakeTlsConnector;
+use tokio::sync::{Mutex, OnceCell};
+use tokio_postgres::types::ToSql;
+use tokio_postgres::{Client, Row};
+use uuid::Uuid;
+use chrono::{DateTime, Local};
+use crate::models::{BandwidthRates, ChangeFilter, CheckRecord, Device, LogFilter, Paging, Rollup, StateChange, User};
+use super::{like_pattern, Storage};
+
+// Schema changes, applied in order. Each runs once and is recorded in schema_migrations,
+// so add new ones at the end rather than editing these.
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE devices (
+        id UUID PRIMARY KEY,
+        name TEXT NOT NULL UNIQUE,
+        ip TEXT NOT NULL,
+        category TEXT NOT NULL,
+        created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+        updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+    );
+    CREATE TABLE check_results (
+        id BIGSERIAL PRIMARY KEY,
+        device_id UUID NOT NULL REFERENCES devices (id) ON DELETE CASCADE,
+        checked_at TIMESTAMPTZ NOT NULL,
+        state TEXT,
+        ping TEXT NOT NULL,
+        http TEXT NOT NULL,
+        bandwidth_in_mbps DOUBLE PRECISION,
+        bandwidth_out_mbps DOUBLE PRECISION,
+        latency_ms DOUBLE PRECISION,
+        jitter_ms DOUBLE PRECISION,
+        loss_percent DOUBLE PRECISION,
+        http_timing JSONB,
+        checks JSONB NOT NULL DEFAULT '[]'
+    );
+    CREATE INDEX check_results_checked_at ON check_results (checked_at);
+    CREATE INDEX check_results_device_checked_at ON check_results (device_id, checked_at);
+    CREATE TABLE state_changes (
+        id BIGSERIAL PRIMARY KEY,
+        device_id UUID NOT NULL REFERENCES devices (id) ON DELETE CASCADE,
+        changed_at TIMESTAMPTZ NOT NULL,
+        old_state TEXT,
+        new_state TEXT NOT NULL,
+        duration_secs DOUBLE PRECISION
+    );
+    CREATE INDEX state_changes_changed_at ON state_changes (changed_at);
+    CREATE INDEX state_changes_device_changed_at ON state_changes (device_id, changed_at)",
+    "ALTER TABLE devices ADD COLUMN status JSONB, ADD COLUMN state_since TIMESTAMPTZ;
+    CREATE TABLE users (
+        username TEXT PRIMARY KEY,
+        password_hash TEXT NOT NULL,
+        role TEXT NOT NULL
+    )",
+    "CREATE TABLE rollups (
+        device_id UUID NOT NULL REFERENCES devices (id) ON DELETE CASCADE,
+        period_secs INTEGER NOT NULL,
+        start_at TIMESTAMPTZ NOT NULL,
+        samples INTEGER NOT NULL,
+        up_samples INTEGER NOT NULL,
+        latency_samples INTEGER NOT NULL,
+        latency_min_ms DOUBLE PRECISION,
+        latency_avg_ms DOUBLE PRECISION,
+        latency_max_ms DOUBLE PRECISION,
+        PRIMARY KEY (device_id, period_secs, start_at)
+    );
+    CREATE INDEX rollups_period_start_at ON rollups (period_secs, start_at)",
+    "ALTER TABLE state_changes ADD COLUMN sensor TEXT",
+    // Devices are kept by id, so a name can come back after its device was removed
+    "ALTER TABLE devices DROP CONSTRAINT devices_name_key;
+    CREATE INDEX devices_name ON devices (name)",
+];
+
+// How long to wait for the server before giving up on connecting
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Storage in PostgreSQL, for a `postgres://` `DATABASE_URL`.
+pub struct PostgresStorage {
+    url: String,
+    client: Mutex<Option<Arc<Client>>>,
+    // Set once the schema has been brought up to date
+    migrated: OnceCell<()>,
+    // Devices already stored, by id, with the name, address and category they were stored with
+    stored_devices: Mutex<HashMap<Uuid, (String, String, String)>>,
+}
+
+impl PostgresStorage {
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            client: Mutex::new(None),
+            migrated: OnceCell::new(),
+            stored_devices: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Connect and bring the schema up to date.
+    pub async fn migrate(&self) -> Result<()> {
+        self.client().await.map(|_| ())
+    }
+
+    // The open connection, connecting again if it was lost
+    async fn client(&self) -> Result<Arc<Client>> {
+        if let Some(client) = self.open_client().await {
+            return Ok(client);
+        }
+        // Connecting can take a while, so other callers aren't kept waiting on the lock meanwhile
+        let new_client = Arc::new(self.connect().await?);
+        self.migrated.get_or_try_init(|| run_migrations(&new_client)).await?;
+        let mut client = self.client.lock().await;
+        // Another caller may have connected in the meantime
+        if let Some(client) = client.as_ref().filter(|client| !client.is_closed()) {
+            return Ok(client.clone());
+        }
+        *client = Some(new_client.clone());
+        Ok(new_client)
+    }
+
+    async fn open_client(&self) -> Option<Arc<Client>> {
+        self.client.lock().await.as_ref().filter(|client| !client.is_closed()).cloned()
+    }
+
+    // TLS is used as the URL's sslmode asks, e.g. always with sslmode=require
+    async fn connect(&self) -> Result<Client> {
+        let tls = MakeTlsConnector::new(TlsConnector::new().context("Failed to set up TLS for PostgreSQL")?);
+        let (client, connection) = tokio::time::timeout(CONNECT_TIMEOUT, tokio_postgres::connect(&self.url, tls))
+            .await
+            .map_err(|_| anyhow!("Timed out connecting to PostgreSQL after {} s", CONNECT_TIMEOUT.as_secs()))?
+            .context("Failed to connect to PostgreSQL")?;
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                error!("PostgreSQL connection error: {}", e);
+            }
+        });
+        Ok(client)
+    }
+
+    // Add the device, or bring its name, address and category up to date
+    async fn upsert_device(&self, client: &Client, id: Uuid, name: &str, ip: &str, category: &str) -> Result<()> {
+        let mut stored_devices = self.stored_devices.lock().await;
+        let stored = (name.to_string(), ip.to_string(), category.to_string());
+        if stored_devices.get(&id) == Some(&stored) {
+            return Ok(());
+        }
+        client.execute(
+            "INSERT INTO devices (id, name, ip, category) VALUES ($1, $2, $3, $4)
+             ON CONFLICT (id) DO UPDATE SET name = EXCLUDED.name, ip = EXCLUDED.ip, category = EXCLUDED.category,
+                updated_at = now()",
+            &[&id, &name, &ip, &category],
+        ).await?;
+        stored_devices.insert(id, stored);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Storage for PostgresStorage {
+    async fn load_devices(&self) -> Result<Vec<Device>> {
+        let client = self.client().await?;
+        let rows = client.query("SELECT id, status, state_since FROM devices WHERE status IS NOT NULL", &[]).await?;
+        rows.iter()
+            .map(|row| {
+                let mut device: Device = serde_json::from_value(row.try_get("status")?)?;
+                device.id = row.try_get("id")?;
+                device.state_since = row.try_get("state_since")?;
+                Ok(device)
+            })
+            .collect()
+    }
+
+    async fn save_device(&self, device: &Device) -> Result<()> {
+        let client = self.client().await?;
+        self.upsert_device(&client, device.id, &device.name, &device.ip, &device.category).await?;
+        client.execute(
+            "UPDATE devices SET status = $2, state_since = $3, updated_at = now() WHERE id = $1",
+            &[&device.id, &serde_json::to_value(device)?, &device.state_since],
+        ).await?;
+        Ok(())
+    }
+
+    async fn insert_check(&self, record: &CheckRecord) -> Result<()> {
+        let client = self.client().await?;
+        let device_id = record.device_id.context("Check result without a device id")?;
+        self.upsert_device(&client, device_id, &record.device, &record.ip, &record.category).await?;
+        let http_timing = record.http_timing.as_ref().map(serde_json::to_value).transpose()?;
+        client.execute(
+            "INSERT INTO check_results (device_id, checked_at, state, ping, http, bandwidth_in_mbps,
+                bandwidth_out_mbps, latency_ms, jitter_ms, loss_percent, http_timing, checks)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)",
+            &[
+                &device_id,
+                &record.timestamp,
+                &record.state.map(|state| state.to_string()),
+                &record.ping,
+                &record.http,
+                &record.bandwidth.as_ref().map(|rates| rates.in_mbps),
+                &record.bandwidth.as_ref().and_then(|rates| rates.out_mbps),
+                &record.latency_ms,
+                &record.jitter_ms,
+                &record.loss_percent,
+                &http_timing,
+                &serde_json::to_value(&record.checks)?,
+            ],
+        ).await?;
+        Ok(())
+    }
+
+    async fn insert_state_change(&self, change: &StateChange) -> Result<()> {
+        let client = self.client().await?;
+        let device_id = change.device_id.context("State change without a device id")?;
+        self.upsert_device(&client, device_id, &change.device, &change.ip, &change.category).await?;
+        client.execute(
+            "INSERT INTO state_changes (device_id, changed_at, sensor, old_state, new_state, duration_secs)
+             VALUES ($1, $2, $3, $4, $5, $6)",
+            &[
+                &device_id,
+                &change.timestamp,
+                &change.sensor,
+                &change.old_state.map(|state| state.to_string()),
+                &change.new_state.to_string(),
+                &change.duration_secs,
+            ],
+        ).await?;
+        Ok(())
+    }
+
+    async fn check_records(&self, filter: &LogFilter) -> Result<Vec<CheckRecord>> {
+        let client = self.client().await?;
+        let conditions = Conditions::from_filter(filter, "c.checked_at", "c.device_id");
+        select_checks(&client, conditions, None).await
+    }
+
+    async fn check_records_page(&self, filter: &LogFilter, paging: Paging) -> Result<(Vec<CheckRecord>, usize)> {
+        let client = self.client().await?;
+        let conditions = Conditions::from_filter(filter, "c.checked_at", "c.device_id");
+        let total = count(&client, "check_results c", &conditions).await?;
+        Ok((select_checks(&client, conditions, Some(paging)).await?, total))
+    }
+
+    async fn state_changes(&self, filter: &LogFilter) -> Result<Vec<StateChange>> {
+        let client = self.client().await?;
+        let conditions = Conditions::from_filter(filter, "s.changed_at", "s.device_id");
+        select_state_changes(&client, conditions, None).await
+    }
+
+    async fn state_changes_page(
+        &self,
+        filter: &LogFilter,
+        changes: &ChangeFilter,
+        paging: Paging,
+    ) -> Result<(Vec<StateChange>, usize)> {
+        let client = self.client().await?;
+        let mut conditions = Conditions::from_filter(filter, "s.changed_at", "s.device_id");
+        conditions.add_changes(changes);
+        let total = count(&client, "state_changes s", &conditions).await?;
+        Ok((select_state_changes(&client, conditions, Some(paging)).await?, total))
+    }
+
+    async fn save_rollups(&self, rollups: &[Rollup]) -> Result<()> {
+        let client = self.client().await?;
+        for rollup in rollups {
+            self.upsert_device(&client, rollup.device_id, &rollup.device, &rollup.ip, &rollup.category).await?;
+            client.execute(
+                "INSERT INTO rollups (device_id, period_secs, start_at, samples, up_samples, latency_samples,
+                    latency_min_ms, latency_avg_ms, latency_max_ms)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+                 ON CONFLICT (device_id, period_secs, start_at) DO UPDATE SET samples = EXCLUDED.samples,
+                    up_samples = EXCLUDED.up_samples, latency_samples = EXCLUDED.latency_samples,
+                    latency_min_ms = EXCLUDED.latency_min_ms, latency_avg_ms = EXCLUDED.latency_avg_ms,
+                    latency_max_ms = EXCLUDED.latency_max_ms",
+                &[
+                    &rollup.device_id,
+                    &(rollup.period_secs as i32),
+                    &rollup.start,
+                    &(rollup.samples as i32),
+                    &(rollup.up_samples as i32),
+                    &(rollup.latency_samples as i32),
+                    &rollup.latency_min_ms,
+                    &rollup.latency_avg_ms,
+                    &rollup.latency_max_ms,
+                ],
+            ).await?;
+        }
+        Ok(())
+    }
+
+    async fn rollups(&self, period_secs: i64, filter: &LogFilter) -> Result<Vec<Rollup>> {
+        let client = self.client().await?;
+        let mut conditions = Conditions::from_filter(filter, "r.start_at", "r.device_id");
+        conditions.add("r.period_secs = $?", period_secs as i32);
+        let query = format!(
+            "SELECT d.id, d.name, d.ip, d.category, r.period_secs, r.start_at, r.samples, r.up_samples,
+                r.latency_samples, r.latency_min_ms, r.latency_avg_ms, r.latency_max_ms
+             FROM rollups r JOIN devices d ON d.id = r.device_id
+             {}
+             ORDER BY r.start_at, d.name",
+            conditions.where_clause()
+        );
+        let rows = client.query(&query, &conditions.params()).await?;
+        rows.iter()
+            .map(|row| {
+                Ok(Rollup {
+                    device_id: row.try_get("id")?,
+                    device: row.try_get("name")?,
+                    ip: row.try_get("ip")?,
+                    category: row.try_get("category")?,
+                    period_secs: row.try_get::<_, i32>("period_secs")? as i64,
+                    start: row.try_get("start_at")?,
+                    samples: row.try_get::<_, i32>("samples")? as u32,
+                    up_samples: row.try_get::<_, i32>("up_samples")? as u32,
+                    latency_samples: row.try_get::<_, i32>("latency_samples")? as u32,
+                    latency_min_ms: row.try_get("latency_min_ms")?,
+                    latency_avg_ms: row.try_get("latency_avg_ms")?,
+                    latency_max_ms: row.try_get("latency_max_ms")?,
+                })
+            })
+            .collect()
+    }
+
+    async fn last_rollup_start(&self, period_secs: i64) -> Result<Option<DateTime<Local>>> {
+        let client = self.client().await?;
+        let row = client
+            .query_one("SELECT MAX(start_at) FROM rollups WHERE period_secs = $1", &[&(period_secs as i32)])
+            .await?;
+        Ok(row.try_get(0)?)
+    }
+
+    async fn delete_history_before(&self, time: DateTime<Local>) -> Result<u64> {
+        let client = self.client().await?;
+        let checks = client.execute("DELETE FROM check_results WHERE checked_at < $1", &[&time]).await?;
+        let changes = client.execute("DELETE FROM state_changes WHERE changed_at < $1", &[&time]).await?;
+        Ok(checks + changes)
+    }
+
+    async fn delete_rollups_before(&self, period_secs: i64, time: DateTime<Local>) -> Result<u64> {
+        let client = self.client().await?;
+        Ok(client
+            .execute("DELETE FROM rollups WHERE period_secs = $1 AND start_at < $2", &[&(period_secs as i32), &time])
+            .await?)
+    }
+
+    async fn users(&self) -> Result<Vec<User>> {
+        let client = self.client().await?;
+        let rows = client.query("SELECT username, password_hash, role FROM users ORDER BY username", &[]).await?;
+        rows.iter()
+            .map(|row| {
+                Ok(User {
+                    username: row.try_get("username")?,
+                    password_hash: row.try_get("password_hash")?,
+                    role: row.try_get("role")?,
+                })
+            })
+            .collect()
+    }
+
+    async fn save_users(&self, users: &[User]) -> Result<()> {
+        let client = self.client().await?;
+        let usernames: Vec<&str> = users.iter().map(|user| user.username.as_str()).collect();
+        let hashes: Vec<&str> = users.iter().map(|user| user.password_hash.as_str()).collect();
+        let roles: Vec<&str> = users.iter().map(|user| user.role.as_str()).collect();
+        // One statement, so the users are replaced all at once
+        client.execute(
+            "WITH new_users AS (
+                SELECT * FROM UNNEST($1::text[], $2::text[], $3::text[]) AS u (username, password_hash, role)
+             ), removed AS (
+                DELETE FROM users WHERE username NOT IN (SELECT username FROM new_users)
+             )
+             INSERT INTO users SELECT * FROM new_users
+             ON CONFLICT (username) DO UPDATE SET password_hash = EXCLUDED.password_hash, role = EXCLUDED.role",
+            &[&usernames, &hashes, &roles],
+        ).await?;
+        Ok(())
+    }
+}
+
+// The WHERE clause of a query and the parameters it refers to
+#[derive(Default)]
+struct Conditions {
+    conditions: Vec<String>,
+    params: Vec<Box<dyn ToSql + Sync + Send>>,
+}
+
+impl Conditions {
+    // Only the filters that are set, so the planner can use the indexes
+    fn from_filter(filter: &LogFilter, time_column: &str, device_column: &str) -> Self {
+        let mut conditions = Self::default();
+        if let Some(start) = filter.start {
+            conditions.add(&format!("{} >= $?", time_column), start);
+        }
+        if let Some(end) = filter.end {
+            conditions.add(&format!("{} < $?", time_column), end);
+        }
+        if let Some(ref devices) = filter.devices {
+            // Substring matches, like the log file filters
+            let patterns: Vec<String> = devices.iter().map(|device| like_pattern(device)).collect();
+            conditions.add(
+                &format!("{} IN (SELECT id FROM devices WHERE name ILIKE ANY($?) OR ip ILIKE ANY($?) OR id::text ILIKE ANY($?))", device_column),
+                patterns,
+            );
+        }
+        conditions
+    }
+
+    // The sensor and state filters of state changes
+    fn add_changes(&mut self, changes: &ChangeFilter) {
+        match changes.sensor {
+            Some(ref sensor) if sensor.eq_ignore_ascii_case("device") => self.conditions.push("s.sensor IS NULL".to_string()),
+            Some(ref sensor) => self.add("LOWER(s.sensor) = LOWER($?)", sensor.clone()),
+            None => {}
+        }
+        if let Some(state) = changes.state {
+            self.add("s.new_state = $?", state.to_string());
+        }
+    }
+
+    // `condition` with each `$?` standing for `param`
+    fn add(&mut self, condition: &str, param: impl ToSql + Sync + Send + 'static) {
+        let placeholder = self.param(param);
+        self.conditions.push(condition.replace("$?", &placeholder));
+    }
+
+    // The placeholder for another parameter, e.g. of a LIMIT
+    fn param(&mut self, param: impl ToSql + Sync + Send + 'static) -> String {
+        self.params.push(Box::new(param));
+        format!("${}", self.params.len())
+    }
+
+    // LIMIT and OFFSET for `paging`, if any
+    fn limit_clause(&mut self, paging: Option<Paging>) -> String {
+        match paging {
+            Some(paging) => {
+                let limit = self.param(paging.limit as i64);
+                format!("LIMIT {} OFFSET {}", limit, self.param(paging.offset as i64))
+            }
+            None => String::new(),
+        }
+    }
+
+    fn where_clause(&self) -> String {
+        if self.conditions.is_empty() {
+            String::new()
+        } else {
+            format!("WHERE {}", self.conditions.join(" AND "))
+        }
+    }
+
+    fn params(&self) -> Vec<&(dyn ToSql + Sync)> {
+        self.params.iter().map(|param| param.as_ref() as &(dyn ToSql + Sync)).collect()
+    }
+}
+
+async fn select_checks(client: &Client, mut conditions: Conditions, paging: Option<Paging>) -> Result<Vec<CheckRecord>> {
+    let limit_clause = conditions.limit_clause(paging);
+    let query = format!(
+        "SELECT d.id, d.name, d.ip, d.category, c.checked_at, c.state, c.ping, c.http, c.bandwidth_in_mbps,
+            c.bandwidth_out_mbps, c.latency_ms, c.jitter_ms, c.loss_percent, c.http_timing, c.checks
+         FROM check_results c JOIN devices d ON d.id = c.device_id
+         {}
+         ORDER BY c.checked_at, c.id
+         {}",
+        conditions.where_clause(),
+        limit_clause
+    );
+    let rows = client.query(&query, &conditions.params()).await?;
+    rows.iter().map(check_record_from_row).collect()
+}
+
+async fn select_state_changes(client: &Client, mut conditions: Conditions, paging: Option<Paging>) -> Result<Vec<StateChange>> {
+    let limit_clause = conditions.limit_clause(paging);
+    let query = format!(
+        "SELECT d.id, d.name, d.ip, d.category, s.changed_at, s.sensor, s.old_state, s.new_state, s.duration_secs
+         FROM state_changes s JOIN devices d ON d.id = s.device_id
+         {}
+         ORDER BY s.changed_at, s.id
+         {}",
+        conditions.where_clause(),
+        limit_clause
+    );
+    let rows = client.query(&query, &conditions.params()).await?;
+    rows.iter().map(state_change_from_row).collect()
+}
+
+// Rows of `table` passing `conditions`
+async fn count(client: &Client, table: &str, conditions: &Conditions) -> Result<usize> {
+    let query = format!("SELECT COUNT(*) FROM {} {}", table, conditions.where_clause());
+    let count: i64 = client.query_one(&query, &conditions.params()).await?.try_get(0)?;
+    Ok(count as usize)
+}
+
+async fn run_migrations(client: &Client) -> Result<()> {
+    client.batch_execute(
+        "CREATE TABLE IF NOT EXISTS schema_migrations (
+            version INTEGER PRIMARY KEY,
+            applied_at TIMESTAMPTZ NOT NULL DEFAULT now()
+        )",
+    ).await?;
+    let applied: i32 = client
+        .query_one("SELECT COALESCE(MAX(version), 0) FROM schema_migrations", &[])
+        .await?
+        .try_get(0)?;
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(applied as usize) {
+        let version = index + 1;
+        // Several statements sent at once run in one transaction, so a failed
+        // migration leaves nothing half applied
+        client
+            .batch_execute(&format!("{}; INSERT INTO schema_migrations (version) VALUES ({})", migration, version))
+            .await
+            .with_context(|| format!("Database migration {} failed", version))?;
+        info!("Applied database migration {}", version);
+    }
+    Ok(())
+}
+
+fn state_change_from_row(row: &Row) -> Result<StateChange> {
+    let old_state: Option<String> = row.try_get("old_state")?;
+    let new_state: String = row.try_get("new_state")?;
+    Ok(StateChange {
+        timestamp: row.try_get("changed_at")?,
+        device_id: row.try_get("id")?,
+        device: row.try_get("name")?,
+        ip: row.try_get("ip")?,
+        category: row.try_get("category")?,
+        sensor: row.try_get("sensor")?,
+        old_state: old_state.and_then(|state| state.parse().ok()),
+        new_state: new_state.parse().map_err(|e: String| anyhow!(e))?,
+        duration_secs: row.try_get("duration_secs")?,
+    })
+}
+
+fn check_record_from_row(row: &Row) -> Result<CheckRecord> {
+    let state: Option<String> = row.try_get("state")?;
+    let in_mbps: Option<f64> = row.try_get("bandwidth_in_mbps")?;
+    let out_mbps: Option<f64> = row.try_get("bandwidth_out_mbps")?;
+    let http_timing: Option<serde_json::Value> = row.try_get("http_timing")?;
+    Ok(CheckRecord {
+        timestamp: row.try_get("checked_at")?,
+        device_id: row.try_get("id")?,
+        device: row.try_get("name")?,
+        ip: row.try_get("ip")?,
+        category: row.try_get("category")?,
+        state: state.and_then(|state| state.parse().ok()),
+        ping: row.try_get("ping")?,
+        http: row.try_get("http")?,
+        bandwidth: in_mbps.map(|in_mbps| BandwidthRates { in_mbps, out_mbps }),
+        latency_ms: row.try_get("latency_ms")?,
+        jitter_ms: row.try_get("jitter_ms")?,
+        loss_percent: row.try_get("loss_percent")?,
+        http_timing: http_timing.map(serde_json::from_value).transpose()?,
+        checks: serde_json::from_value(row.try_get("checks")?)?,
+    })
+}
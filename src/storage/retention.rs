@@ -0,0 +1,200 @@
+// src/storage/retention.rs
+use anyhow::Result;
+use chrono::{Duration as ChronoDuration, Local};
+use log::{error, info};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tokio::time::{sleep, Duration};
+use uuid::Uuid;
+use crate::config::Retention;
+use crate::models::{period_start, LogFilter, Rollup};
+use super::Storage;
+
+const RUN_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Roll five minute rollups up into hourly ones and drop history older than
+/// `retention` allows, at startup and then every hour.
+pub async fn run(storage: Arc<dyn Storage>, retention: Retention) {
+    loop {
+        if let Err(e) = roll_up_hours(storage.as_ref()).await {
+            error!("Failed to build hourly rollups: {:#}", e);
+        }
+        if let Err(e) = prune(storage.as_ref(), &retention).await {
+            error!("Failed to remove old history: {:#}", e);
+        }
+        sleep(RUN_INTERVAL).await;
+    }
+}
+
+// Every hour that is over, from the latest one already rolled up, which may have
+// been missing its last five minutes
+async fn roll_up_hours(storage: &dyn Storage) -> Result<()> {
+    let filter = LogFilter {
+        start: storage.last_rollup_start(Rollup::HOURLY).await?,
+        end: Some(period_start(Local::now(), Rollup::HOURLY)),
+        devices: None,
+    };
+    let mut hours: BTreeMap<(Uuid, i64), Rollup> = BTreeMap::new();
+    for rollup in storage.rollups(Rollup::FIVE_MINUTES, &filter).await? {
+        let start = period_start(rollup.start, Rollup::HOURLY);
+        hours.entry((rollup.device_id, start.timestamp()))
+            .or_insert_with(|| Rollup {
+                period_secs: Rollup::HOURLY,
+                start,
+                samples: 0,
+                up_samples: 0,
+                latency_samples: 0,
+                latency_min_ms: None,
+                latency_avg_ms: None,
+                latency_max_ms: None,
+                ..rollup.clone()
+            })
+            .merge(&rollup);
+    }
+    if !hours.is_empty() {
+        let hours: Vec<Rollup> = hours.into_values().collect();
+        storage.save_rollups(&hours).await?;
+        info!("Rolled up {} device hours", hours.len());
+    }
+    Ok(())
+}
+
+async fn prune(storage: &dyn Storage, retention: &Retention) -> Result<()> {
+    let before = |days: u32| Local::now() - ChronoDuration::days(days as i64);
+    if retention.raw_days > 0 {
+        let deleted = storage.delete_history_before(before(retention.raw_days)).await?;
+        if deleted > 0 {
+            info!("Removed {} check results and state changes older than {} days", deleted, retention.raw_days);
+        }
+    }
+    for (period_secs, days) in [
+        (Rollup::FIVE_MINUTES, retention.five_minute_days),
+        (Rollup::HOURLY, retention.hourly_days),
+    ] {
+        if days > 0 {
+            let deleted = storage.delete_rollups_before(period_secs, before(days)).await?;
+            if deleted > 0 {
+                info!("Removed {} rollups of {}s older than {} days", deleted, period_secs, days);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{CheckRecord, DeviceState, StateChange};
+    use crate::storage::sqlite::SqliteStorage;
+
+    fn rollup(period_secs: i64, start: chrono::DateTime<Local>, samples: u32, latency_ms: f64) -> Rollup {
+        Rollup {
+            device_id: Uuid::from_u128(1),
+            device: "Router".to_string(),
+            ip: "10.0.0.1".to_string(),
+            category: "core".to_string(),
+            period_secs,
+            start,
+            samples,
+            up_samples: samples,
+            latency_samples: samples,
+            latency_min_ms: Some(latency_ms),
+            latency_avg_ms: Some(latency_ms),
+            latency_max_ms: Some(latency_ms),
+        }
+    }
+
+    fn check(timestamp: chrono::DateTime<Local>) -> CheckRecord {
+        CheckRecord {
+            timestamp,
+            device_id: Some(Uuid::from_u128(1)),
+            device: "Router".to_string(),
+            ip: "10.0.0.1".to_string(),
+            category: "core".to_string(),
+            state: Some(DeviceState::Up),
+            ping: "OK".to_string(),
+            http: "N/A".to_string(),
+            bandwidth: None,
+            latency_ms: None,
+            jitter_ms: None,
+            loss_percent: None,
+            http_timing: None,
+            checks: Vec::new(),
+        }
+    }
+
+    fn change(timestamp: chrono::DateTime<Local>) -> StateChange {
+        StateChange {
+            timestamp,
+            device_id: Some(Uuid::from_u128(1)),
+            device: "Router".to_string(),
+            ip: "10.0.0.1".to_string(),
+            category: "core".to_string(),
+            sensor: None,
+            old_state: None,
+            new_state: DeviceState::Up,
+            duration_secs: None,
+        }
+    }
+
+    async fn hours(storage: &dyn Storage) -> Vec<(chrono::DateTime<Local>, u32, Option<f64>)> {
+        storage.rollups(Rollup::HOURLY, &LogFilter::default()).await.unwrap()
+            .into_iter()
+            .map(|hour| (hour.start, hour.samples, hour.latency_avg_ms))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn five_minutes_roll_up_into_their_hour() {
+        let storage = SqliteStorage::open(":memory:").await.unwrap();
+        let hour = period_start(Local::now(), Rollup::HOURLY) - ChronoDuration::hours(3);
+        let minutes = |minutes: i64| hour + ChronoDuration::minutes(minutes);
+        storage.save_rollups(&[
+            rollup(Rollup::FIVE_MINUTES, minutes(0), 10, 10.0),
+            rollup(Rollup::FIVE_MINUTES, minutes(55), 30, 20.0),
+            rollup(Rollup::FIVE_MINUTES, minutes(60), 5, 40.0),
+        ]).await.unwrap();
+        // The current hour isn't over yet
+        storage.save_rollups(&[rollup(Rollup::FIVE_MINUTES, period_start(Local::now(), Rollup::HOURLY), 5, 1.0)])
+            .await.unwrap();
+
+        roll_up_hours(&storage).await.unwrap();
+        assert_eq!(hours(&storage).await, [(minutes(0), 40, Some(17.5)), (minutes(60), 5, Some(40.0))]);
+
+        // Five minutes stored late are taken in by the next run, as the last hour is done again
+        storage.save_rollups(&[rollup(Rollup::FIVE_MINUTES, minutes(65), 5, 20.0)]).await.unwrap();
+        roll_up_hours(&storage).await.unwrap();
+        assert_eq!(hours(&storage).await, [(minutes(0), 40, Some(17.5)), (minutes(60), 10, Some(30.0))]);
+    }
+
+    #[tokio::test]
+    async fn prune_drops_history_past_its_retention() {
+        let storage = SqliteStorage::open(":memory:").await.unwrap();
+        let days_ago = |days: i64| Local::now() - ChronoDuration::days(days);
+        for days in [31, 29] {
+            storage.insert_check(&check(days_ago(days))).await.unwrap();
+            storage.insert_state_change(&change(days_ago(days))).await.unwrap();
+        }
+        storage.save_rollups(&[
+            rollup(Rollup::FIVE_MINUTES, days_ago(91), 1, 1.0),
+            rollup(Rollup::FIVE_MINUTES, days_ago(89), 1, 1.0),
+            rollup(Rollup::HOURLY, days_ago(1000), 1, 1.0),
+        ]).await.unwrap();
+
+        // Hourly rollups are kept forever
+        let retention = Retention { raw_days: 30, five_minute_days: 90, hourly_days: 0 };
+        prune(&storage, &retention).await.unwrap();
+
+        let all = LogFilter::default();
+        let checks = storage.check_records(&all).await.unwrap();
+        assert_eq!(checks.len(), 1);
+        assert!(checks[0].timestamp > days_ago(30));
+        assert_eq!(storage.state_changes(&all).await.unwrap().len(), 1);
+        assert_eq!(storage.rollups(Rollup::FIVE_MINUTES, &all).await.unwrap().len(), 1);
+        assert_eq!(storage.rollups(Rollup::HOURLY, &all).await.unwrap().len(), 1);
+
+        // Nothing is removed with every retention at 0
+        prune(&storage, &Retention { raw_days: 0, five_minute_days: 0, hourly_days: 0 }).await.unwrap();
+        assert_eq!(storage.check_records(&all).await.unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,739 @@
+// src/storage/sqlite.rs
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Local, TimeZone};
+use log::info;
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter, Connection, Row};
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+use crate::models::{BandwidthRates, ChangeFilter, CheckRecord, Device, LogFilter, Paging, Rollup, StateChange, User};
+use super::{like_pattern, Storage};
+
+// Schema changes, applied in order and counted in the database's user_version, so add
+// new ones at the end rather than editing these. Times are milliseconds since the epoch.
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE devices (
+        id TEXT PRIMARY KEY,
+        name TEXT NOT NULL UNIQUE,
+        ip TEXT NOT NULL,
+        category TEXT NOT NULL,
+        status TEXT,
+        state_since INTEGER,
+        created_at INTEGER NOT NULL,
+        updated_at INTEGER NOT NULL
+    );
+    CREATE TABLE check_results (
+        id INTEGER PRIMARY KEY,
+        device_id TEXT NOT NULL REFERENCES devices (id) ON DELETE CASCADE,
+        checked_at INTEGER NOT NULL,
+        state TEXT,
+        ping TEXT NOT NULL,
+        http TEXT NOT NULL,
+        bandwidth_in_mbps REAL,
+        bandwidth_out_mbps REAL,
+        latency_ms REAL,
+        jitter_ms REAL,
+        loss_percent REAL,
+        http_timing TEXT,
+        checks TEXT NOT NULL DEFAULT '[]'
+    );
+    CREATE INDEX check_results_checked_at ON check_results (checked_at);
+    CREATE INDEX check_results_device_checked_at ON check_results (device_id, checked_at);
+    CREATE TABLE state_changes (
+        id INTEGER PRIMARY KEY,
+        device_id TEXT NOT NULL REFERENCES devices (id) ON DELETE CASCADE,
+        changed_at INTEGER NOT NULL,
+        old_state TEXT,
+        new_state TEXT NOT NULL,
+        duration_secs REAL
+    );
+    CREATE INDEX state_changes_changed_at ON state_changes (changed_at);
+    CREATE INDEX state_changes_device_changed_at ON state_changes (device_id, changed_at);
+    CREATE TABLE users (
+        username TEXT PRIMARY KEY,
+        password_hash TEXT NOT NULL,
+        role TEXT NOT NULL
+    );",
+    "CREATE TABLE rollups (
+        device_id TEXT NOT NULL REFERENCES devices (id) ON DELETE CASCADE,
+        period_secs INTEGER NOT NULL,
+        start_at INTEGER NOT NULL,
+        samples INTEGER NOT NULL,
+        up_samples INTEGER NOT NULL,
+        latency_samples INTEGER NOT NULL,
+        latency_min_ms REAL,
+        latency_avg_ms REAL,
+        latency_max_ms REAL,
+        PRIMARY KEY (device_id, period_secs, start_at)
+    );
+    CREATE INDEX rollups_period_start_at ON rollups (period_secs, start_at);",
+    "ALTER TABLE state_changes ADD COLUMN sensor TEXT;",
+    // Devices are kept by id, so a name can come back after its device was removed.
+    // SQLite can't drop a constraint, hence the copy.
+    "CREATE TABLE devices_new (
+        id TEXT PRIMARY KEY,
+        name TEXT NOT NULL,
+        ip TEXT NOT NULL,
+        category TEXT NOT NULL,
+        status TEXT,
+        state_since INTEGER,
+        created_at INTEGER NOT NULL,
+        updated_at INTEGER NOT NULL
+    );
+    INSERT INTO devices_new SELECT id, name, ip, category, status, state_since, created_at, updated_at FROM devices;
+    DROP TABLE devices;
+    ALTER TABLE devices_new RENAME TO devices;
+    CREATE INDEX devices_name ON devices (name);",
+];
+
+/// Storage in a local SQLite file, used unless `DATABASE_URL` says otherwise.
+pub struct SqliteStorage {
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStorage {
+    /// Open or create the database at `path` and bring the schema up to date.
+    pub async fn open(path: &str) -> Result<Self> {
+        let path = path.to_string();
+        let connection = tokio::task::spawn_blocking(move || -> Result<Connection> {
+            let mut connection = Connection::open(&path).with_context(|| format!("Failed to open {}", path))?;
+            // Foreign keys stay off while migrating, or replacing a table would delete
+            // the history referring to it
+            connection.execute_batch("PRAGMA journal_mode = WAL; PRAGMA foreign_keys = OFF; PRAGMA busy_timeout = 5000;")?;
+            run_migrations(&mut connection)?;
+            connection.execute_batch("PRAGMA foreign_keys = ON;")?;
+            Ok(connection)
+        }).await??;
+        Ok(Self { connection: Arc::new(Mutex::new(connection)) })
+    }
+
+    // Run `f` on the connection without holding up the async runtime
+    async fn with_connection<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T> + Send + 'static,
+    {
+        let connection = self.connection.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut connection = connection.lock().map_err(|_| anyhow!("SQLite connection lock poisoned"))?;
+            f(&mut connection)
+        }).await?
+    }
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    async fn load_devices(&self) -> Result<Vec<Device>> {
+        self.with_connection(|connection| {
+            let mut statement = connection.prepare("SELECT id, status, state_since FROM devices WHERE status IS NOT NULL")?;
+            let mut rows = statement.query([])?;
+            let mut devices = Vec::new();
+            while let Some(row) = rows.next()? {
+                let mut device: Device = serde_json::from_str(&row.get::<_, String>("status")?)?;
+                device.id = row.get::<_, String>("id")?.parse()?;
+                device.state_since = row.get::<_, Option<i64>>("state_since")?.and_then(from_millis);
+                devices.push(device);
+            }
+            Ok(devices)
+        }).await
+    }
+
+    async fn save_device(&self, device: &Device) -> Result<()> {
+        let status = serde_json::to_string(device)?;
+        let (id, name, ip, category) = (device.id, device.name.clone(), device.ip.clone(), device.category.clone());
+        let state_since = device.state_since.as_ref().map(DateTime::timestamp_millis);
+        self.with_connection(move |connection| {
+            upsert_device(connection, id, &name, &ip, &category)?;
+            connection.execute(
+                "UPDATE devices SET status = ?2, state_since = ?3 WHERE id = ?1",
+                params![id.to_string(), status, state_since],
+            )?;
+            Ok(())
+        }).await
+    }
+
+    async fn insert_check(&self, record: &CheckRecord) -> Result<()> {
+        let record = record.clone();
+        let http_timing = record.http_timing.as_ref().map(serde_json::to_string).transpose()?;
+        let checks = serde_json::to_string(&record.checks)?;
+        self.with_connection(move |connection| {
+            let device_id = record.device_id.context("Check result without a device id")?;
+            upsert_device(connection, device_id, &record.device, &record.ip, &record.category)?;
+            connection.execute(
+                "INSERT INTO check_results (device_id, checked_at, state, ping, http, bandwidth_in_mbps,
+                    bandwidth_out_mbps, latency_ms, jitter_ms, loss_percent, http_timing, checks)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+                params![
+                    device_id.to_string(),
+                    record.timestamp.timestamp_millis(),
+                    record.state.map(|state| state.to_string()),
+                    record.ping,
+                    record.http,
+                    record.bandwidth.as_ref().map(|rates| rates.in_mbps),
+                    record.bandwidth.as_ref().and_then(|rates| rates.out_mbps),
+                    record.latency_ms,
+                    record.jitter_ms,
+                    record.loss_percent,
+                    http_timing,
+                    checks,
+                ],
+            )?;
+            Ok(())
+        }).await
+    }
+
+    async fn check_records(&self, filter: &LogFilter) -> Result<Vec<CheckRecord>> {
+        let conditions = Conditions::from_filter(filter, "c.checked_at", "c.device_id");
+        self.with_connection(move |connection| select_checks(connection, conditions, None)).await
+    }
+
+    async fn check_records_page(&self, filter: &LogFilter, paging: Paging) -> Result<(Vec<CheckRecord>, usize)> {
+        let conditions = Conditions::from_filter(filter, "c.checked_at", "c.device_id");
+        self.with_connection(move |connection| {
+            let total = count(connection, "check_results c", &conditions)?;
+            Ok((select_checks(connection, conditions, Some(paging))?, total))
+        }).await
+    }
+
+    async fn insert_state_change(&self, change: &StateChange) -> Result<()> {
+        let change = change.clone();
+        self.with_connection(move |connection| {
+            let device_id = change.device_id.context("State change without a device id")?;
+            upsert_device(connection, device_id, &change.device, &change.ip, &change.category)?;
+            connection.execute(
+                "INSERT INTO state_changes (device_id, changed_at, sensor, old_state, new_state, duration_secs)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    device_id.to_string(),
+                    change.timestamp.timestamp_millis(),
+                    change.sensor,
+                    change.old_state.map(|state| state.to_string()),
+                    change.new_state.to_string(),
+                    change.duration_secs,
+                ],
+            )?;
+            Ok(())
+        }).await
+    }
+
+    async fn state_changes(&self, filter: &LogFilter) -> Result<Vec<StateChange>> {
+        let conditions = Conditions::from_filter(filter, "s.changed_at", "s.device_id");
+        self.with_connection(move |connection| select_state_changes(connection, conditions, None)).await
+    }
+
+    async fn state_changes_page(
+        &self,
+        filter: &LogFilter,
+        changes: &ChangeFilter,
+        paging: Paging,
+    ) -> Result<(Vec<StateChange>, usize)> {
+        let mut conditions = Conditions::from_filter(filter, "s.changed_at", "s.device_id");
+        conditions.add_changes(changes);
+        self.with_connection(move |connection| {
+            let total = count(connection, "state_changes s", &conditions)?;
+            Ok((select_state_changes(connection, conditions, Some(paging))?, total))
+        }).await
+    }
+
+    async fn save_rollups(&self, rollups: &[Rollup]) -> Result<()> {
+        let rollups = rollups.to_vec();
+        self.with_connection(move |connection| {
+            let transaction = connection.transaction()?;
+            for rollup in rollups.iter() {
+                upsert_device(&transaction, rollup.device_id, &rollup.device, &rollup.ip, &rollup.category)?;
+                transaction.execute(
+                    "INSERT OR REPLACE INTO rollups (device_id, period_secs, start_at, samples, up_samples,
+                        latency_samples, latency_min_ms, latency_avg_ms, latency_max_ms)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                    params![
+                        rollup.device_id.to_string(),
+                        rollup.period_secs,
+                        rollup.start.timestamp_millis(),
+                        rollup.samples,
+                        rollup.up_samples,
+                        rollup.latency_samples,
+                        rollup.latency_min_ms,
+                        rollup.latency_avg_ms,
+                        rollup.latency_max_ms,
+                    ],
+                )?;
+            }
+            transaction.commit()?;
+            Ok(())
+        }).await
+    }
+
+    async fn rollups(&self, period_secs: i64, filter: &LogFilter) -> Result<Vec<Rollup>> {
+        let mut conditions = Conditions::from_filter(filter, "r.start_at", "r.device_id");
+        conditions.add("r.period_secs = ?", Value::Integer(period_secs));
+        let query = format!(
+            "SELECT d.id, d.name, d.ip, d.category, r.period_secs, r.start_at, r.samples, r.up_samples,
+                r.latency_samples, r.latency_min_ms, r.latency_avg_ms, r.latency_max_ms
+             FROM rollups r JOIN devices d ON d.id = r.device_id
+             {}
+             ORDER BY r.start_at, d.name",
+            conditions.where_clause()
+        );
+        self.with_connection(move |connection| {
+            let mut statement = connection.prepare(&query)?;
+            let mut rows = statement.query(params_from_iter(conditions.params))?;
+            let mut rollups = Vec::new();
+            while let Some(row) = rows.next()? {
+                let start: i64 = row.get("start_at")?;
+                rollups.push(Rollup {
+                    device_id: row.get::<_, String>("id")?.parse()?,
+                    device: row.get("name")?,
+                    ip: row.get("ip")?,
+                    category: row.get("category")?,
+                    period_secs: row.get("period_secs")?,
+                    start: from_millis(start).ok_or_else(|| anyhow!("Invalid rollup start {}", start))?,
+                    samples: row.get("samples")?,
+                    up_samples: row.get("up_samples")?,
+                    latency_samples: row.get("latency_samples")?,
+                    latency_min_ms: row.get("latency_min_ms")?,
+                    latency_avg_ms: row.get("latency_avg_ms")?,
+                    latency_max_ms: row.get("latency_max_ms")?,
+                });
+            }
+            Ok(rollups)
+        }).await
+    }
+
+    async fn last_rollup_start(&self, period_secs: i64) -> Result<Option<DateTime<Local>>> {
+        self.with_connection(move |connection| {
+            let start: Option<i64> = connection.query_row(
+                "SELECT MAX(start_at) FROM rollups WHERE period_secs = ?1",
+                params![period_secs],
+                |row| row.get(0),
+            )?;
+            Ok(start.and_then(from_millis))
+        }).await
+    }
+
+    async fn delete_history_before(&self, time: DateTime<Local>) -> Result<u64> {
+        let before = time.timestamp_millis();
+        self.with_connection(move |connection| {
+            let checks = connection.execute("DELETE FROM check_results WHERE checked_at < ?1", params![before])?;
+            let changes = connection.execute("DELETE FROM state_changes WHERE changed_at < ?1", params![before])?;
+            Ok((checks + changes) as u64)
+        }).await
+    }
+
+    async fn delete_rollups_before(&self, period_secs: i64, time: DateTime<Local>) -> Result<u64> {
+        let before = time.timestamp_millis();
+        self.with_connection(move |connection| {
+            let deleted = connection.execute(
+                "DELETE FROM rollups WHERE period_secs = ?1 AND start_at < ?2",
+                params![period_secs, before],
+            )?;
+            Ok(deleted as u64)
+        }).await
+    }
+
+    async fn users(&self) -> Result<Vec<User>> {
+        self.with_connection(|connection| {
+            let mut statement = connection.prepare("SELECT username, password_hash, role FROM users ORDER BY username")?;
+            let users = statement
+                .query_map([], |row| {
+                    Ok(User {
+                        username: row.get("username")?,
+                        password_hash: row.get("password_hash")?,
+                        role: row.get("role")?,
+                    })
+                })?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            Ok(users)
+        }).await
+    }
+
+    async fn save_users(&self, users: &[User]) -> Result<()> {
+        let users = users.to_vec();
+        self.with_connection(move |connection| {
+            let transaction = connection.transaction()?;
+            transaction.execute("DELETE FROM users", [])?;
+            for user in users.iter() {
+                transaction.execute(
+                    "INSERT INTO users (username, password_hash, role) VALUES (?1, ?2, ?3)",
+                    params![user.username, user.password_hash, user.role],
+                )?;
+            }
+            transaction.commit()?;
+            Ok(())
+        }).await
+    }
+}
+
+// The WHERE clause of a query and the parameters it refers to
+#[derive(Default)]
+struct Conditions {
+    conditions: Vec<String>,
+    params: Vec<Value>,
+}
+
+impl Conditions {
+    // Only the filters that are set, so the indexes can be used
+    fn from_filter(filter: &LogFilter, time_column: &str, device_column: &str) -> Self {
+        let mut conditions = Self::default();
+        if let Some(start) = filter.start {
+            conditions.add(&format!("{} >= ?", time_column), Value::Integer(start.timestamp_millis()));
+        }
+        if let Some(end) = filter.end {
+            conditions.add(&format!("{} < ?", time_column), Value::Integer(end.timestamp_millis()));
+        }
+        if let Some(ref devices) = filter.devices {
+            // Substring matches, like the log file filters; LIKE ignores case
+            let matches: Vec<String> = devices.iter()
+                .map(|device| {
+                    conditions.params.push(Value::Text(like_pattern(device)));
+                    format!(
+                        "name LIKE ?{0} ESCAPE '\\' OR ip LIKE ?{0} ESCAPE '\\' OR id LIKE ?{0} ESCAPE '\\'",
+                        conditions.params.len()
+                    )
+                })
+                .collect();
+            conditions.conditions.push(format!(
+                "{} IN (SELECT id FROM devices WHERE {})",
+                device_column,
+                matches.join(" OR ")
+            ));
+        }
+        conditions
+    }
+
+    // The sensor and state filters of state changes
+    fn add_changes(&mut self, changes: &ChangeFilter) {
+        match changes.sensor {
+            Some(ref sensor) if sensor.eq_ignore_ascii_case("device") => self.conditions.push("s.sensor IS NULL".to_string()),
+            Some(ref sensor) => self.add("s.sensor = ? COLLATE NOCASE", Value::Text(sensor.clone())),
+            None => {}
+        }
+        if let Some(state) = changes.state {
+            self.add("s.new_state = ?", Value::Text(state.to_string()));
+        }
+    }
+
+    // `condition` with its `?` standing for `param`
+    fn add(&mut self, condition: &str, param: Value) {
+        let placeholder = self.param(param);
+        self.conditions.push(condition.replace('?', &placeholder));
+    }
+
+    // The placeholder for another parameter, e.g. of a LIMIT
+    fn param(&mut self, param: Value) -> String {
+        self.params.push(param);
+        format!("?{}", self.params.len())
+    }
+
+    // LIMIT and OFFSET for `paging`, if any
+    fn limit_clause(&mut self, paging: Option<Paging>) -> String {
+        match paging {
+            Some(paging) => {
+                let limit = self.param(Value::Integer(paging.limit as i64));
+                format!("LIMIT {} OFFSET {}", limit, self.param(Value::Integer(paging.offset as i64)))
+            }
+            None => String::new(),
+        }
+    }
+
+    fn where_clause(&self) -> String {
+        if self.conditions.is_empty() {
+            String::new()
+        } else {
+            format!("WHERE {}", self.conditions.join(" AND "))
+        }
+    }
+}
+
+fn select_checks(connection: &Connection, mut conditions: Conditions, paging: Option<Paging>) -> Result<Vec<CheckRecord>> {
+    let limit_clause = conditions.limit_clause(paging);
+    let query = format!(
+        "SELECT d.id, d.name, d.ip, d.category, c.checked_at, c.state, c.ping, c.http, c.bandwidth_in_mbps,
+            c.bandwidth_out_mbps, c.latency_ms, c.jitter_ms, c.loss_percent, c.http_timing, c.checks
+         FROM check_results c JOIN devices d ON d.id = c.device_id
+         {}
+         ORDER BY c.checked_at, c.id
+         {}",
+        conditions.where_clause(),
+        limit_clause
+    );
+    let mut statement = connection.prepare(&query)?;
+    let mut rows = statement.query(params_from_iter(conditions.params))?;
+    let mut records = Vec::new();
+    while let Some(row) = rows.next()? {
+        records.push(check_record_from_row(row)?);
+    }
+    Ok(records)
+}
+
+fn select_state_changes(connection: &Connection, mut conditions: Conditions, paging: Option<Paging>) -> Result<Vec<StateChange>> {
+    let limit_clause = conditions.limit_clause(paging);
+    let query = format!(
+        "SELECT d.id, d.name, d.ip, d.category, s.changed_at, s.sensor, s.old_state, s.new_state, s.duration_secs
+         FROM state_changes s JOIN devices d ON d.id = s.device_id
+         {}
+         ORDER BY s.changed_at, s.id
+         {}",
+        conditions.where_clause(),
+        limit_clause
+    );
+    let mut statement = connection.prepare(&query)?;
+    let mut rows = statement.query(params_from_iter(conditions.params))?;
+    let mut changes = Vec::new();
+    while let Some(row) = rows.next()? {
+        changes.push(state_change_from_row(row)?);
+    }
+    Ok(changes)
+}
+
+// Rows of `table` passing `conditions`
+fn count(connection: &Connection, table: &str, conditions: &Conditions) -> Result<usize> {
+    let query = format!("SELECT COUNT(*) FROM {} {}", table, conditions.where_clause());
+    let count: i64 = connection.query_row(&query, params_from_iter(conditions.params.iter()), |row| row.get(0))?;
+    Ok(count as usize)
+}
+
+fn run_migrations(connection: &mut Connection) -> Result<()> {
+    let applied: usize = connection.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(applied) {
+        let version = index + 1;
+        let transaction = connection.transaction()?;
+        transaction
+            .execute_batch(migration)
+            .with_context(|| format!("Database migration {} failed", version))?;
+        transaction.pragma_update(None, "user_version", version)?;
+        transaction.commit()?;
+        info!("Applied database migration {}", version);
+    }
+    Ok(())
+}
+
+// Add the device, or bring its name, address and category up to date
+fn upsert_device(connection: &Connection, id: Uuid, name: &str, ip: &str, category: &str) -> Result<()> {
+    connection.execute(
+        "INSERT INTO devices (id, name, ip, category, created_at, updated_at) VALUES (?1, ?2, ?3, ?4, ?5, ?5)
+         ON CONFLICT (id) DO UPDATE SET name = excluded.name, ip = excluded.ip, category = excluded.category,
+            updated_at = excluded.updated_at",
+        params![id.to_string(), name, ip, category, Local::now().timestamp_millis()],
+    )?;
+    Ok(())
+}
+
+fn state_change_from_row(row: &Row) -> Result<StateChange> {
+    let changed_at: i64 = row.get("changed_at")?;
+    let old_state: Option<String> = row.get("old_state")?;
+    let new_state: String = row.get("new_state")?;
+    Ok(StateChange {
+        timestamp: from_millis(changed_at).ok_or_else(|| anyhow!("Invalid state change time {}", changed_at))?,
+        device_id: Some(row.get::<_, String>("id")?.parse()?),
+        device: row.get("name")?,
+        ip: row.get("ip")?,
+        category: row.get("category")?,
+        sensor: row.get("sensor")?,
+        old_state: old_state.and_then(|state| state.parse().ok()),
+        new_state: new_state.parse().map_err(|e: String| anyhow!(e))?,
+        duration_secs: row.get("duration_secs")?,
+    })
+}
+
+fn check_record_from_row(row: &Row) -> Result<CheckRecord> {
+    let checked_at: i64 = row.get("checked_at")?;
+    let state: Option<String> = row.get("state")?;
+    let in_mbps: Option<f64> = row.get("bandwidth_in_mbps")?;
+    let http_timing: Option<String> = row.get("http_timing")?;
+    Ok(CheckRecord {
+        timestamp: from_millis(checked_at).ok_or_else(|| anyhow!("Invalid check time {}", checked_at))?,
+        device_id: Some(row.get::<_, String>("id")?.parse()?),
+        device: row.get("name")?,
+        ip: row.get("ip")?,
+        category: row.get("category")?,
+        state: state.and_then(|state| state.parse().ok()),
+        ping: row.get("ping")?,
+        http: row.get("http")?,
+        bandwidth: match in_mbps {
+            Some(in_mbps) => Some(BandwidthRates { in_mbps, out_mbps: row.get("bandwidth_out_mbps")? }),
+            None => None,
+        },
+        latency_ms: row.get("latency_ms")?,
+        jitter_ms: row.get("jitter_ms")?,
+        loss_percent: row.get("loss_percent")?,
+        http_timing: http_timing.map(|timing| serde_json::from_str(&timing)).transpose()?,
+        checks: serde_json::from_str(&row.get::<_, String>("checks")?)?,
+    })
+}
+
+fn from_millis(millis: i64) -> Option<DateTime<Local>> {
+    Local.timestamp_millis_opt(millis).single()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::DeviceState;
+
+    fn in_memory(connection: Connection) -> SqliteStorage {
+        SqliteStorage { connection: Arc::new(Mutex::new(connection)) }
+    }
+
+    async fn open() -> SqliteStorage {
+        SqliteStorage::open(":memory:").await.unwrap()
+    }
+
+    fn device_id(n: u128) -> Uuid {
+        Uuid::from_u128(n)
+    }
+
+    fn at(minute: i64) -> DateTime<Local> {
+        Local.timestamp_opt(1_700_000_000 + minute * 60, 0).unwrap()
+    }
+
+    fn check(id: u128, name: &str, minute: i64) -> CheckRecord {
+        CheckRecord {
+            timestamp: at(minute),
+            device_id: Some(device_id(id)),
+            device: name.to_string(),
+            ip: format!("10.0.0.{}", id),
+            category: "core".to_string(),
+            state: Some(DeviceState::Up),
+            ping: "OK".to_string(),
+            http: "N/A".to_string(),
+            bandwidth: None,
+            latency_ms: Some(minute as f64),
+            jitter_ms: None,
+            loss_percent: None,
+            http_timing: None,
+            checks: Vec::new(),
+        }
+    }
+
+    fn change(id: u128, minute: i64, sensor: Option<&str>, new_state: DeviceState) -> StateChange {
+        StateChange {
+            timestamp: at(minute),
+            device_id: Some(device_id(id)),
+            device: format!("device-{}", id),
+            ip: format!("10.0.0.{}", id),
+            category: "core".to_string(),
+            sensor: sensor.map(str::to_string),
+            old_state: None,
+            new_state,
+            duration_secs: None,
+        }
+    }
+
+    fn devices(names: &[&str]) -> LogFilter {
+        LogFilter { devices: Some(names.iter().map(|name| name.to_string()).collect()), ..Default::default() }
+    }
+
+    fn names(records: &[CheckRecord]) -> Vec<&str> {
+        records.iter().map(|record| record.device.as_str()).collect()
+    }
+
+    // Names of the devices with check results passing `filter`
+    async fn matching(storage: &SqliteStorage, filter: LogFilter) -> Vec<String> {
+        let records = storage.check_records(&filter).await.unwrap();
+        records.into_iter().map(|record| record.device).collect()
+    }
+
+    // How many of the first state change there are, and the total
+    async fn first_change(storage: &SqliteStorage, sensor: Option<&str>, state: Option<DeviceState>) -> (usize, usize) {
+        let changes = ChangeFilter { sensor: sensor.map(str::to_string), state };
+        let (page, total) = storage
+            .state_changes_page(&LogFilter::default(), &changes, Paging { offset: 0, limit: 1 })
+            .await
+            .unwrap();
+        (page.len(), total)
+    }
+
+    #[tokio::test]
+    async fn migrations_keep_history_and_allow_reused_names() {
+        // A database from before devices were kept by id
+        let mut connection = Connection::open_in_memory().unwrap();
+        connection.execute_batch(&MIGRATIONS[..3].join(";")).unwrap();
+        connection.pragma_update(None, "user_version", 3).unwrap();
+        connection.execute_batch(&format!(
+            "INSERT INTO devices (id, name, ip, category, created_at, updated_at) VALUES ('{}', 'Router', '10.0.0.1', 'core', 0, 0);
+             INSERT INTO check_results (device_id, checked_at, ping, http) VALUES ('{0}', 0, 'OK', 'N/A');
+             INSERT INTO state_changes (device_id, changed_at, new_state) VALUES ('{0}', 0, 'UP');",
+            device_id(1)
+        )).unwrap();
+
+        connection.execute_batch("PRAGMA foreign_keys = OFF;").unwrap();
+        run_migrations(&mut connection).unwrap();
+        // Nothing left to do the second time
+        run_migrations(&mut connection).unwrap();
+        connection.execute_batch("PRAGMA foreign_keys = ON;").unwrap();
+        let version: usize = connection.query_row("PRAGMA user_version", [], |row| row.get(0)).unwrap();
+        assert_eq!(version, MIGRATIONS.len());
+
+        let storage = in_memory(connection);
+        assert_eq!(storage.check_records(&LogFilter::default()).await.unwrap().len(), 1);
+        assert_eq!(storage.state_changes(&LogFilter::default()).await.unwrap().len(), 1);
+        // A new device may take the name of an old one
+        storage.insert_check(&check(2, "Router", 1)).await.unwrap();
+        assert_eq!(names(&storage.check_records(&LogFilter::default()).await.unwrap()), ["Router", "Router"]);
+    }
+
+    #[tokio::test]
+    async fn device_filters_match_parts_literally() {
+        let storage = open().await;
+        for (id, name) in [(1, "Web_1"), (2, "webX1"), (3, "100% uptime"), (4, "back\\slash")] {
+            storage.insert_check(&check(id, name, id as i64)).await.unwrap();
+        }
+        // LIKE wildcards in a filter are plain characters, and case doesn't matter
+        assert_eq!(matching(&storage, devices(&["web_1"])).await, ["Web_1"]);
+        assert_eq!(matching(&storage, devices(&["web"])).await, ["Web_1", "webX1"]);
+        assert_eq!(matching(&storage, devices(&["%"])).await, ["100% uptime"]);
+        assert_eq!(matching(&storage, devices(&["k\\s"])).await, ["back\\slash"]);
+        // By address or id too, any of several
+        assert_eq!(matching(&storage, devices(&["10.0.0.3", &device_id(4).to_string()])).await, ["100% uptime", "back\\slash"]);
+        assert!(matching(&storage, devices(&["nothing"])).await.is_empty());
+        // From `start` up to but not including `end`
+        let range = LogFilter { start: Some(at(2)), end: Some(at(4)), devices: None };
+        assert_eq!(matching(&storage, range).await, ["webX1", "100% uptime"]);
+    }
+
+    #[tokio::test]
+    async fn pages_count_everything_passing_the_filters() {
+        let storage = open().await;
+        for minute in 0..5 {
+            storage.insert_check(&check(1, "Router", minute)).await.unwrap();
+            storage.insert_check(&check(2, "Switch", minute)).await.unwrap();
+        }
+        let (page, total) = storage
+            .check_records_page(&devices(&["router"]), Paging { offset: 1, limit: 2 })
+            .await
+            .unwrap();
+        assert_eq!(total, 5);
+        assert_eq!(page.iter().map(|record| record.timestamp).collect::<Vec<_>>(), [at(1), at(2)]);
+        let (page, total) = storage.check_records_page(&LogFilter::default(), Paging { offset: 8, limit: 5 }).await.unwrap();
+        assert_eq!((page.len(), total), (2, 10));
+
+        storage.insert_state_change(&change(1, 0, None, DeviceState::Down)).await.unwrap();
+        storage.insert_state_change(&change(1, 0, Some("Ping"), DeviceState::Down)).await.unwrap();
+        storage.insert_state_change(&change(1, 1, Some("TCP 22"), DeviceState::Down)).await.unwrap();
+        storage.insert_state_change(&change(1, 2, None, DeviceState::Up)).await.unwrap();
+        assert_eq!(first_change(&storage, None, None).await, (1, 4));
+        assert_eq!(first_change(&storage, Some("device"), None).await, (1, 2));
+        assert_eq!(first_change(&storage, Some("tcp 22"), None).await, (1, 1));
+        assert_eq!(first_change(&storage, None, Some(DeviceState::Down)).await, (1, 3));
+        assert_eq!(first_change(&storage, Some("device"), Some(DeviceState::Up)).await, (1, 1));
+        assert_eq!(first_change(&storage, Some("HTTP"), None).await, (0, 0));
+    }
+
+    #[tokio::test]
+    async fn save_users_replaces_every_user() {
+        let storage = open().await;
+        let user = |username: &str, hash: &str| User {
+            username: username.to_string(),
+            password_hash: hash.to_string(),
+            role: "admin".to_string(),
+        };
+        storage.save_users(&[user("admin", "a"), user("ops", "b")]).await.unwrap();
+        assert_eq!(storage.users().await.unwrap().len(), 2);
+
+        storage.save_users(&[user("ops", "c")]).await.unwrap();
+        let users = storage.users().await.unwrap();
+        assert_eq!(users.len(), 1);
+        assert_eq!((users[0].username.as_str(), users[0].password_hash.as_str()), ("ops", "c"));
+    }
+}
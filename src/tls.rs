@@ -0,0 +1,228 @@
+// src/tls.rs
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use log::warn;
+use rustls::client::{ServerCertVerified, ServerCertVerifier, WebPkiVerifier};
+use rustls::{Certificate, CertificateError, ClientConfig, RootCertStore, ServerName};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::SystemTime;
+use tokio::net::TcpStream;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::TlsConnector;
+use x509_parser::extensions::GeneralName;
+use x509_parser::prelude::{FromDer, X509Certificate};
+use crate::models::CertificateInfo;
+
+/// Certificates presented by the server and what the verifier made of them.
+pub struct PeerCertificates {
+    pub chain: Vec<Certificate>,
+    // `None` when the chain verified cleanly
+    pub verify_error: Option<rustls::Error>,
+}
+
+// Runs the normal WebPKI checks but only records the outcome, so the
+// handshake still completes and we can report on broken certificates.
+struct RecordingVerifier {
+    inner: WebPkiVerifier,
+    seen: Mutex<Option<PeerCertificates>>,
+}
+
+impl ServerCertVerifier for RecordingVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verify_error = self.inner
+            .verify_server_cert(end_entity, intermediates, server_name, scts, ocsp_response, now)
+            .err();
+
+        let mut chain = vec![end_entity.clone()];
+        chain.extend_from_slice(intermediates);
+        *self.seen.lock().unwrap() = Some(PeerCertificates { chain, verify_error });
+
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+// Loading the platform trust store is slow, so only do it once.
+fn root_store() -> Arc<RootCertStore> {
+    static ROOTS: OnceLock<Arc<RootCertStore>> = OnceLock::new();
+    ROOTS.get_or_init(|| {
+        let mut roots = RootCertStore::empty();
+        match rustls_native_certs::load_native_certs() {
+            Ok(certs) => {
+                for cert in certs {
+                    if let Err(e) = roots.add(&Certificate(cert.0)) {
+                        warn!("Skipping invalid root certificate: {}", e);
+                    }
+                }
+            }
+            Err(e) => warn!("Unable to load system root certificates: {}", e),
+        }
+        Arc::new(roots)
+    }).clone()
+}
+
+/// Perform a TLS handshake over `stream`, recording the server's certificates
+/// instead of aborting when they fail verification.
+pub async fn handshake(stream: TcpStream, server_name: &str) -> Result<(TlsStream<TcpStream>, PeerCertificates)> {
+    let name = ServerName::try_from(server_name)
+        .map_err(|_| anyhow!("Invalid TLS server name: {}", server_name))?;
+
+    let verifier = Arc::new(RecordingVerifier {
+        inner: WebPkiVerifier::new(root_store(), None),
+        seen: Mutex::new(None),
+    });
+    let config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(verifier.clone())
+        .with_no_client_auth();
+
+    let stream = TlsConnector::from(Arc::new(config)).connect(name, stream).await?;
+    let peer = verifier.seen.lock().unwrap().take()
+        .ok_or_else(|| anyhow!("Server did not present a certificate"))?;
+    Ok((stream, peer))
+}
+
+/// Summarise the presented chain for reporting.
+pub fn inspect(peer: &PeerCertificates, server_name: &str) -> Result<CertificateInfo> {
+    let mut parsed = Vec::with_capacity(peer.chain.len());
+    for cert in &peer.chain {
+        let (_, cert) = X509Certificate::from_der(&cert.0)
+            .map_err(|e| anyhow!("Unable to parse certificate: {}", e))?;
+        parsed.push(cert);
+    }
+    let leaf = parsed.first().ok_or_else(|| anyhow!("Empty certificate chain"))?;
+
+    let sans: Vec<String> = match leaf.subject_alternative_name() {
+        Ok(Some(ext)) => ext.value.general_names.iter()
+            .filter_map(|name| match name {
+                GeneralName::DNSName(dns) => Some(dns.to_string()),
+                GeneralName::IPAddress(bytes) => ip_from_bytes(bytes).map(|ip| ip.to_string()),
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    };
+
+    let not_after = DateTime::<Utc>::from_timestamp(leaf.validity().not_after.timestamp(), 0)
+        .ok_or_else(|| anyhow!("Certificate expiry date out of range"))?;
+
+    // webpki stops at the first problem, so an untrusted chain never gets its
+    // names checked; match against the SANs ourselves to report both.
+    let hostname_matches = sans.iter().any(|san| name_matches(san, server_name));
+    let trusted = is_trusted(peer.verify_error.as_ref());
+
+    Ok(CertificateInfo {
+        subject: leaf.subject().to_string(),
+        issuer: leaf.issuer().to_string(),
+        sans,
+        not_after: not_after.format("%Y-%m-%d %H:%M:%S UTC").to_string(),
+        days_until_expiry: (not_after - Utc::now()).num_days(),
+        chain: parsed.iter().map(|cert| cert.subject().to_string()).collect(),
+        trusted,
+        hostname_matches,
+    })
+}
+
+// Whether the chain leads to a trusted root, even if the leaf is expired or for
+// another name, which are reported on their own
+fn is_trusted(verify_error: Option<&rustls::Error>) -> bool {
+    !matches!(
+        verify_error,
+        Some(rustls::Error::InvalidCertificate(
+            CertificateError::UnknownIssuer
+                | CertificateError::BadSignature
+                | CertificateError::BadEncoding
+                | CertificateError::Revoked
+                | CertificateError::Other(_)
+        )) | Some(rustls::Error::General(_))
+    )
+}
+
+fn ip_from_bytes(bytes: &[u8]) -> Option<IpAddr> {
+    match bytes.len() {
+        4 => Some(IpAddr::from(<[u8; 4]>::try_from(bytes).ok()?)),
+        16 => Some(IpAddr::from(<[u8; 16]>::try_from(bytes).ok()?)),
+        _ => None,
+    }
+}
+
+// RFC 6125 style matching, allowing a single leftmost wildcard label.
+fn name_matches(san: &str, server_name: &str) -> bool {
+    // Addresses only match IP SANs, never a wildcard
+    if let Ok(ip) = server_name.parse::<IpAddr>() {
+        return san.parse::<IpAddr>().is_ok_and(|san| san == ip);
+    }
+    let san = san.trim_end_matches('.').to_ascii_lowercase();
+    let host = server_name.trim_end_matches('.').to_ascii_lowercase();
+
+    match san.strip_prefix("*.") {
+        Some(suffix) => host
+            .split_once('.')
+            .is_some_and(|(label, rest)| !label.is_empty() && rest == suffix),
+        None => san == host,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcards_cover_a_single_label() {
+        assert!(name_matches("*.example.com", "www.example.com"));
+        assert!(!name_matches("*.example.com", "a.b.example.com"));
+        assert!(!name_matches("*.example.com", "example.com"));
+        assert!(!name_matches("*.example.com", ".example.com"));
+        assert!(!name_matches("*.example.com", "www.example.org"));
+        // Only a whole leftmost label can be a wildcard
+        assert!(!name_matches("w*.example.com", "www.example.com"));
+        assert!(!name_matches("www.*.com", "www.example.com"));
+    }
+
+    #[test]
+    fn names_match_ignoring_case_and_trailing_dots() {
+        assert!(name_matches("WWW.Example.COM", "www.example.com"));
+        assert!(name_matches("*.EXAMPLE.com", "Mail.example.COM"));
+        assert!(name_matches("example.com.", "example.com"));
+        assert!(name_matches("example.com", "example.com."));
+        assert!(!name_matches("example.com", "example.co"));
+    }
+
+    #[test]
+    fn addresses_match_ip_sans_only() {
+        assert!(name_matches("10.0.0.1", "10.0.0.1"));
+        assert!(!name_matches("10.0.0.1", "10.0.0.2"));
+        assert!(name_matches("2001:db8::1", "2001:db8:0:0::1"));
+        assert!(!name_matches("*.0.0.1", "10.0.0.1"));
+        assert!(!name_matches("10.0.0.1", "host.example.com"));
+
+        assert_eq!(ip_from_bytes(&[10, 0, 0, 1]), Some("10.0.0.1".parse().unwrap()));
+        let mut v6 = [0u8; 16];
+        v6[15] = 1;
+        assert_eq!(ip_from_bytes(&v6), Some("::1".parse().unwrap()));
+        assert_eq!(ip_from_bytes(&[10, 0, 0]), None);
+    }
+
+    #[test]
+    fn only_chain_problems_make_a_certificate_untrusted() {
+        let invalid = |error| Some(rustls::Error::InvalidCertificate(error));
+        assert!(is_trusted(None));
+        // Reported as expired or for the wrong name instead
+        assert!(is_trusted(invalid(CertificateError::Expired).as_ref()));
+        assert!(is_trusted(invalid(CertificateError::NotValidYet).as_ref()));
+        assert!(is_trusted(invalid(CertificateError::NotValidForName).as_ref()));
+
+        assert!(!is_trusted(invalid(CertificateError::UnknownIssuer).as_ref()));
+        assert!(!is_trusted(invalid(CertificateError::BadSignature).as_ref()));
+        assert!(!is_trusted(invalid(CertificateError::Revoked).as_ref()));
+        assert!(!is_trusted(Some(rustls::Error::General("self signed".to_string())).as_ref()));
+    }
+}
@@ -294,18 +294,18 @@ function renderDeviceList() {
     const deviceList = document.getElementById('deviceList');
     deviceList.innerHTML = '';
 
-    devices.forEach((device, index) => {
+    devices.forEach(device => {
         const row = document.createElement('tr');
         row.innerHTML = `
             <td>${device.name}</td>
             <td>${device.ip}</td>
             <td>${device.category}</td>
-            <td>${device.sensors.join(', ')}</td>
+            <td>${device.sensors.map(sensorLabel).join(', ')}</td>
             <td>
-                <button class="button icon-button edit-button" onclick="editDevice(${index})">
+                <button class="button icon-button edit-button" onclick="editDevice('${device.id}')">
                     <i class="fas fa-edit"></i>
                 </button>
-                <button class="button icon-button delete-button" onclick="removeDevice(${index})">
+                <button class="button icon-button delete-button" onclick="removeDevice('${device.id}')">
                     <i class="fas fa-trash"></i>
                 </button>
             </td>
@@ -352,19 +352,22 @@ async function addDevice(newDevice) {
     }
 }
 
-async function removeDevice(index) {
+async function removeDevice(id) {
     if (!confirm('Are you sure you want to remove this device?')) return;
 
     try {
         // Get device info before removing (for messages)
+        const index = devices.findIndex(device => device.id === id);
+        if (index === -1) return;
         const deviceName = devices[index].name;
         
         // Try API call first
         let apiSuccess = false;
         try {
-            const response = await fetch(`/devices/${index}`, { method: 'DELETE' });
+            const response = await fetch(`/devices/${id}`, { method: 'DELETE' });
             if (response.ok) {
                 apiSuccess = true;
+                devices.splice(index, 1);
                 showNotification(`Device "${deviceName}" removed from server`, 'success');
             } else {
                 throw new Error(`Server returned ${response.status}`);
@@ -539,12 +542,13 @@ function logout() {
     window.location.href = '/static/login.html';
 }
 
-let editingIndex = null;
+let editingId = null;
 
-function editDevice(index) {
+function editDevice(id) {
+    const device = devices.find(device => device.id === id);
+    if (!device) return;
     isEditing = true;
-    editingIndex = index;
-    const device = devices[index];
+    editingId = id;
 
     // Update form UI
     document.getElementById('formTitle').textContent = 'Edit Device';
@@ -589,10 +593,20 @@ document.getElementById('deviceForm').addEventListener('submit', async (e) => {
         http_path: document.getElementById('httpPath').value || null
     };
 
+    // Keep settings the form can't edit yet (e.g. TCP port checks, HTTP assertions, bandwidth source, intervals)
+    const existing = isEditing ? devices.find(device => device.id === editingId) : null;
+    if (existing) {
+        formData.sensors.push(...existing.sensors.filter(s => typeof s !== 'string'));
+        for (const key of ['http', 'bandwidth', 'interval_secs', 'timeout_ms', 'retries', 'retry_delay_ms', 'sensor_settings',
+                           'down_after', 'up_after', 'flap_window_secs', 'flap_threshold', 'depends_on']) {
+            if (existing[key] !== undefined && existing[key] !== null) formData[key] = existing[key];
+        }
+    }
+
     try {
-        if (isEditing && editingIndex !== null) {
+        if (isEditing && editingId !== null) {
             // Update existing device
-            const response = await fetch(`/devices/${editingIndex}`, {
+            const response = await fetch(`/devices/${editingId}`, {
                 method: 'PUT',
                 headers: { 'Content-Type': 'application/json' },
                 body: JSON.stringify(formData)
@@ -601,7 +615,7 @@ document.getElementById('deviceForm').addEventListener('submit', async (e) => {
             if (!response.ok) throw new Error('Failed to update device');
             
             // Update local array
-            devices[editingIndex] = formData;
+            Object.assign(existing, formData);
             showNotification('Device updated successfully', 'success');
         } else {
             // Add new device
@@ -628,7 +642,7 @@ document.getElementById('deviceForm').addEventListener('submit', async (e) => {
 
 function cancelEdit() {
     isEditing = false;
-    editingIndex = null;
+    editingId = null;
     
     document.getElementById('formTitle').textContent = 'Add Device';
     document.getElementById('submitButtonText').textContent = 'Add Device';
@@ -639,4 +653,12 @@ function cancelEdit() {
 }
 
 // Add cancel button handler
-document.getElementById('cancelButton').addEventListener('click', cancelEdit);
\ No newline at end of file
+document.getElementById('cancelButton').addEventListener('click', cancelEdit);
+
+// Sensors with options (e.g. {"Tcp": {"port": 22}}) are objects rather than plain names
+function sensorLabel(sensor) {
+    if (typeof sensor === 'string') return sensor;
+    const [kind, options] = Object.entries(sensor)[0];
+    if (options && options.name) return `${kind} ${options.name}`;
+    return options && options.port ? `${kind} ${options.port}` : kind;
+}
@@ -67,6 +67,21 @@ async function fetchDevices() {
   }
 }
 
+// Refresh when the monitor logs something, rather than on a timer. Several events
+// usually arrive together, so wait for them to settle first.
+function followDevices() {
+  const events = new EventSource('/api/events/stream');
+  let refresh = null;
+  const scheduleRefresh = () => {
+    clearTimeout(refresh);
+    refresh = setTimeout(fetchDevices, 500);
+  };
+  events.addEventListener('check', scheduleRefresh);
+  events.addEventListener('state_change', scheduleRefresh);
+  // Catch up after a reconnect
+  events.addEventListener('open', scheduleRefresh);
+}
+
 function renderData(devices) {
   updateTable(devices);
   updateBandwidthChart(devices);
@@ -142,9 +157,10 @@ function updateTable(devices) {
   devices.forEach(device => {
     const tr = document.createElement("tr");
     tr.setAttribute('data-category', device.category);
+    tr.setAttribute('data-device', device.id);
     tr.addEventListener("click", () => {
       // Handle row click:  Update selected device and pie chart.
-      if (selectedDevice && selectedDevice.name === device.name) {
+      if (selectedDevice && selectedDevice.id === device.id) {
         // Clicking the same row again deselects it.
         selectedDevice = null;
         clearPieChart();
@@ -179,7 +195,7 @@ function updateTable(devices) {
       device.sensors.forEach(sensor => {
         const span = document.createElement("span");
         span.className = "sensor-box badge badge-primary";
-        span.textContent = sensor;
+        span.textContent = sensorLabel(sensor);
         sensorTd.appendChild(span);
       });
     } else {
@@ -296,7 +312,7 @@ function updatePieChart(device) {
   }
 
   const ctx = document.getElementById('devicePieChart').getContext('2d');
-  const labels = device.sensors;
+  const labels = device.sensors.map(sensorLabel);
   const data = device.sensors.map(() => 1); // Equal distribution
   const isDarkMode = document.body.classList.contains('dark-mode');
 
@@ -563,7 +579,9 @@ document.addEventListener("DOMContentLoaded", () => {
 
   // Fetch devices and update UI
   fetchDevices();
-  setInterval(fetchDevices, 15000); // Fetch data every 15 seconds
+  followDevices();
+  // Latency and bandwidth change without an event, so still refresh now and then
+  setInterval(fetchDevices, 60000);
   updateSortIcons();
 
   // Initialize category filter
@@ -626,8 +644,8 @@ async function fetchDeviceStatuses() {
 
         // Only update the UI if there are significant changes
         let hasChanges = false;
-        for (const [deviceName, status] of Object.entries(newStatuses)) {
-            const lastStatus = deviceStatuses.get(deviceName);
+        for (const [deviceId, status] of Object.entries(newStatuses)) {
+            const lastStatus = deviceStatuses.get(deviceId);
             const hasSignificantChange = !lastStatus || 
                 lastStatus.ping_status !== status.ping_status ||
                 lastStatus.http_status !== status.http_status ||
@@ -635,8 +653,8 @@ async function fetchDeviceStatuses() {
 
             if (hasSignificantChange) {
                 hasChanges = true;
-                deviceStatuses.set(deviceName, status);
-                updateDeviceRow(deviceName, status);
+                deviceStatuses.set(deviceId, status);
+                updateDeviceRow(deviceId, status);
             }
         }
 
@@ -653,8 +671,8 @@ async function fetchDeviceStatuses() {
     }
 }
 
-function updateDeviceRow(deviceName, status) {
-    const row = document.querySelector(`tr[data-device="${deviceName}"]`);
+function updateDeviceRow(deviceId, status) {
+    const row = document.querySelector(`tr[data-device="${deviceId}"]`);
     if (!row) return;
 
     // Update status indicators
@@ -697,9 +715,9 @@ async function initializeDeviceStatuses() {
         if (!response.ok) throw new Error('Failed to fetch initial device statuses');
         const statuses = await response.json();
         
-        for (const [deviceName, status] of Object.entries(statuses)) {
-            deviceStatuses.set(deviceName, status);
-            updateDeviceRow(deviceName, status);
+        for (const [deviceId, status] of Object.entries(statuses)) {
+            deviceStatuses.set(deviceId, status);
+            updateDeviceRow(deviceId, status);
         }
     } catch (error) {
         console.error('Error initializing device statuses:', error);
@@ -1210,4 +1228,12 @@ function handleDropdownMenu() {
 // Initialize dropdown menu when DOM is loaded
 document.addEventListener('DOMContentLoaded', () => {
     handleDropdownMenu();
-});
\ No newline at end of file
+});
+
+// Sensors with options (e.g. {"Tcp": {"port": 22}}) are objects rather than plain names
+function sensorLabel(sensor) {
+  if (typeof sensor === 'string') return sensor;
+  const [kind, options] = Object.entries(sensor)[0];
+  if (options && options.name) return `${kind} ${options.name}`;
+  return options && options.port ? `${kind} ${options.port}` : kind;
+}